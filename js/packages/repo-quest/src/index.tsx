@@ -7,10 +7,18 @@ import React, { useContext, useEffect, useState } from "react";
 import { createPortal } from "react-dom";
 import ReactDOM from "react-dom/client";
 import {
+  type ActionPlan,
+  type ExistingRepo,
   events,
+  type ForgeKind,
+  type ForgeSettings,
+  type GithubHost,
+  type PackageInfo,
   type QuestConfig,
+  type QuestAction,
   type QuestLocation,
   type QuestState,
+  type ReferenceSolution,
   type Result,
   type Stage,
   type StageState,
@@ -102,11 +110,187 @@ async function tryAwait<T>(
   }
 }
 
-let GithubLoader = () => (
-  <Await promise={commands.getGithubToken()}>
+let OfflineContext = React.createContext(false);
+
+let OfflineLoader = () => (
+  <Await promise={commands.initLocalForge()}>
+    {result =>
+      result.status === "ok" ? (
+        <OfflineContext.Provider value={true}>
+          <LoaderEntry />
+        </OfflineContext.Provider>
+      ) : (
+        <ErrorView action="Loading offline forge" message={result.error} />
+      )
+    }
+  </Await>
+);
+
+let GithubLoader = () => {
+  let [offline, setOffline] = useState(false);
+  return offline ? (
+    <OfflineLoader />
+  ) : (
+    <GithubTokenLoader playOffline={() => setOffline(true)} />
+  );
+};
+
+let ForgeLoader = () => (
+  <Await promise={commands.getForgeSettings()}>
+    {settings =>
+      settings.status === "error" ? (
+        <ErrorView action="Loading forge settings" message={settings.error} />
+      ) : settings.data.kind === "Github" ? (
+        <GithubLoader />
+      ) : settings.data.kind === "Local" ? (
+        <OfflineLoader />
+      ) : (
+        <RestForgeLoader settings={settings.data} />
+      )
+    }
+  </Await>
+);
+
+let RestForgeLoader: React.FC<{ settings: ForgeSettings }> = ({
+  settings
+}) => {
+  let url = settings.url ?? "";
+  let init = (token: string) =>
+    settings.kind === "Gitea"
+      ? commands.initGitea(url, token)
+      : commands.initGitlab(url, token);
+  return (
+    <Await promise={commands.getForgeToken(url)}>
+      {token =>
+        token.type === "Found" ? (
+          <Await promise={init(token.value)}>
+            {result =>
+              result.status === "ok" ? (
+                <LoaderEntry />
+              ) : (
+                <ErrorView
+                  action={`Loading ${settings.kind} API`}
+                  message={result.error}
+                />
+              )
+            }
+          </Await>
+        ) : token.type === "NotFound" ? (
+          <>
+            <div>
+              Before running RepoQuest, you need to provide it an access token
+              for {url === "" ? settings.kind : url}.
+            </div>
+            <ForgeForm settings={settings} />
+          </>
+        ) : (
+          <ErrorView action="Loading access token" message={token.value} />
+        )
+      }
+    </Await>
+  );
+};
+
+let ForgeForm: React.FC<{ settings: ForgeSettings }> = ({ settings }) => {
+  let [kind, setKind] = useState<ForgeKind>(settings.kind);
+  let [url, setUrl] = useState(settings.url ?? "");
+  let [token, setToken] = useState("");
+  let setMessage = useContext(ErrorContext)!;
+  let save = async () => {
+    let result = await commands.setForgeSettings({
+      kind,
+      url: url === "" ? null : url
+    });
+    if (result.status === "ok" && kind !== "Github" && token !== "") {
+      result = await commands.setForgeToken(url, token);
+    }
+    if (result.status === "error") {
+      setMessage({ action: "Saving forge settings", message: result.error });
+    } else {
+      window.location.reload();
+    }
+  };
+  return (
+    <div className="github-host">
+      <div>
+        If your school hosts its own Gitea, Forgejo or GitLab server, choose it
+        here and enter its URL and an access token for your account.
+      </div>
+      <table>
+        <tbody>
+          <tr>
+            <td>Forge:</td>
+            <td>
+              <select
+                value={kind}
+                onChange={e => setKind(e.target.value as ForgeKind)}
+              >
+                <option value="Github">GitHub</option>
+                <option value="Gitea">Gitea or Forgejo</option>
+                <option value="Gitlab">GitLab</option>
+              </select>
+            </td>
+          </tr>
+          {kind !== "Github" && (
+            <>
+              <tr>
+                <td>URL:</td>
+                <td>
+                  <input
+                    type="text"
+                    value={url}
+                    placeholder="https://codeberg.org"
+                    onChange={e => setUrl(e.target.value)}
+                  />
+                </td>
+              </tr>
+              <tr>
+                <td>Access token:</td>
+                <td>
+                  <input
+                    type="password"
+                    value={token}
+                    onChange={e => setToken(e.target.value)}
+                  />
+                </td>
+              </tr>
+            </>
+          )}
+        </tbody>
+      </table>
+      <button
+        type="button"
+        disabled={kind !== "Github" && (url === "" || token === "")}
+        onClick={save}
+      >
+        Save
+      </button>
+    </div>
+  );
+};
+
+let GithubTokenLoader: React.FC<{ playOffline: () => void }> = ({
+  playOffline
+}) => (
+  <Await promise={commands.getGithubHost()}>
+    {host =>
+      host.status === "ok" ? (
+        <GithubHostTokenLoader host={host.data} playOffline={playOffline} />
+      ) : (
+        <ErrorView action="Loading Github host" message={host.error} />
+      )
+    }
+  </Await>
+);
+
+let GithubHostTokenLoader: React.FC<{
+  host: GithubHost;
+  playOffline: () => void;
+}> = ({ host, playOffline }) => (
+  <Await promise={commands.getGithubToken(host)}>
     {token =>
       token.type === "Found" ? (
-        <Await promise={commands.initOctocrab(token.value)}>
+        <Await promise={commands.initOctocrab(token.value, host)}>
           {result =>
             result.status === "ok" ? (
               <LoaderEntry />
@@ -126,6 +310,17 @@ let GithubLoader = () => (
               https://github.com/cognitive-engineering-lab/repo-quest/blob/main/README.md#github-token
             </Link>
           </div>
+          <GithubHostForm host={host} />
+          <ForgeForm settings={{ kind: "Github", url: null }} />
+          <div>
+            If you can't access Github, you can instead play with a quest
+            package offline.
+          </div>
+          <div>
+            <button type="button" onClick={playOffline}>
+              Play offline
+            </button>
+          </div>
         </>
       ) : (
         <ErrorView action="Loading Github token" message={token.value} />
@@ -134,6 +329,58 @@ let GithubLoader = () => (
   </Await>
 );
 
+let GithubHostForm: React.FC<{ host: GithubHost }> = ({ host }) => {
+  let [hostname, setHostname] = useState(host.host);
+  let [apiBase, setApiBase] = useState(host.api_base ?? "");
+  let setMessage = useContext(ErrorContext)!;
+  let save = async () => {
+    let result = await commands.setGithubHost({
+      host: hostname,
+      api_base: apiBase === "" ? null : apiBase
+    });
+    if (result.status === "error") {
+      setMessage({ action: "Saving Github host", message: result.error });
+    } else {
+      window.location.reload();
+    }
+  };
+  return (
+    <div className="github-host">
+      <div>
+        If your school uses Github Enterprise, enter its hostname here. Leave
+        the API URL empty to use <code>https://&lt;host&gt;/api/v3</code>.
+      </div>
+      <table>
+        <tbody>
+          <tr>
+            <td>Host:</td>
+            <td>
+              <input
+                type="text"
+                value={hostname}
+                onChange={e => setHostname(e.target.value)}
+              />
+            </td>
+          </tr>
+          <tr>
+            <td>API URL:</td>
+            <td>
+              <input
+                type="text"
+                value={apiBase}
+                onChange={e => setApiBase(e.target.value)}
+              />
+            </td>
+          </tr>
+        </tbody>
+      </table>
+      <button type="button" disabled={hostname === ""} onClick={save}>
+        Save
+      </button>
+    </div>
+  );
+};
+
 let LoaderEntry = () => {
   let promise = async () => {
     let cwd = await commands.currentDir();
@@ -194,9 +441,38 @@ let InitForm = () => {
 
 const QUESTS = ["cognitive-engineering-lab/rqst-async"];
 
+let PackageInfoView = ({ info }: { info: Result<PackageInfo, string> }) => (
+  <div className="package-info">
+    {info.status === "error" ? (
+      <span className="invalid">{info.error}</span>
+    ) : info.data.signer ? (
+      <>
+        {info.data.title}, signed by <strong>{info.data.signer.name}</strong>{" "}
+        (key <code>{info.data.fingerprint}</code>)
+        {!info.data.trusted && (
+          <div className="unsigned">
+            This key is not in ~/.rqst-trusted-keys, so anyone could have signed
+            the package under this name. Check the fingerprint with your
+            instructor.
+          </div>
+        )}
+      </>
+    ) : (
+      <>
+        {info.data.title}, <span className="unsigned">unsigned</span>
+      </>
+    )}
+  </div>
+);
+
 let NewQuest = () => {
   let [dir, setDir] = useState<string | undefined>(undefined);
   let [quest, setQuest] = useState<QuestLocation | undefined>(undefined);
+  let [repoName, setRepoName] = useState("");
+  let [existing, setExisting] = useState<ExistingRepo>("Fail");
+  let [packageInfo, setPackageInfo] = useState<
+    Result<PackageInfo, string> | undefined
+  >(undefined);
   let [submit, setSubmit] = useState(false);
   return !submit ? (
     <div className="new-quest">
@@ -209,9 +485,10 @@ let NewQuest = () => {
             <td>Quest:</td>
             <td>
               <select
-                onChange={e =>
-                  setQuest({ type: "Remote", value: e.target.value })
-                }
+                onChange={e => {
+                  setQuest({ type: "Remote", value: e.target.value });
+                  setPackageInfo(undefined);
+                }}
                 defaultValue={""}
               >
                 <option disabled={true} value="">
@@ -237,6 +514,7 @@ let NewQuest = () => {
                   if (e.target.checkValidity())
                     setQuest({ type: "Remote", value: e.target.value });
                   else setQuest(undefined);
+                  setPackageInfo(undefined);
                 }}
                 pattern="[^\/]+\/.+"
               />
@@ -249,12 +527,15 @@ let NewQuest = () => {
                 type="button"
                 onClick={async () => {
                   let file = await dialog.open();
-                  if (file !== null) setQuest({ type: "Local", value: file });
+                  if (file === null) return;
+                  setQuest({ type: "Local", value: file });
+                  setPackageInfo(await commands.inspectPackage(file));
                 }}
               >
                 Choose a local package file
               </button>
               {quest && quest.type === "Local" && <code>{quest.value}</code>}
+              {packageInfo && <PackageInfoView info={packageInfo} />}
             </td>
           </tr>
           <tr>
@@ -273,20 +554,63 @@ let NewQuest = () => {
               {dir && <code>{dir}</code>}
             </td>
           </tr>
+          <tr>
+            <td>Repo name:</td>
+            <td>
+              <input
+                type="text"
+                placeholder="Same as the quest"
+                value={repoName}
+                onChange={e => setRepoName(e.target.value)}
+              />
+            </td>
+          </tr>
+          <tr>
+            <td>If it exists:</td>
+            <td>
+              <select
+                value={existing}
+                onChange={e => setExisting(e.target.value as ExistingRepo)}
+              >
+                <option value="Fail">Stop</option>
+                <option value="Adopt">Continue the quest in it</option>
+                <option value="Rename">Create a repo with a new name</option>
+                <option value="Replace">Delete it and start over</option>
+              </select>
+            </td>
+          </tr>
         </tbody>
       </table>
       <div>
         <button
           type="button"
-          disabled={dir === undefined || quest === undefined}
-          onClick={() => setSubmit(true)}
+          disabled={
+            dir === undefined ||
+            quest === undefined ||
+            packageInfo?.status === "error"
+          }
+          onClick={async () => {
+            if (
+              existing === "Replace" &&
+              !(await dialog.confirm(
+                "This will irrevocably delete your existing repo and directory for this quest. Are you sure?"
+              ))
+            )
+              return;
+            setSubmit(true);
+          }}
         >
           Create
         </button>
       </div>
     </div>
   ) : (
-    <Await promise={commands.newQuest(dir!, quest!)}>
+    <Await
+      promise={commands.newQuest(dir!, quest!, {
+        repo_name: repoName === "" ? null : repoName,
+        existing
+      })}
+    >
       {quest_res =>
         quest_res.status === "ok" ? (
           <QuestView
@@ -421,12 +745,82 @@ let QuestView: React.FC<{
               </select>
             </div>
           )}
+
+          <div>
+            <select
+              defaultValue={""}
+              onChange={async e => {
+                if (e.target.value === "") return;
+                let stage = Number.parseInt(e.target.value);
+                e.target.value = "";
+                let confirmed = await dialog.confirm(
+                  "This will discard your progress since the start of that chapter. Are you sure?"
+                );
+                if (!confirmed) return;
+                let reference =
+                  initialState.can_skip &&
+                  stage > 0 &&
+                  (await dialog.ask(
+                    "Start the chapter from the reference solution of the previous chapter, or from your own code?",
+                    { okLabel: "Reference solution", cancelLabel: "My own code" }
+                  ));
+                await loader.loadAwait(
+                  commands.rewindToStage(stage, reference ? "Reference" : "Own")
+                );
+              }}
+            >
+              <option disabled={true} value="">
+                Rewind to chapter...
+              </option>
+              {quest.stages
+                .map<[Stage, number]>((stage, i) => [stage, i])
+                .filter(([_stage, i]) => i <= cur_stage)
+                .map(([stage, i]) => (
+                  <option key={stage.label} value={i}>
+                    Chapter {i}: {stage.name}
+                  </option>
+                ))}
+            </select>
+          </div>
+
+          {state?.solutions_locked && <UnlockSolutions />}
         </div>
       </div>
     </div>
   );
 };
 
+let UnlockSolutions = () => {
+  let loader = useContext(Loader.context)!;
+  let setMessage = useContext(ErrorContext)!;
+  let [password, setPassword] = useState("");
+  return (
+    <form
+      className="unlock"
+      onSubmit={e => {
+        e.preventDefault();
+        loader.loadAwait(
+          tryAwait(
+            commands.unlockSolutions(password),
+            "Unlocking reference solutions",
+            setMessage
+          )
+        );
+      }}
+    >
+      <input
+        type="password"
+        placeholder="Password from your instructor"
+        value={password}
+        onChange={e => setPassword(e.target.value)}
+      />
+      <button type="submit" disabled={password === ""}>
+        Unlock reference solutions
+      </button>
+    </form>
+  );
+};
+
 let StageView: React.FC<{
   index: number;
   stage: StageState;
@@ -458,13 +852,16 @@ let StageView: React.FC<{
                   ? "File issue"
                   : "File issue & starter PR"}
               </button>
+              <PreviewButton
+                action={{ type: "FileFeatureAndIssue", stage: index }}
+              />
             ) : (
               <span className="status">
                 Waiting for you to merge starter PR
               </span>
             )
           ) : state.status === "Start" ? (
-            stage.reference_solution_pr_url ? (
+            stage.reference_solution ? (
               <details className="help">
                 <summary>Help</summary>
                 <div>
@@ -474,9 +871,10 @@ let StageView: React.FC<{
                 </div>
                 <div>
                   <div>
-                    <Link href={stage.reference_solution_pr_url}>
-                      View reference solution
-                    </Link>
+                    <ReferenceSolutionLink
+                      index={index}
+                      reference={stage.reference_solution}
+                    />
                   </div>
                   <div>
                     <button
@@ -493,6 +891,9 @@ let StageView: React.FC<{
                     >
                       File reference solution
                     </button>
+                    <PreviewButton
+                      action={{ type: "FileSolution", stage: index }}
+                    />
                   </div>
                 </div>
               </details>
@@ -510,6 +911,12 @@ let StageView: React.FC<{
           <span className="status">Completed</span>
         )}
       </div>
+      {state.type === "Ongoing" && state.stage === index && (
+        <ConflictActions index={index} stage={stage} state={state} />
+      )}
+      {state.type === "Ongoing" && state.stage === index && (
+        <OfflineActions index={index} state={state} />
+      )}
       <div className="gh-links">
         {stage.issue_url && <Link href={stage.issue_url}>Issue</Link>}
         {stage.feature_pr_url && (
@@ -519,10 +926,192 @@ let StageView: React.FC<{
           <Link href={stage.solution_pr_url}>Solution PR</Link>
         )}
       </div>
+      {stage.backups.length > 0 && (
+        <div className="backups">
+          Your code before it was overwritten is saved in:{" "}
+          {stage.backups.map(branch => (
+            <code key={branch}>{branch}</code>
+          ))}
+        </div>
+      )}
     </li>
   );
 };
 
+function renderPlan(plan: ActionPlan): string {
+  let lines = plan.steps.map((step, i) => {
+    let n = i + 1;
+    switch (step.type) {
+      case "Backup":
+        return `${n}. Back up ${step.rev} to a backup branch`;
+      case "CreateBranch": {
+        let merge =
+          step.merge_type === "Success"
+            ? "merges cleanly"
+            : typeof step.merge_type === "object"
+              ? `conflicts in ${step.merge_type.Conflict.join(", ")}`
+              : "conflicts, so your code would be reset";
+        return `${n}. Create branch ${step.branch} (${step.patch.source}), ${merge}`;
+      }
+      case "ResetMain":
+        return `${n}. Reset main to ${step.target}, overwriting your code`;
+      case "FilePr":
+        return `${n}. File PR "${step.title}" with labels: ${step.labels.join(", ")}`;
+      case "FileIssue":
+        return `${n}. File issue "${step.title}" with labels: ${step.labels.join(", ")}`;
+      case "CloseIssue":
+        return `${n}. Close issue "${step.title}"`;
+    }
+  });
+  if (plan.stale)
+    lines.push(
+      "Your repo's main has changed since it was last fetched, so the merges may turn out differently."
+    );
+  return lines.join("\n");
+}
+
+// Solutions from a package are shown from the package, since learners may not be able to see
+// the author's quest repo.
+let ReferenceSolutionLink: React.FC<{
+  index: number;
+  reference: ReferenceSolution;
+}> = ({ index, reference }) => {
+  let loader = useContext(Loader.context)!;
+  let setMessage = useContext(ErrorContext)!;
+  if (reference.type === "Pr")
+    return <Link href={reference.value}>View reference solution</Link>;
+  return (
+    <button
+      type="button"
+      onClick={async () => {
+        let result = await loader.loadAwait(commands.referenceSolution(index));
+        if (result.status === "error")
+          setMessage({
+            action: "Loading reference solution",
+            message: result.error
+          });
+        else
+          await dialog.message(result.data, { title: "Reference solution" });
+      }}
+    >
+      View reference solution
+    </button>
+  );
+};
+
+// Shows what an action would do without doing it.
+let PreviewButton: React.FC<{ action: QuestAction }> = ({ action }) => {
+  let loader = useContext(Loader.context)!;
+  let setMessage = useContext(ErrorContext)!;
+  return (
+    <button
+      type="button"
+      className="preview"
+      onClick={async () => {
+        let result = await loader.loadAwait(commands.previewAction(action));
+        if (result.status === "error")
+          setMessage({ action: "Previewing action", message: result.error });
+        else await dialog.message(renderPlan(result.data), { title: "Preview" });
+      }}
+    >
+      Preview
+    </button>
+  );
+};
+
+// A PR that conflicts with the learner's changes can be resolved by hand, or reset to our code.
+let ConflictActions: React.FC<{
+  index: number;
+  stage: StageState;
+  state: Extract<QuestState, { type: "Ongoing" }>;
+}> = ({ index, stage, state }) => {
+  let loader = useContext(Loader.context)!;
+  let setMessage = useContext(ErrorContext)!;
+  let conflict =
+    state.part === "Starter"
+      ? stage.feature_pr_conflict
+      : stage.solution_pr_conflict;
+  if (state.status !== "Ongoing" || !conflict) return null;
+
+  return (
+    <details className="help">
+      <summary>Merge conflicts</summary>
+      <div>
+        This PR conflicts with your changes. Its description lists the
+        conflicting files for you to resolve. If you'd rather not, we can
+        replace your code with ours.
+      </div>
+      <div>
+        <button
+          type="button"
+          onClick={async () => {
+            let confirmed = await dialog.confirm(
+              "This will irrevocably overwrite your changes in the PR. Are you sure?"
+            );
+            if (confirmed)
+              await loader.loadAwait(
+                tryAwait(
+                  commands.resetPr(index, state.part),
+                  "Resetting PR",
+                  setMessage
+                )
+              );
+          }}
+        >
+          Reset PR to our code
+        </button>
+      </div>
+    </details>
+  );
+};
+
+// Without a forge web UI, learners merge PRs and close issues from RepoQuest itself.
+let OfflineActions: React.FC<{
+  index: number;
+  state: Extract<QuestState, { type: "Ongoing" }>;
+}> = ({ index, state }) => {
+  let offline = useContext(OfflineContext);
+  let loader = useContext(Loader.context)!;
+  let setMessage = useContext(ErrorContext)!;
+  if (!offline) return null;
+
+  let act = (promise: Promise<Result<null, string>>, action: string) =>
+    loader.loadAwait(tryAwait(promise, action, setMessage));
+  let mergeButton = (
+    <button
+      type="button"
+      onClick={() =>
+        act(commands.mergePr(index, state.part), "Merging PR offline")
+      }
+    >
+      Merge {state.part === "Starter" ? "starter" : "solution"} PR
+    </button>
+  );
+  let closeButton = (
+    <button
+      type="button"
+      onClick={() => act(commands.closeIssue(index), "Closing issue offline")}
+    >
+      Close issue
+    </button>
+  );
+
+  return (
+    <div className="offline-actions">
+      {state.part === "Starter"
+        ? state.status === "Ongoing" && mergeButton
+        : state.status === "Start"
+          ? closeButton
+          : (
+            <>
+              {mergeButton}
+              {closeButton}
+            </>
+          )}
+    </div>
+  );
+};
+
 let App = () => {
   let [title, setTitle] = useState<string | undefined>(undefined);
   let [errorMessage, setErrorMessage] = useState<ErrorMessage | undefined>(
@@ -549,7 +1138,7 @@ let App = () => {
                 <pre>{errorMessage.message}</pre>
               </div>
             ) : (
-              <GithubLoader />
+              <ForgeLoader />
             )}
           </div>
         </TitleContext.Provider>
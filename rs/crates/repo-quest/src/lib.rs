@@ -1,12 +1,25 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{collections::HashMap, env, path::PathBuf, sync::Arc};
+use std::{
+  collections::HashMap,
+  env,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
 
 use rq_core::{
-  github::{self, GithubToken},
+  forge::Forge,
+  github::{self, GithubForge, GithubHost, GithubToken},
+  local::LocalForge,
   package::QuestPackage,
-  quest::{CreateSource, Quest, QuestConfig, StateDescriptor, StateEmitter},
+  plan::{ActionPlan, QuestAction},
+  quest::{
+    CreateOptions, CreateSource, Quest, QuestConfig, RewindTarget, StateDescriptor, StateEmitter,
+  },
+  settings::{self, ForgeKind, ForgeSettings},
+  sign::Signer,
+  stage::StagePart,
 };
 use serde::{Deserialize, Serialize};
 use specta::Type;
@@ -30,16 +43,105 @@ fn fmt_err<T>(r: anyhow::Result<T>) -> Result<T, String> {
   r.map_err(|e| format!("{e:?}"))
 }
 
+/// The forge chosen at startup: GitHub, Gitea, GitLab or the offline local forge.
+#[derive(Default)]
+pub struct ForgeState(Mutex<Option<Arc<dyn Forge>>>);
+
+impl ForgeState {
+  fn set(&self, forge: Arc<dyn Forge>) {
+    *self.0.lock().unwrap() = Some(forge);
+  }
+
+  fn get(&self) -> Result<Arc<dyn Forge>, String> {
+    let forge = self.0.lock().unwrap();
+    forge
+      .clone()
+      .ok_or_else(|| "Forge not initialized".to_string())
+  }
+}
+
+#[tauri::command]
+#[specta::specta]
+fn get_github_host() -> Result<GithubHost, String> {
+  fmt_err(GithubHost::load())
+}
+
 #[tauri::command]
 #[specta::specta]
-fn get_github_token() -> GithubToken {
-  github::get_github_token()
+fn set_github_host(host: GithubHost) -> Result<(), String> {
+  fmt_err(host.save())
 }
 
 #[tauri::command]
 #[specta::specta]
-fn init_octocrab(token: String) -> Result<(), String> {
-  fmt_err(github::init_octocrab(&token))
+fn get_github_token(host: GithubHost) -> GithubToken {
+  github::get_github_token(&host)
+}
+
+#[tauri::command]
+#[specta::specta]
+fn init_octocrab(
+  token: String,
+  host: GithubHost,
+  forge: State<'_, ForgeState>,
+) -> Result<(), String> {
+  fmt_err(github::init_octocrab(&token, &host))?;
+  forge.set(Arc::new(GithubForge::new(host)));
+  Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn get_forge_settings() -> Result<ForgeSettings, String> {
+  fmt_err(ForgeSettings::load())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn set_forge_settings(settings: ForgeSettings) -> Result<(), String> {
+  fmt_err(settings.save())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn get_forge_token(url: String) -> GithubToken {
+  settings::get_forge_token(&url)
+}
+
+#[tauri::command]
+#[specta::specta]
+fn set_forge_token(url: String, token: String) -> Result<(), String> {
+  fmt_err(settings::save_forge_token(&url, &token))
+}
+
+#[tauri::command]
+#[specta::specta]
+fn init_gitea(url: String, token: String, forge: State<'_, ForgeState>) -> Result<(), String> {
+  forge.set(fmt_err(settings::connect_rest(
+    ForgeKind::Gitea,
+    &url,
+    &token,
+  ))?);
+  Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn init_gitlab(url: String, token: String, forge: State<'_, ForgeState>) -> Result<(), String> {
+  forge.set(fmt_err(settings::connect_rest(
+    ForgeKind::Gitlab,
+    &url,
+    &token,
+  ))?);
+  Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn init_local_forge(forge: State<'_, ForgeState>) -> Result<(), String> {
+  let local = fmt_err(LocalForge::open_default())?;
+  forge.set(Arc::new(local));
+  Ok(())
 }
 
 #[tauri::command]
@@ -65,8 +167,10 @@ fn manage_quest(quest: Quest, app: &AppHandle) -> Arc<Quest> {
 async fn load_quest(
   dir: PathBuf,
   app: AppHandle,
+  forge: State<'_, ForgeState>,
 ) -> Result<(QuestConfig, StateDescriptor), String> {
-  let quest = fmt_err(Quest::load(dir, Box::new(TauriEmitter(app.clone()))).await)?;
+  let forge = forge.get()?;
+  let quest = fmt_err(Quest::load(forge, dir, Box::new(TauriEmitter(app.clone()))).await)?;
   let quest = manage_quest(quest, &app);
   let state = fmt_err(quest.state_descriptor().await)?;
   Ok((quest.config.clone(), state))
@@ -79,13 +183,43 @@ pub enum QuestLocation {
   Local(PathBuf),
 }
 
+/// What the new-quest flow shows about a package before the quest is created.
+#[derive(Serialize, Deserialize, Type)]
+pub struct PackageInfo {
+  title: String,
+  signer: Option<Signer>,
+  fingerprint: Option<String>,
+  /// Whether the learner pinned the signer's key as trusted.
+  trusted: bool,
+}
+
+#[tauri::command]
+#[specta::specta]
+fn inspect_package(path: PathBuf) -> Result<PackageInfo, String> {
+  let package = fmt_err(QuestPackage::load_from_file(&path))?;
+  let signer = package.signer().cloned();
+  let trusted = match &signer {
+    Some(signer) => fmt_err(signer.is_trusted())?,
+    None => false,
+  };
+  Ok(PackageInfo {
+    title: package.config.title,
+    fingerprint: signer.as_ref().map(Signer::fingerprint),
+    signer,
+    trusted,
+  })
+}
+
 #[tauri::command]
 #[specta::specta]
 async fn new_quest(
   dir: PathBuf,
   quest_loc: QuestLocation,
+  options: CreateOptions,
   app: AppHandle,
+  forge: State<'_, ForgeState>,
 ) -> Result<(QuestConfig, StateDescriptor), String> {
+  let forge = forge.get()?;
   let source = match quest_loc {
     QuestLocation::Remote(remote) => {
       let (user, repo) = remote
@@ -98,10 +232,19 @@ async fn new_quest(
     }
     QuestLocation::Local(local) => {
       let package = fmt_err(QuestPackage::load_from_file(&local))?;
-      CreateSource::Package(package)
+      CreateSource::Package(Box::new(package))
     }
   };
-  let quest = fmt_err(Quest::create(dir, source, Box::new(TauriEmitter(app.clone()))).await)?;
+  let quest = fmt_err(
+    Quest::create(
+      forge,
+      dir,
+      source,
+      options,
+      Box::new(TauriEmitter(app.clone())),
+    )
+    .await,
+  )?;
   let quest = manage_quest(quest, &app);
   let state = fmt_err(quest.state_descriptor().await)?;
   Ok((quest.config.clone(), state))
@@ -137,6 +280,60 @@ async fn skip_to_stage(quest: State<'_, Arc<Quest>>, stage: u32) -> Result<(), S
   Ok(())
 }
 
+#[tauri::command]
+#[specta::specta]
+async fn preview_action(
+  quest: State<'_, Arc<Quest>>,
+  action: QuestAction,
+) -> Result<ActionPlan, String> {
+  fmt_err(quest.plan(action).await)
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn reset_pr(quest: State<'_, Arc<Quest>>, stage: u32, part: StagePart) -> Result<(), String> {
+  let stage = usize::try_from(stage).unwrap();
+  fmt_err(quest.reset_stage_pr(stage, part).await)
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn rewind_to_stage(
+  quest: State<'_, Arc<Quest>>,
+  stage: u32,
+  target: RewindTarget,
+) -> Result<(), String> {
+  let stage = usize::try_from(stage).unwrap();
+  fmt_err(quest.rewind_to_stage(stage, target).await)
+}
+
+#[tauri::command]
+#[specta::specta]
+fn reference_solution(quest: State<'_, Arc<Quest>>, stage: u32) -> Result<String, String> {
+  let stage = usize::try_from(stage).unwrap();
+  fmt_err(quest.reference_solution(stage))
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn unlock_solutions(quest: State<'_, Arc<Quest>>, password: String) -> Result<(), String> {
+  fmt_err(quest.unlock_solutions(&password).await)
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn merge_pr(quest: State<'_, Arc<Quest>>, stage: u32, part: StagePart) -> Result<(), String> {
+  let stage = usize::try_from(stage).unwrap();
+  fmt_err(quest.merge_stage_pr(stage, part).await)
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn close_issue(quest: State<'_, Arc<Quest>>, stage: u32) -> Result<(), String> {
+  let stage = usize::try_from(stage).unwrap();
+  fmt_err(quest.close_stage_issue(stage).await)
+}
+
 #[derive(Serialize, Deserialize, Type)]
 struct DevDump {
   env: HashMap<String, String>,
@@ -147,22 +344,42 @@ struct DevDump {
 #[specta::specta]
 fn dev_dump() -> DevDump {
   let env = env::vars().collect::<HashMap<_, _>>();
-  let token = github::get_github_token();
+  let token = match GithubHost::load() {
+    Ok(host) => github::get_github_token(&host),
+    Err(e) => GithubToken::Error(format!("{e:?}")),
+  };
   DevDump { env, token }
 }
 
 pub fn specta_builder() -> tauri_specta::Builder {
   tauri_specta::Builder::<tauri::Wry>::new()
     .commands(tauri_specta::collect_commands![
+      get_github_host,
+      set_github_host,
       get_github_token,
       init_octocrab,
+      get_forge_settings,
+      set_forge_settings,
+      get_forge_token,
+      set_forge_token,
+      init_gitea,
+      init_gitlab,
+      init_local_forge,
       load_quest,
       current_dir,
+      inspect_package,
       new_quest,
       file_feature_and_issue,
       file_solution,
       refresh_state,
       skip_to_stage,
+      rewind_to_stage,
+      reset_pr,
+      preview_action,
+      reference_solution,
+      unlock_solutions,
+      merge_pr,
+      close_issue,
       dev_dump
     ])
     .events(collect_events![StateEvent])
@@ -16,6 +16,7 @@ async fn main() {
   tauri::Builder::default()
     .plugin(tauri_plugin_dialog::init())
     .plugin(tauri_plugin_shell::init())
+    .manage(repo_quest::ForgeState::default())
     .invoke_handler(specta_builder.invoke_handler())
     .setup(move |app| {
       #[cfg(debug_assertions)]
@@ -1,40 +1,556 @@
-use std::path::{Path, PathBuf};
+use std::{
+  env,
+  io::{self, IsTerminal},
+  path::{Path, PathBuf},
+  sync::Arc,
+};
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use rq_core::{
-  github::{self, GithubToken},
-  package::QuestPackage,
+  forge::Forge,
+  github::GithubHost,
+  package::{PackOptions, QuestPackage, SolutionPolicy},
+  plan::QuestAction,
+  quest::{
+    CreateOptions, CreateSource, ExistingRepo, NoopEmitter, Quest, QuestState, RewindTarget,
+    StateDescriptor,
+  },
+  settings::{ForgeKind, ForgeSettings},
+  sign::SigningKey,
+  stage::StagePart,
+  unpack,
+  validate::{self, Problem},
 };
+use terminal::{render_plan, render_state, TerminalEmitter};
+
+mod terminal;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+  /// Forge to play quests on, if not the one saved in ~/.rqst-forge.toml (by default Github)
+  #[arg(long, value_enum, global = true)]
+  forge: Option<ForgeArg>,
+
+  /// Base URL of a Gitea or Gitlab forge, e.g. https://codeberg.org
+  #[arg(long, global = true)]
+  forge_url: Option<String>,
+
+  /// Hostname of a Github Enterprise server, e.g. github.example.edu
+  #[arg(long, global = true)]
+  github_host: Option<String>,
+
+  /// Base URL of the Github REST API, if not https://<github-host>/api/v3
+  #[arg(long, global = true, requires = "github_host")]
+  github_api: Option<String>,
+
+  /// Directory of the quest to play, if not the current directory
+  #[arg(short = 'C', long = "dir", global = true)]
+  quest_dir: Option<PathBuf>,
+
+  /// Print the quest state as JSON
+  #[arg(long, global = true)]
+  json: bool,
+
+  /// Show what file-issue, file-solution or skip would do, without doing it
+  #[arg(long, global = true)]
+  dry_run: bool,
+
   #[command(subcommand)]
   command: Command,
 }
 
+impl Cli {
+  fn github_host(&self) -> Result<GithubHost> {
+    match &self.github_host {
+      Some(host) => Ok(GithubHost::new(host, self.github_api.clone())),
+      None => GithubHost::load(),
+    }
+  }
+
+  fn forge_settings(&self) -> Result<ForgeSettings> {
+    let mut settings = match self.forge {
+      Some(forge) => ForgeSettings::new(forge.into(), None),
+      None => ForgeSettings::load()?,
+    };
+    if self.forge_url.is_some() {
+      settings.url.clone_from(&self.forge_url);
+    }
+    Ok(settings)
+  }
+
+  fn quest_dir(&self) -> Result<PathBuf> {
+    match &self.quest_dir {
+      Some(dir) => Ok(dir.clone()),
+      None => env::current_dir().context("Failed to get current directory"),
+    }
+  }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ForgeArg {
+  Github,
+  Gitea,
+  Gitlab,
+  /// The offline forge in ~/.rqst-forge, for quest packages
+  Local,
+}
+
+impl From<ForgeArg> for ForgeKind {
+  fn from(forge: ForgeArg) -> Self {
+    match forge {
+      ForgeArg::Github => ForgeKind::Github,
+      ForgeArg::Gitea => ForgeKind::Gitea,
+      ForgeArg::Gitlab => ForgeKind::Gitlab,
+      ForgeArg::Local => ForgeKind::Local,
+    }
+  }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Existing {
+  /// Continue the quest in the existing repo
+  Adopt,
+  /// Create the repo under a new name with a numeric suffix
+  Rename,
+  /// Delete the existing repo and directory
+  Replace,
+}
+
+impl From<Existing> for ExistingRepo {
+  fn from(existing: Existing) -> Self {
+    match existing {
+      Existing::Adopt => ExistingRepo::Adopt,
+      Existing::Rename => ExistingRepo::Rename,
+      Existing::Replace => ExistingRepo::Replace,
+    }
+  }
+}
+
 #[derive(Subcommand)]
 enum Command {
-  Pack { path: PathBuf },
+  /// Build a quest package from a quest repo
+  Pack {
+    path: PathBuf,
+    /// Embed a git bundle of the quest repo, so quests keep its history and solutions
+    #[arg(long, conflicts_with_all = ["no_solutions", "solutions_password"])]
+    bundle: bool,
+    /// Leave out the reference solutions
+    #[arg(long)]
+    no_solutions: bool,
+    /// Encrypt the reference solutions, so learners need a password to use them.
+    /// The password is read from RQST_SOLUTIONS_PASSWORD, or prompted for
+    #[arg(long, conflicts_with = "no_solutions")]
+    solutions_password: bool,
+    /// Sign the package with the key in this file, made by `rq keygen`
+    #[arg(long, value_name = "KEY_FILE")]
+    sign: Option<PathBuf>,
+  },
+
+  /// Rebuild a quest repo from a quest package
+  Unpack {
+    package: PathBuf,
+    /// Directory to create the quest repo in
+    dir: PathBuf,
+    /// Create the quest repo on the forge, with its issues and PRs, instead of writing them to files
+    #[arg(long)]
+    publish: bool,
+    /// Name of the repo on the forge, if not the quest's
+    #[arg(long, requires = "publish")]
+    name: Option<String>,
+    /// Unlock the package's encrypted reference solutions with a password,
+    /// read from RQST_SOLUTIONS_PASSWORD or prompted for
+    #[arg(long)]
+    solutions_password: bool,
+  },
+
+  /// Check a quest repo or package for authoring mistakes, and list every one found
+  Validate {
+    /// A quest repo, or the path to a quest package
+    quest: PathBuf,
+    /// Unlock the package's encrypted reference solutions, so they are checked too.
+    /// The password is read from RQST_SOLUTIONS_PASSWORD, or prompted for
+    #[arg(long)]
+    solutions_password: bool,
+  },
+
+  /// Generate a key for signing quest packages
+  Keygen {
+    /// Your name, as learners will see it on packages you sign
+    signer: String,
+    /// File to save the key to
+    file: PathBuf,
+  },
+
+  /// Start a quest from a repo on the forge (owner/repo) or a package (.json.gz)
+  New {
+    /// A repo on the forge as owner/repo, or the path to a quest package
+    quest: String,
+    /// Directory to clone the quest repo into
+    dir: PathBuf,
+    /// Name of your repo, if not the quest's
+    #[arg(long)]
+    name: Option<String>,
+    /// What to do if you already have a repo or directory with that name
+    #[arg(long, value_enum)]
+    existing: Option<Existing>,
+  },
+
+  /// Show the state of the quest
+  Status,
+
+  /// File the starter PR and issue for a stage, by default the current one
+  FileIssue { stage: Option<String> },
+
+  /// File the solution PR for a stage, by default the current one
+  FileSolution { stage: Option<String> },
+
+  /// Show the reference solution for a stage, by default the current one
+  Solution { stage: Option<String> },
+
+  /// Overwrite an unmerged PR of a stage with the quest's code, e.g. to give up on its conflicts
+  Reset {
+    stage: String,
+    /// Reset the starter PR rather than the solution PR
+    #[arg(long)]
+    starter: bool,
+  },
+
+  /// Skip to a stage by resetting to the solution of the stage before it
+  Skip { stage: String },
+
+  /// Unlock the reference solutions of a packaged quest with the password from your instructor,
+  /// read from RQST_SOLUTIONS_PASSWORD or prompted for
+  Unlock,
+
+  /// Go back to an earlier stage, discarding the work done since it started
+  Rewind {
+    stage: String,
+    /// Restore the reference solution of the stage before, instead of your own code
+    #[arg(long)]
+    reference: bool,
+  },
+
+  /// Refetch the quest's issues and PRs, and show its state
+  Refresh,
+
+  /// Show the state of the quest, and update it as the quest progresses
+  Watch,
+}
+
+/// Connects to the forge chosen on the command line or in the saved settings.
+fn init_forge(args: &Cli) -> Result<Arc<dyn Forge>> {
+  let settings = args.forge_settings()?;
+  let host = match settings.kind {
+    ForgeKind::Github => args.github_host()?,
+    _ => GithubHost::default(),
+  };
+  settings.connect(&host)
+}
+
+/// Parses a stage given by label or by index.
+fn parse_stage(quest: &Quest, stage: &str) -> Result<usize> {
+  let stages = quest.stages();
+  if let Some(index) = stages.iter().position(|s| s.label == stage) {
+    return Ok(index);
+  }
+  match stage.parse::<usize>() {
+    Ok(index) if index < stages.len() => Ok(index),
+    _ => bail!("Unknown stage: {stage}"),
+  }
+}
+
+/// The stage given on the command line, or else the stage the learner is on.
+async fn stage_or_current(quest: &Quest, stage: Option<String>) -> Result<usize> {
+  match stage {
+    Some(stage) => parse_stage(quest, &stage),
+    None => match quest.state_descriptor().await?.state {
+      QuestState::Ongoing { stage, .. } => Ok(stage as usize),
+      QuestState::Completed => bail!("The quest is completed, so a stage must be given"),
+    },
+  }
+}
+
+/// Reads the password for a package's reference solutions from `RQST_SOLUTIONS_PASSWORD`,
+/// a prompt, or the first line of stdin if it isn't a terminal. Passwords aren't taken as
+/// arguments, since those end up in shell history and process listings.
+fn read_solutions_password() -> Result<String> {
+  if let Ok(password) = env::var("RQST_SOLUTIONS_PASSWORD") {
+    return Ok(password);
+  }
+  let password = if io::stdin().is_terminal() {
+    rpassword::prompt_password("Solutions password: ")?
+  } else {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    line.trim_end_matches(['\r', '\n']).to_string()
+  };
+  if password.is_empty() {
+    bail!("No solutions password was given");
+  }
+  Ok(password)
+}
+
+fn is_package(path: &Path) -> bool {
+  path.to_string_lossy().ends_with(".json.gz") || path.is_file()
+}
+
+fn print_problems(problems: &[Problem], json: bool) -> Result<()> {
+  if json {
+    println!("{}", serde_json::to_string_pretty(problems)?);
+  } else {
+    for problem in problems {
+      println!("{problem}");
+    }
+  }
+  if !problems.is_empty() {
+    bail!("Found {} problem(s) in the quest", problems.len());
+  }
+  if !json {
+    println!("No problems found");
+  }
+  Ok(())
+}
+
+fn print_state(quest: &Quest, state: &StateDescriptor, json: bool) -> Result<()> {
+  if json {
+    println!("{}", serde_json::to_string_pretty(state)?);
+  } else {
+    println!("{} ({})", quest.config.title, state.dir.display());
+    print!("{}", render_state(state));
+  }
+  Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
   let args = Cli::parse();
+  let can_dry_run = matches!(
+    args.command,
+    Command::FileIssue { .. } | Command::FileSolution { .. } | Command::Skip { .. }
+  );
+  if args.dry_run && !can_dry_run {
+    bail!("--dry-run is only supported for file-issue, file-solution and skip");
+  }
+
+  if let Command::Keygen { signer, file } = &args.command {
+    let key = SigningKey::generate(signer)?;
+    key.save(file)?;
+    let public = key.public()?;
+    println!(
+      "Saved signing key for {} with fingerprint {} to: {}",
+      public.name,
+      public.fingerprint(),
+      file.display()
+    );
+    return Ok(());
+  }
+
+  if let Command::Unpack {
+    package,
+    dir,
+    publish,
+    name,
+    solutions_password,
+  } = &args.command
+  {
+    let mut package = QuestPackage::load_from_file(package)?;
+    if *solutions_password {
+      package.unlock_solutions(&read_solutions_password()?)?;
+    }
+    let repo = unpack::unpack(&package, dir)?;
+    if *publish {
+      let forge = init_forge(&args)?;
+      let name = name.as_deref().unwrap_or(&package.config.repo);
+      let origin = unpack::publish(&package, &repo, forge.as_ref(), name).await?;
+      println!(
+        "Published the quest to {}/{}. Mark it as a template repository in its settings, so learners can start the quest from it.",
+        origin.owner(),
+        origin.name()
+      );
+    } else {
+      unpack::write_documents(&package, &repo)?;
+      println!(
+        "Unpacked the quest to {}, with its issues and PRs on the meta branch",
+        dir.display()
+      );
+    }
+    return Ok(());
+  }
+
+  if let Command::Validate {
+    quest,
+    solutions_password,
+  } = &args.command
+  {
+    let problems = if is_package(quest) {
+      let package = QuestPackage::load_from_file(quest)?;
+      let password = solutions_password
+        .then(read_solutions_password)
+        .transpose()?;
+      validate::validate_package(&package, password.as_deref())?
+    } else {
+      let forge = init_forge(&args)?;
+      validate::validate_repo(quest, forge.as_ref()).await?
+    };
+    return print_problems(&problems, args.json);
+  }
+
+  let forge = init_forge(&args)?;
+
+  if let Command::Pack {
+    path,
+    bundle,
+    no_solutions,
+    solutions_password,
+    sign,
+  } = &args.command
+  {
+    // Load the key first, so a bad key file doesn't waste a build.
+    let key = sign.as_deref().map(SigningKey::load).transpose()?;
+    let solutions = if *solutions_password {
+      SolutionPolicy::Encrypt(read_solutions_password()?)
+    } else if *no_solutions {
+      SolutionPolicy::Omit
+    } else {
+      SolutionPolicy::Include
+    };
+    let options = PackOptions {
+      bundle: *bundle,
+      solutions,
+    };
+    let mut package = QuestPackage::build(path, forge.as_ref(), &options).await?;
+    if let Some(key) = &key {
+      package.sign(key)?;
+    }
+    let dst = format!("{}.json.gz", package.config.repo);
+    package.save(Path::new(&dst))?;
+    println!("Successfully generated quest package: {dst}");
+    return Ok(());
+  }
+
+  if let Command::Watch = &args.command {
+    let emitter = TerminalEmitter::new(args.json);
+    let quest = Quest::load(forge, args.quest_dir()?, Box::new(emitter)).await?;
+    quest.infer_state_loop().await;
+    return Ok(());
+  }
+
+  let quest = match &args.command {
+    Command::New {
+      quest,
+      dir,
+      name,
+      existing,
+    } => {
+      let source = if quest.ends_with(".json.gz") || Path::new(quest).exists() {
+        let package = QuestPackage::load_from_file(Path::new(quest))?;
+        match package.signer() {
+          Some(signer) => {
+            eprintln!(
+              "Package signed by {} ({})",
+              signer.name,
+              signer.fingerprint()
+            );
+            if !signer.is_trusted()? {
+              eprintln!(
+                "Warning: this key is not in ~/.rqst-trusted-keys or RQST_TRUSTED_KEYS, so \
+                anyone could have signed the package under this name. Check the fingerprint \
+                with your instructor."
+              );
+            }
+          }
+          None => eprintln!("Package is unsigned"),
+        }
+        CreateSource::Package(Box::new(package))
+      } else {
+        let (user, repo) = quest
+          .split_once('/')
+          .with_context(|| format!("Invalid quest name: {quest}"))?;
+        CreateSource::Remote {
+          user: user.to_string(),
+          repo: repo.to_string(),
+        }
+      };
+      let options = CreateOptions {
+        repo_name: name.clone(),
+        existing: existing.map(ExistingRepo::from).unwrap_or_default(),
+      };
+      Quest::create(forge, dir.clone(), source, options, Box::new(NoopEmitter)).await?
+    }
+    _ => Quest::load(forge, args.quest_dir()?, Box::new(NoopEmitter)).await?,
+  };
+
+  if args.dry_run {
+    let action = match args.command {
+      Command::FileIssue { stage } => QuestAction::FileFeatureAndIssue {
+        stage: stage_or_current(&quest, stage).await? as u32,
+      },
+      Command::FileSolution { stage } => QuestAction::FileSolution {
+        stage: stage_or_current(&quest, stage).await? as u32,
+      },
+      Command::Skip { stage } => QuestAction::SkipToStage {
+        stage: parse_stage(&quest, &stage)? as u32,
+      },
+      _ => unreachable!(),
+    };
+    let plan = quest.plan(action).await?;
+    if args.json {
+      println!("{}", serde_json::to_string_pretty(&plan)?);
+    } else {
+      print!("{}", render_plan(&plan));
+    }
+    return Ok(());
+  }
+
   match args.command {
-    Command::Pack { path } => {
-      let token = github::get_github_token();
-      match token {
-        GithubToken::Found(token) => github::init_octocrab(&token).unwrap(),
-        other => panic!("Failed to get github token: {other:?}"),
-      }
-      let package = QuestPackage::build(&path).await?;
-      let dst = format!("{}.json.gz", package.config.repo);
-      package.save(Path::new(&dst))?;
-      println!("Successfully generated quest package: {dst}");
+    Command::Pack { .. }
+    | Command::Unpack { .. }
+    | Command::Validate { .. }
+    | Command::Keygen { .. }
+    | Command::New { .. }
+    | Command::Status
+    | Command::Watch => {}
+    Command::FileIssue { stage } => {
+      let stage = stage_or_current(&quest, stage).await?;
+      quest.file_feature_and_issue(stage).await?;
     }
+    Command::FileSolution { stage } => {
+      let stage = stage_or_current(&quest, stage).await?;
+      quest.file_solution(stage).await?;
+    }
+    Command::Reset { stage, starter } => {
+      let stage = parse_stage(&quest, &stage)?;
+      let part = if starter {
+        StagePart::Starter
+      } else {
+        StagePart::Solution
+      };
+      quest.reset_stage_pr(stage, part).await?;
+    }
+    Command::Skip { stage } => {
+      let stage = parse_stage(&quest, &stage)?;
+      quest.skip_to_stage(stage).await?;
+    }
+    Command::Rewind { stage, reference } => {
+      let stage = parse_stage(&quest, &stage)?;
+      let target = if reference {
+        RewindTarget::Reference
+      } else {
+        RewindTarget::Own
+      };
+      quest.rewind_to_stage(stage, target).await?;
+    }
+    Command::Solution { stage } => {
+      let stage = stage_or_current(&quest, stage).await?;
+      print!("{}", quest.reference_solution(stage)?);
+      return Ok(());
+    }
+    Command::Unlock => quest.unlock_solutions(&read_solutions_password()?).await?,
+    Command::Refresh => quest.infer_state_update().await?,
   }
 
-  Ok(())
+  let state = quest.state_descriptor().await?;
+  print_state(&quest, &state, args.json)
 }
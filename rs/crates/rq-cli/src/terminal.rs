@@ -0,0 +1,210 @@
+use std::{
+  fmt::Write,
+  io::{self, IsTerminal},
+  sync::Mutex,
+};
+
+use anyhow::Result;
+use rq_core::{
+  git::MergeType,
+  plan::{ActionPlan, PlanStep},
+  quest::{QuestState, ReferenceSolution, StateDescriptor, StateEmitter},
+  stage::StagePart,
+};
+
+/// Renders the steps of a plan, with the full text of what would be filed.
+pub fn render_plan(plan: &ActionPlan) -> String {
+  let mut out = String::new();
+  writeln!(out, "Dry run, nothing was changed. The action would:").unwrap();
+  for (i, step) in plan.steps.iter().enumerate() {
+    let n = i + 1;
+    match step {
+      PlanStep::Backup { stage, rev } => writeln!(
+        out,
+        "{n}. Back up {rev} to a rqst-backup/{stage}-<time> branch"
+      )
+      .unwrap(),
+      PlanStep::CreateBranch {
+        branch,
+        base,
+        patch,
+        merge_type,
+      } => {
+        let merge = match merge_type {
+          MergeType::Success => "merges cleanly".to_string(),
+          MergeType::SolutionReset | MergeType::StarterReset => {
+            "conflicts, so it would hard reset to the quest's code".to_string()
+          }
+          MergeType::Conflict(files) => format!("conflicts in: {}", files.join(", ")),
+        };
+        writeln!(out, "{n}. Create branch {branch} (from {base}), {merge}").unwrap();
+        writeln!(out, "   Changes: {}", patch.source).unwrap();
+        for line in patch.diff.lines() {
+          writeln!(out, "   | {line}").unwrap();
+        }
+      }
+      PlanStep::ResetMain { target } => {
+        writeln!(out, "{n}. Hard reset main to {target} and force-push it").unwrap()
+      }
+      PlanStep::FilePr {
+        head,
+        title,
+        body,
+        labels,
+      } => {
+        writeln!(out, "{n}. File PR \"{title}\" from {head}").unwrap();
+        render_filed(&mut out, body, labels);
+      }
+      PlanStep::FileIssue {
+        title,
+        body,
+        labels,
+      } => {
+        writeln!(out, "{n}. File issue \"{title}\"").unwrap();
+        render_filed(&mut out, body, labels);
+      }
+      PlanStep::CloseIssue { title } => writeln!(out, "{n}. Close issue \"{title}\"").unwrap(),
+    }
+  }
+  if plan.stale {
+    writeln!(
+      out,
+      "Your repo's main has changed since it was last fetched, so the merges may turn out differently. Run `git fetch` for an up-to-date prediction."
+    )
+    .unwrap();
+  }
+  out
+}
+
+fn render_filed(out: &mut String, body: &str, labels: &[String]) {
+  writeln!(out, "   Labels: {}", labels.join(", ")).unwrap();
+  for line in body.lines() {
+    writeln!(out, "   > {line}").unwrap();
+  }
+}
+
+/// Renders the current stage and each stage's issue and PR links.
+pub fn render_state(state: &StateDescriptor) -> String {
+  let mut out = String::new();
+  match &state.state {
+    QuestState::Completed => writeln!(out, "Status: completed").unwrap(),
+    QuestState::Ongoing {
+      stage,
+      part,
+      status,
+    } => {
+      let stage = &state.stages[*stage as usize].stage;
+      let part = match part {
+        StagePart::Starter => "starter",
+        StagePart::Solution => "solution",
+      };
+      let status = if status.is_start() {
+        "not started"
+      } else {
+        "in progress"
+      };
+      writeln!(
+        out,
+        "Status: {} ({}), {part} {status}",
+        stage.name, stage.label
+      )
+      .unwrap();
+    }
+  }
+
+  for (i, stage_state) in state.stages.iter().enumerate() {
+    let stage = &stage_state.stage;
+    writeln!(out, "{i}. {} ({})", stage.name, stage.label).unwrap();
+    let reference = stage_state
+      .reference_solution
+      .as_ref()
+      .map(|reference| match reference {
+        ReferenceSolution::Pr(url) => url.clone(),
+        ReferenceSolution::Package => format!("see `rq solution {}`", stage.label),
+      });
+    let links = [
+      ("Issue", &stage_state.issue_url, false),
+      (
+        "Starter PR",
+        &stage_state.feature_pr_url,
+        stage_state.feature_pr_conflict,
+      ),
+      (
+        "Solution PR",
+        &stage_state.solution_pr_url,
+        stage_state.solution_pr_conflict,
+      ),
+      ("Reference solution", &reference, false),
+    ];
+    for (name, url, conflict) in links {
+      if let Some(url) = url {
+        let note = if conflict { " (has conflicts)" } else { "" };
+        writeln!(out, "   {name}: {url}{note}").unwrap();
+      }
+    }
+    for backup in &stage_state.backups {
+      writeln!(out, "   Backup: {backup}").unwrap();
+    }
+  }
+
+  if state.solutions_locked {
+    writeln!(
+      out,
+      "Reference solutions are locked, unlock them with `rq unlock <password>`"
+    )
+    .unwrap();
+  }
+
+  out
+}
+
+/// Prints the quest state whenever it changes, for `rq watch`.
+///
+/// On a terminal the screen is redrawn in place. Otherwise each state is appended,
+/// as one line of JSON per state in JSON mode.
+pub struct TerminalEmitter {
+  json: bool,
+  last: Mutex<Option<String>>,
+}
+
+impl TerminalEmitter {
+  pub fn new(json: bool) -> Self {
+    TerminalEmitter {
+      json,
+      last: Mutex::new(None),
+    }
+  }
+}
+
+impl StateEmitter for TerminalEmitter {
+  fn emit(&self, state: StateDescriptor) -> Result<()> {
+    let rendered = if self.json {
+      serde_json::to_string(&state)?
+    } else {
+      format!("Quest in {}\n{}", state.dir.display(), render_state(&state))
+    };
+
+    let mut last = self.last.lock().unwrap();
+    if last.as_ref() == Some(&rendered) {
+      return Ok(());
+    }
+
+    if self.json {
+      println!("{rendered}");
+    } else if io::stdout().is_terminal() {
+      // Clear the screen and move the cursor to the top left.
+      print!("\x1b[2J\x1b[H{rendered}");
+    } else {
+      println!("{rendered}");
+    }
+    *last = Some(rendered);
+
+    Ok(())
+  }
+
+  fn emit_error(&self, error: &anyhow::Error) {
+    eprintln!("Failed to update the quest state, retrying in 10 seconds: {error:#}");
+    // Print the state again once it updates, in case the error scrolled it away.
+    *self.last.lock().unwrap() = None;
+  }
+}
@@ -0,0 +1,220 @@
+//! Progress of [`Quest::create`](crate::quest::Quest::create), so that a failed attempt can be
+//! cleaned up or resumed.
+//!
+//! Progress is saved next to the quest directory as `.rqst-create-<repo>.json` until the quest
+//! is created. If creation fails, everything it made so far is rolled back. If RepoQuest exits
+//! before it could roll back, the next attempt finds the progress file and either resumes, when
+//! the repo was fully set up, or rolls back and starts over.
+//!
+//! A repo or directory that already exists without a progress file was not made by RepoQuest,
+//! or at least not by an unfinished attempt, so [`resolve_existing`] asks the learner what to do.
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{forge::Forge, quest::ExistingRepo};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CreateStep {
+  RepoCreated,
+  LabelsCopied,
+  Cloned,
+  /// The clone has its upstream remote or initial files, so the quest can be loaded from it.
+  Initialized,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProgressFile {
+  owner: Option<String>,
+  step: Option<CreateStep>,
+}
+
+pub struct CreateProgress {
+  file: PathBuf,
+  repo: String,
+  repo_dir: PathBuf,
+  owner: Option<String>,
+  step: Option<CreateStep>,
+}
+
+impl CreateProgress {
+  fn file(dir: &Path, repo: &str) -> PathBuf {
+    dir.join(format!(".rqst-create-{repo}.json"))
+  }
+
+  /// Whether an earlier attempt to create `repo` in `dir` left its progress behind.
+  pub fn exists(dir: &Path, repo: &str) -> bool {
+    Self::file(dir, repo).exists()
+  }
+
+  /// Starts tracking the creation of `repo` in `dir`, or picks up an earlier attempt.
+  pub fn load(dir: &Path, repo: &str) -> Result<Self> {
+    let file = Self::file(dir, repo);
+    let repo_dir = dir.join(repo);
+    let saved = if file.exists() {
+      let contents =
+        fs::read_to_string(&file).with_context(|| format!("Failed to read: {}", file.display()))?;
+      Some(
+        serde_json::from_str::<ProgressFile>(&contents)
+          .context("Failed to parse quest creation progress")?,
+      )
+    } else {
+      ensure!(
+        !repo_dir.exists(),
+        "Directory already exists: {}",
+        repo_dir.display()
+      );
+      None
+    };
+    let (owner, step) = saved
+      .map(|saved| (saved.owner, saved.step))
+      .unwrap_or_default();
+    Ok(CreateProgress {
+      file,
+      repo: repo.to_string(),
+      repo_dir,
+      owner,
+      step,
+    })
+  }
+
+  pub fn step(&self) -> Option<CreateStep> {
+    self.step
+  }
+
+  pub fn owner(&self) -> Option<&str> {
+    self.owner.as_deref()
+  }
+
+  /// Records that the learner's repo was created under `owner`.
+  pub fn repo_created(&mut self, owner: &str) -> Result<()> {
+    self.owner = Some(owner.to_string());
+    self.advance(CreateStep::RepoCreated)
+  }
+
+  pub fn advance(&mut self, step: CreateStep) -> Result<()> {
+    self.step = Some(step);
+    self.save()
+  }
+
+  fn save(&self) -> Result<()> {
+    let file = ProgressFile {
+      owner: self.owner.clone(),
+      step: self.step,
+    };
+    fs::write(&self.file, serde_json::to_string(&file)?)
+      .with_context(|| format!("Failed to write: {}", self.file.display()))
+  }
+
+  /// Deletes the learner's repo and directory, as far as they were made. If the repo can't be
+  /// deleted, or it can't be told whether it still exists, the progress is kept for next time.
+  pub async fn rollback(&mut self, forge: &dyn Forge) -> Result<()> {
+    if let Some(owner) = &self.owner {
+      let exists = forge
+        .repo_exists(owner, &self.repo)
+        .await
+        .with_context(|| format!("Failed to look up repo: {owner}/{}", self.repo))?;
+      if exists {
+        let origin = forge.load_repo(owner, &self.repo).await?;
+        origin
+          .delete()
+          .await
+          .with_context(|| format!("Failed to delete repo: {owner}/{}", self.repo))?;
+      } else {
+        tracing::warn!("Repo {owner}/{} is already gone", self.repo);
+      }
+    }
+    if self.repo_dir.exists() {
+      fs::remove_dir_all(&self.repo_dir)
+        .with_context(|| format!("Failed to remove: {}", self.repo_dir.display()))?;
+    }
+    self.owner = None;
+    self.step = None;
+    self.finish()
+  }
+
+  /// Forgets the progress, once the quest is created or rolled back.
+  pub fn finish(&self) -> Result<()> {
+    if self.file.exists() {
+      fs::remove_file(&self.file)
+        .with_context(|| format!("Failed to remove: {}", self.file.display()))?;
+    }
+    Ok(())
+  }
+}
+
+/// Where [`Quest::create`](crate::quest::Quest::create) should put the learner's repo.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RepoTarget {
+  /// Create a new repo with this name.
+  Create(String),
+  /// Use the existing repo with this name.
+  Adopt(String),
+}
+
+/// Decides what to do if `owner` already has a repo named `name`, or `dir` already contains it.
+pub async fn resolve_existing(
+  forge: &dyn Forge,
+  owner: &str,
+  dir: &Path,
+  name: &str,
+  existing: ExistingRepo,
+) -> Result<RepoTarget> {
+  let repo_exists = forge.repo_exists(owner, name).await?;
+  let dir_exists = dir.join(name).exists();
+  if !repo_exists && !dir_exists {
+    return Ok(RepoTarget::Create(name.to_string()));
+  }
+
+  match existing {
+    ExistingRepo::Fail => {
+      let what = if repo_exists {
+        format!("Repo {owner}/{name}")
+      } else {
+        format!("Directory {}", dir.join(name).display())
+      };
+      bail!("{what} already exists. Choose whether to adopt it, create the quest under a new name, or replace it.")
+    }
+    ExistingRepo::Adopt => {
+      ensure!(
+        repo_exists,
+        "Cannot adopt {}, because there is no repo {owner}/{name}",
+        dir.join(name).display()
+      );
+      Ok(RepoTarget::Adopt(name.to_string()))
+    }
+    ExistingRepo::Rename => {
+      for n in 2.. {
+        let candidate = format!("{name}-{n}");
+        if !forge.repo_exists(owner, &candidate).await?
+          && !dir.join(&candidate).exists()
+          && !CreateProgress::exists(dir, &candidate)
+        {
+          return Ok(RepoTarget::Create(candidate));
+        }
+      }
+      unreachable!()
+    }
+    ExistingRepo::Replace => {
+      if repo_exists {
+        let repo = forge.load_repo(owner, name).await?;
+        repo
+          .delete()
+          .await
+          .with_context(|| format!("Failed to delete repo: {owner}/{name}"))?;
+      }
+      let repo_dir = dir.join(name);
+      if dir_exists {
+        fs::remove_dir_all(&repo_dir)
+          .with_context(|| format!("Failed to remove: {}", repo_dir.display()))?;
+      }
+      Ok(RepoTarget::Create(name.to_string()))
+    }
+  }
+}
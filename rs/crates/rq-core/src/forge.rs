@@ -0,0 +1,373 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use parking_lot::{MappedMutexGuard, Mutex};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+  git::{GitRepo, MergeType},
+  package::{PackageIssue, PackagePr},
+  utils,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueState {
+  Open,
+  Closed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+  pub name: String,
+  pub color: String,
+  pub description: Option<String>,
+  #[serde(default)]
+  pub default: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Issue {
+  pub number: u64,
+  pub title: String,
+  pub body: Option<String>,
+  #[serde(default)]
+  pub labels: Vec<Label>,
+  pub state: IssueState,
+  pub html_url: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Head {
+  #[serde(rename = "ref")]
+  pub ref_field: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PullRequest {
+  pub number: u64,
+  pub title: Option<String>,
+  pub body: Option<String>,
+  pub head: Head,
+  #[serde(default)]
+  pub labels: Vec<Label>,
+  pub html_url: String,
+  pub merged_at: Option<String>,
+}
+
+impl PullRequest {
+  pub fn is_merged(&self) -> bool {
+    self.merged_at.is_some()
+  }
+}
+
+/// A review comment attached to a line of a PR's diff.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Comment {
+  pub path: String,
+  pub body: String,
+  pub line: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FullPullRequest {
+  pub data: PullRequest,
+  pub comments: Vec<Comment>,
+}
+
+#[derive(Debug)]
+pub enum PullSelector {
+  Branch(String),
+  Label(String),
+}
+
+pub fn find_pr<'a>(
+  selector: &PullSelector,
+  prs: impl IntoIterator<Item = &'a FullPullRequest> + 'a,
+) -> Option<usize> {
+  prs.into_iter().position(|pr| match selector {
+    PullSelector::Branch(branch) => &pr.data.head.ref_field == branch,
+    PullSelector::Label(label) => pr.data.labels.iter().any(|l| &l.name == label),
+  })
+}
+
+pub fn find_issue<'a>(
+  label_name: &str,
+  issues: impl IntoIterator<Item = &'a Issue> + 'a,
+) -> Option<usize> {
+  issues
+    .into_iter()
+    .position(|issue| issue.labels.iter().any(|label| label.name == label_name))
+}
+
+/// The body of a PR copied from `pr`, with a note on how it was merged with the learner's code.
+pub fn copied_pr_body(pr: &PackagePr, merge_type: &MergeType) -> String {
+  let mut body = pr
+    .body
+    .as_ref()
+    .expect("Author error: PR missing body")
+    .clone();
+
+  match merge_type {
+    MergeType::SolutionReset => {
+      body.push_str(r#"
+
+Note: due to a merge conflict, this PR is a hard reset to the reference solution, and may have overwritten your previous changes."#);
+    }
+
+    MergeType::StarterReset => {
+      body.push_str(r#"
+
+Note: due to a merge conflict, this PR is a hard reset to the starter code, and may have overwritten your previous changes."#);
+    }
+
+    MergeType::Conflict(files) => {
+      body.push_str(
+        r#"
+
+Note: this PR conflicts with your changes to the following files:
+"#,
+      );
+      for file in files {
+        body.push_str(&format!("\n- `{file}`"));
+      }
+      body.push_str(r#"
+
+Where our changes could be merged with yours, the files now contain conflict markers. Where they could not, for example because you deleted the file, the file is left as you had it. To resolve the conflicts, check out this PR's branch and edit each file to keep the code you want, removing the `<<<<<<<`, `=======`, and `>>>>>>>` lines. Then commit and push your changes before merging. If you would rather use our code instead of yours, you can reset this PR from RepoQuest, which will overwrite your changes."#);
+    }
+
+    MergeType::Success => {}
+  }
+
+  body
+}
+
+/// The labels of a PR copied from `pr`, plus one for how it was merged with the learner's code.
+pub fn copied_pr_labels(pr: &PackagePr, merge_type: &MergeType) -> Vec<String> {
+  let mut labels = pr.labels.clone();
+  match merge_type {
+    MergeType::SolutionReset | MergeType::StarterReset => labels.push(RESET_LABEL.into()),
+    MergeType::Conflict(_) => labels.push(CONFLICT_LABEL.into()),
+    MergeType::Success => {}
+  }
+  labels
+}
+
+/// Updates a repo's cached PRs with the results of [`ForgeRepo::refresh`], where `None` means
+/// the PR was not found. The cached review comments of each PR are kept.
+pub(crate) fn refresh_prs(
+  cache: &Mutex<Option<Vec<FullPullRequest>>>,
+  fetched: Vec<(u64, Option<PullRequest>)>,
+) {
+  let mut cache = cache.lock();
+  let prs = cache.get_or_insert_with(Vec::new);
+  for (number, pr) in fetched {
+    let idx = prs.iter().position(|cached| cached.data.number == number);
+    match (idx, pr) {
+      (Some(idx), Some(pr)) => prs[idx].data = pr,
+      (Some(idx), None) => {
+        prs.remove(idx);
+      }
+      (None, Some(pr)) => prs.push(FullPullRequest {
+        data: pr,
+        comments: Vec::new(),
+      }),
+      (None, None) => {}
+    }
+  }
+}
+
+/// Updates a repo's cached issues with the results of [`ForgeRepo::refresh`], where `None`
+/// means the issue was not found.
+pub(crate) fn refresh_issues(
+  cache: &Mutex<Option<Vec<Issue>>>,
+  fetched: Vec<(u64, Option<Issue>)>,
+) {
+  let mut cache = cache.lock();
+  let issues = cache.get_or_insert_with(Vec::new);
+  for (number, issue) in fetched {
+    let idx = issues.iter().position(|cached| cached.number == number);
+    match (idx, issue) {
+      (Some(idx), Some(issue)) => issues[idx] = issue,
+      (Some(idx), None) => {
+        issues.remove(idx);
+      }
+      (None, Some(issue)) => issues.push(issue),
+      (None, None) => {}
+    }
+  }
+}
+
+pub(crate) const RESET_LABEL: &str = "reset";
+pub(crate) const REWOUND_LABEL: &str = "rewound";
+pub(crate) const CONFLICT_LABEL: &str = "conflict";
+
+pub enum GitProtocol {
+  Ssh,
+  Https,
+}
+
+/// A service that hosts quest repositories, e.g. GitHub.
+#[async_trait]
+pub trait Forge: Send + Sync + 'static {
+  /// Returns the login of the authenticated user.
+  async fn current_user(&self) -> Result<String>;
+
+  /// Loads an existing repo and fetches its PRs and issues.
+  async fn load_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>>;
+
+  /// Whether `owner` has a repo named `name`, without fetching its PRs and issues. Only a
+  /// repo that is not found is false, any other failure is an error.
+  async fn repo_exists(&self, owner: &str, name: &str) -> Result<bool>;
+
+  /// Creates an empty repo owned by the current user.
+  async fn create_repo(&self, name: &str) -> Result<Box<dyn ForgeRepo>>;
+
+  /// Creates a repo named `name` owned by the current user with the contents of `base`.
+  async fn create_repo_from_template(
+    &self,
+    base: &dyn ForgeRepo,
+    name: &str,
+  ) -> Result<Box<dyn ForgeRepo>>;
+}
+
+/// A single repository on a [`Forge`].
+///
+/// PRs and issues are cached by [`ForgeRepo::fetch`] so they can be queried synchronously.
+#[async_trait]
+pub trait ForgeRepo: Send + Sync + 'static {
+  fn owner(&self) -> &str;
+
+  fn name(&self) -> &str;
+
+  fn remote(&self, protocol: GitProtocol) -> String;
+
+  /// Refreshes the cached PRs and issues. Returns false if the repo does not exist.
+  async fn fetch(&self) -> Result<bool>;
+
+  /// Refreshes only the cached PRs and issues with these numbers, without their review comments.
+  /// Unlike [`ForgeRepo::fetch`], this costs a request per PR or issue however busy the repo is.
+  /// PRs and issues that no longer exist are dropped from the cache.
+  async fn refresh(&self, prs: &[u64], issues: &[u64]) -> Result<()>;
+
+  fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>>;
+
+  fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>>;
+
+  async fn labels(&self) -> Result<Vec<Label>>;
+
+  async fn create_labels(&self, labels: &[Label]) -> Result<()>;
+
+  async fn create_pr(&self, title: &str, head: &str, base: &str, body: &str)
+    -> Result<PullRequest>;
+
+  async fn add_pr_labels(&self, pr: u64, labels: &[String]) -> Result<()>;
+
+  async fn create_pr_comment(&self, pr: u64, comment: &Comment, commit: &str) -> Result<()>;
+
+  async fn create_issue(&self, title: &str, body: &str, labels: &[String]) -> Result<Issue>;
+
+  async fn close_issue(&self, issue: &Issue) -> Result<()>;
+
+  async fn merge_pr(&self, pr: &PullRequest) -> Result<()>;
+
+  async fn delete(&self) -> Result<()>;
+
+  /// How to refer to a PR in Markdown, e.g. `#3` on GitHub.
+  fn pr_reference(&self, number: u64) -> String {
+    format!("#{number}")
+  }
+
+  fn clone(&self, path: &Path) -> Result<GitRepo> {
+    let remote = self.remote(GitProtocol::Ssh);
+    GitRepo::clone(&path.join(self.name()), &remote)
+  }
+
+  fn pr(&self, selector: &PullSelector) -> Option<MappedMutexGuard<'_, FullPullRequest>> {
+    let prs = self.prs();
+    let idx = find_pr(selector, prs.iter())?;
+    Some(MappedMutexGuard::map(prs, |prs| &mut prs[idx]))
+  }
+
+  fn issue(&self, label_name: &str) -> Option<MappedMutexGuard<'_, Issue>> {
+    let issues = self.issues();
+    let idx = find_issue(label_name, issues.iter())?;
+    Some(MappedMutexGuard::map(issues, |issues| &mut issues[idx]))
+  }
+
+  async fn copy_pr(
+    &self,
+    pr: &PackagePr,
+    head: &str,
+    merge_type: MergeType,
+  ) -> Result<PullRequest> {
+    let body = copied_pr_body(pr, &merge_type);
+
+    let title = pr.title.as_ref().expect("Author error: PR missing title");
+    let self_pr = self
+      .create_pr(title, &pr.branch, "main", &body) // don't copy base
+      .await
+      .context("Failed to create new PR")?;
+
+    // TODO: lots of parallelism below we should exploit
+
+    let labels = copied_pr_labels(pr, &merge_type);
+    self
+      .add_pr_labels(self_pr.number, &labels)
+      .await
+      .context("Failed to add labels to PR")?;
+
+    for comment in &pr.comments {
+      self
+        .create_pr_comment(self_pr.number, &comment.into(), head)
+        .await
+        .context("Failed to add comment to PR")?;
+    }
+
+    Ok(self_pr)
+  }
+
+  fn process_issue_body(&self, body: &str) -> String {
+    let re = Regex::new(r"\{\{ (\S+) (\S+) \}\}").unwrap();
+    let mut new_body = body.to_string();
+    let substitutions = re.captures_iter(body).filter_map(|cap| {
+      let full_match = cap.get(0).unwrap();
+      let label = &cap[1];
+      let kind = &cap[2];
+      let reference = match kind {
+        "pr" => {
+          let Some(pr) = self.pr(&PullSelector::Label(label.to_string())) else {
+            warn!("No PR with label {label}");
+            return None;
+          };
+          self.pr_reference(pr.data.number)
+        }
+        "issue" => {
+          let Some(issue) = self.issue(label) else {
+            warn!("No issue with label {label}");
+            return None;
+          };
+          format!("#{}", issue.number)
+        }
+        _ => unimplemented!(),
+      };
+
+      Some((full_match.range(), reference))
+    });
+    utils::replace_many_ranges(&mut new_body, substitutions);
+
+    new_body
+  }
+
+  async fn copy_issue(&self, issue: &PackageIssue) -> Result<Issue> {
+    let body = issue.body.as_ref().unwrap();
+    let body_processed = self.process_issue_body(body);
+    self
+      .create_issue(&issue.title, &body_processed, &issue.labels)
+      .await
+      .with_context(|| format!("Failed to create issue: {}", issue.title))
+  }
+}
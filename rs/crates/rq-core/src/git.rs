@@ -3,15 +3,17 @@ use std::{
   fs,
   io::Write,
   path::{Path, PathBuf},
-  process::Stdio,
+  process::{Output, Stdio},
 };
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use specta::Type;
 
 use crate::{
   command::command,
-  github::{GitProtocol, GithubRepo},
-  package::QuestPackage,
+  forge::{ForgeRepo, GitProtocol},
+  package::{FileMode, PackageFile, QuestPackage},
   template::QuestTemplate,
 };
 
@@ -21,11 +23,19 @@ pub struct GitRepo {
 
 pub const UPSTREAM: &str = "upstream";
 pub const INITIAL_TAG: &str = "initial";
+pub const BACKUP_PREFIX: &str = "rqst-backup";
+/// Git config key for the name of the learner's repo, which may differ from the quest's.
+const REPO_NAME_KEY: &str = "rqst.repo";
+/// Git config key for the public key that signed a quest's package, if it was signed.
+const SIGNER_KEY: &str = "rqst.signer";
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
 pub enum MergeType {
   Success,
   SolutionReset,
   StarterReset,
+  /// A three-way merge left conflict markers in these files, or could not merge them at all.
+  Conflict(Vec<String>),
 }
 
 macro_rules! git {
@@ -44,6 +54,8 @@ macro_rules! git_output {
   }}
 }
 
+pub(crate) use {git, git_output};
+
 impl GitRepo {
   pub fn new(path: &Path) -> Self {
     GitRepo {
@@ -51,8 +63,35 @@ impl GitRepo {
     }
   }
 
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// The repo's `.git` directory.
+  pub fn git_dir(&self) -> Result<PathBuf> {
+    let output = git_output!(self, "rev-parse --absolute-git-dir")?;
+    Ok(PathBuf::from(output.trim_end()))
+  }
+
+  /// Creates a new repo at `path`, with `main` as its default branch.
+  pub fn init(path: &Path) -> Result<Self> {
+    fs::create_dir_all(path)
+      .with_context(|| format!("Failed to create directory: {}", path.display()))?;
+    let repo = GitRepo::new(path);
+    git!(repo, "init --initial-branch=main")?;
+    Ok(repo)
+  }
+
+  /// Commits in this repo as RepoQuest. Scratch repos are thrown away, so committing in them
+  /// shouldn't need the user to have set up a git identity.
+  pub fn use_scratch_identity(&self) -> Result<()> {
+    git!(self, "config user.name RepoQuest")?;
+    git!(self, "config user.email repo-quest@localhost")
+  }
+
   pub fn clone(path: &Path, url: &str) -> Result<Self> {
-    let output = command(&format!("git clone {url}"), path.parent().unwrap()).output()?;
+    let quoted = shlex::try_quote(url)?;
+    let output = command(&format!("git clone {quoted}"), path.parent().unwrap()).output()?;
     ensure!(
       output.status.success(),
       "`git clone {url}` failed, stderr:\n{}",
@@ -84,16 +123,17 @@ impl GitRepo {
     Ok(stdout)
   }
 
-  fn git(&self, args: &str) -> Result<()> {
+  pub(crate) fn git(&self, args: &str) -> Result<()> {
     self.git_core(args, false).map(|_| ())
   }
 
-  fn git_output(&self, args: &str) -> Result<String> {
+  pub(crate) fn git_output(&self, args: &str) -> Result<String> {
     self.git_core(args, true).map(|s| s.unwrap())
   }
 
-  pub fn setup_upstream(&self, upstream: &GithubRepo) -> Result<()> {
+  pub fn setup_upstream(&self, upstream: &dyn ForgeRepo) -> Result<()> {
     let remote = upstream.remote(GitProtocol::Https);
+    let remote = shlex::try_quote(&remote)?;
     git!(self, "remote add {UPSTREAM} {remote}")?;
     git!(self, "fetch {UPSTREAM}")?;
     Ok(())
@@ -101,6 +141,7 @@ impl GitRepo {
 
   pub fn has_upstream(&self) -> Result<bool> {
     let status = command(&format!("git remote get-url {UPSTREAM}"), &self.path)
+      .stdout(Stdio::null())
       .status()
       .context("`git remote` failed")?;
     Ok(status.success())
@@ -108,41 +149,138 @@ impl GitRepo {
 
   fn apply(&self, patch: &str) -> Result<()> {
     tracing::trace!("Applying patch:\n{patch}");
-    let mut child = command("git apply -", &self.path)
+    let output = self.apply_with("", patch)?;
+    ensure!(
+      output.status.success(),
+      "git apply failed with stderr:\n{}",
+      String::from_utf8(output.stderr)?
+    );
+    Ok(())
+  }
+
+  fn apply_with(&self, flags: &str, patch: &str) -> Result<Output> {
+    let mut child = command(&format!("git apply {flags} -"), &self.path)
       .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
       .stderr(Stdio::piped())
       .spawn()?;
     let mut stdin = child.stdin.take().unwrap();
     stdin.write_all(patch.as_bytes())?;
     drop(stdin);
-    let output = child.wait_with_output()?;
+    Ok(child.wait_with_output()?)
+  }
+
+  /// Applies `patch` with a three-way merge, leaving conflict markers where it conflicts with
+  /// the working tree. Returns the conflicting files, or `None` if the patch could not be merged
+  /// at all, e.g. because the repo lacks the blobs it was made from.
+  fn apply_3way(&self, patch: &str) -> Result<Option<Vec<String>>> {
+    let output = self.apply_with("--3way", patch)?;
+    if output.status.success() {
+      return Ok(Some(Vec::new()));
+    }
+    let conflicts = self.conflicted_files()?;
+    if conflicts.is_empty() {
+      tracing::warn!(
+        "Three-way merge failed with stderr:\n{}",
+        String::from_utf8(output.stderr)?
+      );
+      return Ok(None);
+    }
+    Ok(Some(conflicts))
+  }
+
+  /// The files that `patch` changes. Renamed files are listed under both names.
+  fn patch_files(&self, patch: &str) -> Result<Vec<String>> {
+    let output = self.apply_with("--numstat -z", patch)?;
     ensure!(
       output.status.success(),
-      "git apply failed with stderr:\n{}",
+      "git apply --numstat failed with stderr:\n{}",
       String::from_utf8(output.stderr)?
     );
-    tracing::trace!("wtf: {}", String::from_utf8(output.stderr)?);
-    Ok(())
+    let stdout = String::from_utf8(output.stdout)?;
+    // Each entry is `added\tdeleted\tpath\0`, or `added\tdeleted\t\0old\0new\0` for a rename.
+    let mut files = Vec::new();
+    let mut fields = stdout.split('\0');
+    while let Some(entry) = fields.next() {
+      match entry.splitn(3, '\t').nth(2) {
+        Some("") => files.extend(fields.by_ref().take(2).map(String::from)),
+        Some(path) => files.push(path.to_string()),
+        None => {}
+      }
+    }
+    Ok(files)
+  }
+
+  /// Applies `patch` one file at a time with a three-way merge, for when the patch can't be
+  /// merged as a whole, e.g. because the learner deleted a file it changes. Returns the files
+  /// that conflict, including those that could not be merged at all and are left as they were.
+  fn apply_3way_per_file(&self, patch: &str) -> Result<Vec<String>> {
+    let mut conflicts = Vec::new();
+    for file in self.patch_files(patch)? {
+      let include = shlex::try_quote(&format!("--include={file}"))?.into_owned();
+      let output = self.apply_with(&format!("--3way {include}"), patch)?;
+      if !output.status.success() {
+        tracing::warn!(
+          "Three-way merge of {file} failed with stderr:\n{}",
+          String::from_utf8(output.stderr)?
+        );
+        conflicts.push(file);
+      }
+    }
+    for file in self.conflicted_files()? {
+      if !conflicts.contains(&file) {
+        conflicts.push(file);
+      }
+    }
+    Ok(conflicts)
+  }
+
+  fn conflicted_files(&self) -> Result<Vec<String>> {
+    let output = git_output!(self, "diff --name-only --diff-filter=U")?;
+    Ok(output.lines().map(String::from).collect())
+  }
+
+  /// Commits a three-way merge, which is a [`MergeType::Conflict`] if any `conflicts` remain.
+  fn commit_merge(&self, conflicts: Vec<String>, message: &str) -> Result<MergeType> {
+    git!(self, "add .")?;
+    if conflicts.is_empty() {
+      git!(self, "commit -m '{message}'")?;
+      Ok(MergeType::Success)
+    } else {
+      // Nothing may have merged, but the PR is still filed so the learner can reset it.
+      git!(self, "commit --allow-empty -m '{message} (with conflicts)'")?;
+      Ok(MergeType::Conflict(conflicts))
+    }
   }
 
   pub fn apply_patch(&self, patches: &[&str]) -> Result<MergeType> {
     let last = patches.last().unwrap();
-    let merge_type = match self.apply(last) {
-      Ok(()) => MergeType::Success,
-      Err(e) => {
-        tracing::warn!("Failed to apply patch: {e:?}");
-        git!(self, "reset --hard {INITIAL_TAG}")?;
-        for patch in patches {
-          self.apply(patch)?;
-        }
-        MergeType::StarterReset
-      }
-    };
+    if let Err(e) = self.apply(last) {
+      tracing::warn!("Failed to apply patch, trying a three-way merge: {e:?}");
+      let conflicts = match self.apply_3way(last)? {
+        Some(conflicts) => conflicts,
+        None => self.apply_3way_per_file(last)?,
+      };
+      return self.commit_merge(conflicts, "Starter code");
+    }
+
+    git!(self, "add .")?;
+    git!(self, "commit -m 'Starter code'")?;
+
+    Ok(MergeType::Success)
+  }
+
+  /// Replaces the working tree with the initial code plus `patches`, discarding the learner's changes.
+  pub fn reset_patch(&self, patches: &[&str]) -> Result<MergeType> {
+    git!(self, "reset --hard {INITIAL_TAG}")?;
+    for patch in patches {
+      self.apply(patch)?;
+    }
 
     git!(self, "add .")?;
     git!(self, "commit -m 'Starter code'")?;
 
-    Ok(merge_type)
+    Ok(MergeType::StarterReset)
   }
 
   pub fn cherry_pick(&self, base_branch: &str, target_branch: &str) -> Result<MergeType> {
@@ -151,23 +289,34 @@ impl GitRepo {
       "cherry-pick {UPSTREAM}/{base_branch}..{UPSTREAM}/{target_branch}"
     );
 
-    Ok(match res {
-      Ok(_) => MergeType::Success,
-      Err(e) => {
-        tracing::warn!("Merge conflicts when cherry-picking, resorting to hard reset: ${e:?}");
+    let Err(e) = res else {
+      return Ok(MergeType::Success);
+    };
+    tracing::warn!("Merge conflicts when cherry-picking, trying a three-way merge: {e:?}");
 
-        git!(self, "cherry-pick --abort").context("Failed to abort cherry-pick")?;
+    git!(self, "cherry-pick --abort").context("Failed to abort cherry-pick")?;
 
-        let upstream_target = format!("{UPSTREAM}/{target_branch}");
-        git!(self, "reset --hard {upstream_target}")?;
+    let patch = self.diff(
+      &format!("{UPSTREAM}/{base_branch}"),
+      &format!("{UPSTREAM}/{target_branch}"),
+    )?;
+    let conflicts = match self.apply_3way(&patch)? {
+      Some(conflicts) => conflicts,
+      None => self.apply_3way_per_file(&patch)?,
+    };
+    self.commit_merge(conflicts, "Merge reference solution")
+  }
 
-        git!(self, "reset --soft main").context("Failed to soft reset to main")?;
+  /// Replaces the working tree with the upstream `target_branch`, discarding the learner's changes.
+  pub fn reset_solution(&self, target_branch: &str) -> Result<MergeType> {
+    let upstream_target = format!("{UPSTREAM}/{target_branch}");
+    git!(self, "reset --hard {upstream_target}")?;
 
-        git!(self, "commit -m 'Override with reference solution'")?;
+    git!(self, "reset --soft main").context("Failed to soft reset to main")?;
 
-        MergeType::SolutionReset
-      }
-    })
+    git!(self, "commit -m 'Override with reference solution'")?;
+
+    Ok(MergeType::SolutionReset)
   }
 
   pub fn create_branch_from(
@@ -189,6 +338,60 @@ impl GitRepo {
     Ok((head, merge_type))
   }
 
+  /// Resets `target_branch` to the quest's code, discarding the learner's changes, and force-pushes it.
+  pub fn reset_branch(
+    &self,
+    template: &dyn QuestTemplate,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<String> {
+    git!(self, "checkout {target_branch}")?;
+
+    template.reset_patch(self, base_branch, target_branch)?;
+
+    git!(self, "push --force origin {target_branch}")?;
+
+    let head = self.head_commit()?;
+
+    git!(self, "checkout main")?;
+
+    Ok(head)
+  }
+
+  /// Predicts the [`MergeType`] of [`GitRepo::create_branch_from`] on top of origin's main as
+  /// last fetched, which may be stale, see [`GitRepo::origin_main_stale`]. The patch is applied
+  /// in a scratch clone that shares this repo's objects, so nothing in the repo is changed.
+  pub fn predict_merge(
+    &self,
+    template: &dyn QuestTemplate,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<MergeType> {
+    let dir = tempfile::tempdir().context("Failed to create scratch directory")?;
+    let scratch_path = dir.path().join("predict");
+    let source = shlex::try_quote(&self.path.display().to_string())?.into_owned();
+    let scratch_arg = shlex::try_quote(&scratch_path.display().to_string())?.into_owned();
+    git!(self, "clone --shared --no-checkout {source} {scratch_arg}")?;
+
+    // The clone only has our branches, so copy every ref, e.g. origin/main and upstream/*.
+    let scratch = GitRepo::new(&scratch_path);
+    git!(scratch, "fetch --update-head-ok origin '+refs/*:refs/*'")?;
+    git!(scratch, "checkout --detach origin/main")?;
+    scratch.use_scratch_identity()?;
+    template.apply_patch(&scratch, base_branch, target_branch)
+  }
+
+  /// Whether origin's main on the forge differs from the last fetched `origin/main`, checked
+  /// without fetching. If the forge can't be reached, it may be, so this is true.
+  pub fn origin_main_stale(&self) -> bool {
+    let remote = git_output!(self, "ls-remote origin refs/heads/main");
+    let local = git_output!(self, "rev-parse origin/main");
+    match (remote, local) {
+      (Ok(remote), Ok(local)) => remote.split_whitespace().next() != Some(local.trim()),
+      _ => true,
+    }
+  }
+
   pub fn checkout_main_and_pull(&self) -> Result<()> {
     git!(self, "checkout main")?;
     git!(self, "pull")?;
@@ -206,6 +409,160 @@ impl GitRepo {
     Ok(())
   }
 
+  /// The first commit on main, i.e. the quest's initial code.
+  pub fn root_commit(&self) -> Result<String> {
+    let output = git_output!(self, "rev-list --max-parents=0 main")?;
+    let root = output.lines().last().context("Main has no commits")?;
+    Ok(root.to_string())
+  }
+
+  /// Saves `rev` to a new `rqst-backup/<stage>-<time>` branch and pushes it, returning the branch.
+  pub fn backup(&self, stage: &str, rev: &str) -> Result<String> {
+    let time = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let mut branch = format!("{BACKUP_PREFIX}/{stage}-{time}");
+    let mut n = 1;
+    while git_output!(self, "branch --list {branch}")?.trim() != "" {
+      n += 1;
+      branch = format!("{BACKUP_PREFIX}/{stage}-{time}-{n}");
+    }
+    git!(self, "branch {branch} {rev}")?;
+    git!(self, "push origin {branch}")?;
+    Ok(branch)
+  }
+
+  /// Deletes `branch` locally and from origin, wherever it exists.
+  pub fn delete_branch(&self, branch: &str) -> Result<()> {
+    if git_output!(self, "branch --list {branch}")?.trim() != "" {
+      git!(self, "branch -D {branch}")?;
+    }
+    if git_output!(self, "ls-remote --heads origin {branch}")?.trim() != "" {
+      git!(self, "push origin --delete {branch}")?;
+    }
+    Ok(())
+  }
+
+  /// Whether `a` and `b` have identical contents.
+  pub fn same_tree(&self, a: &str, b: &str) -> Result<bool> {
+    let a = git_output!(self, "rev-parse {a}^{{tree}}")?;
+    let b = git_output!(self, "rev-parse {b}^{{tree}}")?;
+    Ok(a == b)
+  }
+
+  /// Creates a local `branch` from origin, unless it already exists.
+  pub fn track_branch(&self, branch: &str) -> Result<()> {
+    if git_output!(self, "branch --list {branch}")?.trim() == "" {
+      git!(self, "branch --track {branch} origin/{branch}")?;
+    }
+    Ok(())
+  }
+
+  /// The value of `key` in the repo's git config, or `None` if it is not set.
+  fn config_value(&self, key: &str) -> Result<Option<String>> {
+    let output = command(&format!("git config --get {key}"), &self.path)
+      .stderr(Stdio::null())
+      .output()
+      .context("`git config` failed")?;
+    Ok(
+      output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()),
+    )
+  }
+
+  /// The URL of `remote` as configured, i.e. without `insteadOf` rewrites.
+  pub fn remote_url(&self, remote: &str) -> Result<Option<String>> {
+    self.config_value(&format!("remote.{remote}.url"))
+  }
+
+  /// The name of the learner's repo on the forge, if recorded with [`GitRepo::set_repo_name`].
+  pub fn repo_name(&self) -> Result<Option<String>> {
+    self.config_value(REPO_NAME_KEY)
+  }
+
+  pub fn set_repo_name(&self, name: &str) -> Result<()> {
+    let name = shlex::try_quote(name)?;
+    git!(self, "config --local {REPO_NAME_KEY} {name}")
+  }
+
+  pub fn signer_key(&self) -> Result<Option<String>> {
+    self.config_value(SIGNER_KEY)
+  }
+
+  pub fn set_signer_key(&self, public_key: &str) -> Result<()> {
+    let public_key = shlex::try_quote(public_key)?;
+    git!(self, "config --local {SIGNER_KEY} {public_key}")
+  }
+
+  fn has_ref(&self, name: &str) -> Result<bool> {
+    let status = command(
+      &format!("git rev-parse --verify --quiet {name}"),
+      &self.path,
+    )
+    .stdout(Stdio::null())
+    .status()
+    .context("`git rev-parse` failed")?;
+    Ok(status.success())
+  }
+
+  pub fn has_branch(&self, branch: &str) -> Result<bool> {
+    self.has_ref(&format!("refs/heads/{branch}"))
+  }
+
+  pub fn has_remote_branch(&self, remote: &str, branch: &str) -> Result<bool> {
+    self.has_ref(&format!("refs/remotes/{remote}/{branch}"))
+  }
+
+  /// Whether `ancestor` is in the history of `rev`.
+  pub fn is_ancestor(&self, ancestor: &str, rev: &str) -> Result<bool> {
+    let status = command(
+      &format!("git merge-base --is-ancestor {ancestor} {rev}"),
+      &self.path,
+    )
+    .status()
+    .context("`git merge-base` failed")?;
+    Ok(status.success())
+  }
+
+  /// Bundles `branches` with their history, returning the bundle's contents.
+  pub fn create_bundle(&self, branches: &[String]) -> Result<Vec<u8>> {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("quest.bundle");
+    let path = shlex::try_quote(&file.display().to_string())?.into_owned();
+    git!(self, "bundle create {path} {}", branches.join(" "))?;
+    fs::read(&file).with_context(|| format!("Failed to read bundle: {}", file.display()))
+  }
+
+  /// Fetches every branch in `bundle` to `upstream/<branch>`, as if the bundle were the
+  /// upstream remote, unless they were already fetched.
+  pub fn fetch_bundle(&self, bundle: &[u8]) -> Result<()> {
+    if self.has_ref(&format!("refs/remotes/{UPSTREAM}/main"))? {
+      return Ok(());
+    }
+    self.fetch_bundle_into(bundle, &format!("'refs/heads/*:refs/remotes/{UPSTREAM}/*'"))
+  }
+
+  /// Fetches every branch in `bundle` to a local branch of the same name, and checks out main.
+  pub fn fetch_bundle_branches(&self, bundle: &[u8]) -> Result<()> {
+    self.fetch_bundle_into(bundle, "--update-head-ok 'refs/heads/*:refs/heads/*'")?;
+    git!(self, "reset --hard main")
+  }
+
+  /// Runs `git fetch` from `bundle` with `args`, e.g. a quoted refspec.
+  fn fetch_bundle_into(&self, bundle: &[u8], args: &str) -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("quest.bundle");
+    fs::write(&file, bundle)
+      .with_context(|| format!("Failed to write bundle: {}", file.display()))?;
+    let path = shlex::try_quote(&file.display().to_string())?.into_owned();
+    git!(self, "fetch {path} {args}")
+  }
+
+  /// Like [`GitRepo::diff`], but binary files are included so the diff can be applied.
+  pub fn binary_diff(&self, base: &str, head: &str) -> Result<String> {
+    git_output!(self, "diff --binary {base}..{head}")
+  }
+
   pub fn diff(&self, base: &str, head: &str) -> Result<String> {
     git_output!(self, "diff {base}..{head}")
   }
@@ -226,53 +583,100 @@ impl GitRepo {
     Ok(output.stdout)
   }
 
-  pub fn read_initial_files(&self) -> Result<HashMap<PathBuf, String>> {
-    let ls_tree_out = git_output!(self, "ls-tree -r main --name-only")?;
-    let files = ls_tree_out.trim().split("\n");
-    files
-      .map(|file| {
-        let path = PathBuf::from(file);
-        let contents = self.show("main", file)?;
-        Ok((path, contents))
+  /// Every file on main, with its mode.
+  pub fn read_initial_files(&self) -> Result<HashMap<PathBuf, PackageFile>> {
+    let ls_tree_out = git_output!(self, "ls-tree -r -z main")?;
+    ls_tree_out
+      .split('\0')
+      .filter(|line| !line.is_empty())
+      .map(|line| {
+        let (info, file) = line.split_once('\t').context("Invalid ls-tree output")?;
+        let [mode, _kind, hash] = info.split(' ').collect::<Vec<_>>()[..] else {
+          bail!("Invalid ls-tree output: {line}");
+        };
+        let mode = FileMode::from_git(mode)
+          .with_context(|| format!("Unsupported file mode {mode} of {file}"))?;
+        let contents = self.blob(hash)?;
+        Ok((PathBuf::from(file), PackageFile::new(mode, contents)))
       })
       .collect()
   }
 
-  pub fn write_initial_files(&self, package: &QuestPackage) -> Result<()> {
-    for (rel_path, contents) in &package.initial {
-      let abs_path = self.path.join(rel_path);
-      if let Some(dir) = abs_path.parent() {
-        fs::create_dir_all(dir)
-          .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
-      }
-      fs::write(&abs_path, contents)
-        .with_context(|| format!("Failed to write: {}", abs_path.display()))?;
-    }
+  fn blob(&self, hash: &str) -> Result<Vec<u8>> {
+    let output = command(&format!("git cat-file blob {hash}"), &self.path)
+      .output()
+      .with_context(|| format!("Failed to `git cat-file blob {hash}`"))?;
+    ensure!(
+      output.status.success(),
+      "git cat-file failed with stderr:\n{}",
+      String::from_utf8(output.stderr)?
+    );
+    Ok(output.stdout)
+  }
 
-    // HACK:Eventually we should either directly package a git repo in the file
-    // or include the permissions
-    #[cfg(unix)]
-    {
-      use std::os::unix::fs::PermissionsExt;
-      let hooks_dir = self.path.join(".githooks");
-      if hooks_dir.exists() {
-        let hooks = fs::read_dir(&hooks_dir)
-          .with_context(|| format!("Failed to read hooks directory: {}", hooks_dir.display()))?;
-        for hook in hooks {
-          let hook = hook.context("Failed to read hooks directory entry")?;
-          let mut perms = hook
-            .metadata()
-            .with_context(|| format!("Failed to read hook metadata: {}", hook.path().display()))?
-            .permissions();
-          perms.set_mode(perms.mode() | 0o111);
-          fs::set_permissions(hook.path(), perms).with_context(|| {
-            format!("Failed to set hook permissions: {}", hook.path().display())
-          })?;
-        }
-      }
+  /// Writes `contents` to the object database, returning its hash.
+  fn hash_blob(&self, contents: &[u8]) -> Result<String> {
+    let mut child = command("git hash-object -w --stdin", &self.path)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(contents)?;
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    ensure!(
+      output.status.success(),
+      "git hash-object failed with stderr:\n{}",
+      String::from_utf8(output.stderr)?
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+  }
+
+  /// Adds `files` straight to the index.
+  fn index_files(&self, files: &HashMap<PathBuf, PackageFile>) -> Result<()> {
+    for (rel_path, file) in files {
+      let hash = self.hash_blob(&file.contents.to_bytes()?)?;
+      let mode = file.mode.git_mode();
+      let cacheinfo =
+        shlex::try_quote(&format!("{mode},{hash},{}", rel_path.display()))?.into_owned();
+      git!(self, "update-index --add --cacheinfo {cacheinfo}")?;
     }
+    Ok(())
+  }
+
+  /// Makes the first commit of a new repo on main with exactly `files`.
+  pub fn commit_initial_files(&self, files: &HashMap<PathBuf, PackageFile>) -> Result<()> {
+    self.index_files(files)?;
+    git!(self, "checkout-index --all --force")?;
+    git!(self, "commit -m 'Initial commit'")
+  }
 
+  /// Creates `branch` from `base` with `patch` applied on top as a single commit.
+  pub fn commit_patch(&self, base: &str, branch: &str, patch: &str, message: &str) -> Result<()> {
+    git!(self, "checkout -b {branch} {base}")?;
+    let message = shlex::try_quote(message)?;
+    if patch.is_empty() {
+      return git!(self, "commit --allow-empty -m {message}");
+    }
+    self
+      .apply(patch)
+      .with_context(|| format!("Failed to apply patch for {branch} on {base}"))?;
     git!(self, "add .")?;
+    git!(self, "commit -m {message}")
+  }
+
+  pub fn write_initial_files(&self, package: &QuestPackage) -> Result<()> {
+    // Files are added straight to the index, so their modes and symlinks are kept exactly
+    // regardless of what the filesystem supports.
+    if let Some(bundle) = package.bundle()? {
+      self.fetch_bundle(&bundle)?;
+      git!(self, "read-tree {UPSTREAM}/main")?;
+    } else {
+      self.index_files(&package.initial)?;
+    }
+    git!(self, "checkout-index --all --force")?;
+
     git!(self, "commit -m 'Initial commit'")?;
     git!(self, "tag {INITIAL_TAG}")?;
     git!(self, "push -u origin main")?;
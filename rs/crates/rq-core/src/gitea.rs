@@ -0,0 +1,527 @@
+//! Forge backed by the Gitea REST API, which is also served by Forgejo.
+
+use std::sync::Arc;
+
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use futures_util::future::try_join_all;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::try_join;
+
+use crate::{
+  forge::{
+    self, Comment, Forge, ForgeRepo, FullPullRequest, GitProtocol, Head, Issue, IssueState, Label,
+    PullRequest,
+  },
+  rest::RestClient,
+};
+
+#[derive(Deserialize)]
+struct GiteaUser {
+  login: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaRepoInfo {
+  clone_url: String,
+  ssh_url: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaLabel {
+  id: u64,
+  name: String,
+  color: String,
+  description: Option<String>,
+}
+
+impl From<GiteaLabel> for Label {
+  fn from(label: GiteaLabel) -> Self {
+    Label {
+      name: label.name,
+      color: label.color.trim_start_matches('#').to_string(),
+      description: label.description.filter(|desc| !desc.is_empty()),
+      default: false,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GiteaIssue {
+  number: u64,
+  title: String,
+  body: Option<String>,
+  labels: Vec<GiteaLabel>,
+  state: IssueState,
+  html_url: String,
+}
+
+impl From<GiteaIssue> for Issue {
+  fn from(issue: GiteaIssue) -> Self {
+    Issue {
+      number: issue.number,
+      title: issue.title,
+      body: issue.body,
+      labels: issue.labels.into_iter().map(Label::from).collect(),
+      state: issue.state,
+      html_url: issue.html_url,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GiteaBranch {
+  #[serde(rename = "ref")]
+  ref_field: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaPull {
+  number: u64,
+  title: Option<String>,
+  body: Option<String>,
+  head: GiteaBranch,
+  labels: Vec<GiteaLabel>,
+  html_url: String,
+  merged_at: Option<String>,
+}
+
+impl From<GiteaPull> for PullRequest {
+  fn from(pr: GiteaPull) -> Self {
+    PullRequest {
+      number: pr.number,
+      title: pr.title,
+      body: pr.body,
+      head: Head {
+        ref_field: pr.head.ref_field,
+      },
+      labels: pr.labels.into_iter().map(Label::from).collect(),
+      html_url: pr.html_url,
+      merged_at: pr.merged_at,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GiteaReview {
+  id: u64,
+}
+
+#[derive(Deserialize)]
+struct GiteaReviewComment {
+  path: String,
+  body: String,
+  position: Option<u64>,
+}
+
+pub struct GiteaForge {
+  client: Arc<RestClient>,
+}
+
+impl GiteaForge {
+  /// Connects to the Gitea or Forgejo instance at `url`, e.g. `https://codeberg.org`.
+  pub fn new(url: &str, token: &str) -> Result<Self> {
+    let base = format!("{}/api/v1", url.trim_end_matches('/'));
+    let client = RestClient::new(&base, "Authorization", &format!("token {token}"))?;
+    Ok(GiteaForge {
+      client: Arc::new(client),
+    })
+  }
+
+  async fn repo(&self, owner: &str, name: &str) -> Result<Option<GiteaRepo>> {
+    let route = format!("/repos/{owner}/{name}");
+    let info = self
+      .client
+      .json::<GiteaRepoInfo>(Method::GET, &route, None)
+      .await?;
+    Ok(info.map(|info| GiteaRepo {
+      client: Arc::clone(&self.client),
+      owner: owner.to_string(),
+      name: name.to_string(),
+      clone_url: info.clone_url,
+      ssh_url: info.ssh_url,
+      prs: Mutex::new(None),
+      issues: Mutex::new(None),
+    }))
+  }
+
+  async fn init_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let repo = self
+      .repo(owner, name)
+      .await?
+      .with_context(|| format!("Repo was not created: {owner}/{name}"))?;
+
+    // Creators automatically watch new repos, so unwatch to avoid annoying emails.
+    repo
+      .client
+      .send_unit(Method::DELETE, &repo.route("/subscription"), None)
+      .await
+      .context("Failed to unsubscribe from repo")?;
+
+    repo.fetch().await?;
+    Ok(Box::new(repo))
+  }
+}
+
+#[async_trait]
+impl Forge for GiteaForge {
+  async fn current_user(&self) -> Result<String> {
+    let user = self
+      .client
+      .get::<GiteaUser>("/user")
+      .await
+      .context("Failed to query Gitea for current user")?;
+    Ok(user.login)
+  }
+
+  async fn load_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let repo = self.repo(owner, name).await?;
+    let repo = repo.with_context(|| format!("Not found: {owner}/{name}"))?;
+    ensure!(repo.fetch().await?, "Not found");
+    Ok(Box::new(repo))
+  }
+
+  async fn repo_exists(&self, owner: &str, name: &str) -> Result<bool> {
+    Ok(self.repo(owner, name).await?.is_some())
+  }
+
+  async fn create_repo(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let user = self.current_user().await?;
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::POST,
+        "/user/repos",
+        &json!({
+          "name": name,
+          "private": true,
+          "auto_init": false,
+        }),
+      )
+      .await
+      .context("Failed to create repo")?;
+    self.init_repo(&user, name).await
+  }
+
+  async fn create_repo_from_template(
+    &self,
+    base: &dyn ForgeRepo,
+    name: &str,
+  ) -> Result<Box<dyn ForgeRepo>> {
+    let user = self.current_user().await?;
+
+    // The base repo must be marked as a template in its settings. Labels are
+    // copied separately by the quest template, so they are excluded here.
+    let route = format!("/repos/{}/{}/generate", base.owner(), base.name());
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::POST,
+        &route,
+        &json!({
+          "owner": user,
+          "name": name,
+          "private": true,
+          "git_content": true,
+        }),
+      )
+      .await
+      .with_context(|| {
+        format!(
+          "Failed to clone template repo {}/{}",
+          base.owner(),
+          base.name()
+        )
+      })?;
+    self.init_repo(&user, name).await
+  }
+}
+
+pub struct GiteaRepo {
+  client: Arc<RestClient>,
+  owner: String,
+  name: String,
+  clone_url: String,
+  ssh_url: String,
+  prs: Mutex<Option<Vec<FullPullRequest>>>,
+  issues: Mutex<Option<Vec<Issue>>>,
+}
+
+impl GiteaRepo {
+  fn route(&self, suffix: &str) -> String {
+    format!("/repos/{}/{}{suffix}", self.owner, self.name)
+  }
+
+  async fn list_pulls(&self) -> Result<Option<Vec<GiteaPull>>> {
+    self.client.get_all(&self.route("/pulls?state=all")).await
+  }
+
+  async fn list_issues(&self) -> Result<Option<Vec<GiteaIssue>>> {
+    self
+      .client
+      .get_all(&self.route("/issues?state=all&type=issues"))
+      .await
+  }
+
+  /// Gitea only exposes review comments through the reviews that contain them.
+  async fn pr_comments(&self, number: u64) -> Result<Vec<Comment>> {
+    let reviews = self
+      .client
+      .get::<Vec<GiteaReview>>(&self.route(&format!("/pulls/{number}/reviews")))
+      .await?;
+    let comments = try_join_all(reviews.iter().map(|review| {
+      let route = self.route(&format!("/pulls/{number}/reviews/{}/comments", review.id));
+      async move { self.client.get::<Vec<GiteaReviewComment>>(&route).await }
+    }))
+    .await?;
+    Ok(
+      comments
+        .into_iter()
+        .flatten()
+        .map(|comment| Comment {
+          path: comment.path,
+          body: comment.body,
+          line: comment.position,
+        })
+        .collect(),
+    )
+  }
+
+  async fn label_ids(&self, names: &[String]) -> Result<Vec<u64>> {
+    let labels = self
+      .client
+      .get_all::<GiteaLabel>(&self.route("/labels"))
+      .await?
+      .unwrap_or_default();
+    names
+      .iter()
+      .map(|name| {
+        labels
+          .iter()
+          .find(|label| &label.name == name)
+          .map(|label| label.id)
+          .with_context(|| format!("Missing label: {name}"))
+      })
+      .collect()
+  }
+}
+
+#[async_trait]
+impl ForgeRepo for GiteaRepo {
+  fn owner(&self) -> &str {
+    &self.owner
+  }
+
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn remote(&self, protocol: GitProtocol) -> String {
+    match protocol {
+      GitProtocol::Ssh if !self.ssh_url.is_empty() => self.ssh_url.clone(),
+      _ => self.clone_url.clone(),
+    }
+  }
+
+  async fn fetch(&self) -> Result<bool> {
+    let (prs, issues) = try_join!(self.list_pulls(), self.list_issues())?;
+    let (Some(prs), Some(issues)) = (prs, issues) else {
+      return Ok(false);
+    };
+
+    let full_prs = try_join_all(prs.into_iter().map(|pr| async move {
+      let comments = self
+        .pr_comments(pr.number)
+        .await
+        .with_context(|| format!("Failed to fetch comments for PR {}", pr.number))?;
+      Ok::<_, anyhow::Error>(FullPullRequest {
+        data: pr.into(),
+        comments,
+      })
+    }))
+    .await?;
+
+    *self.prs.lock() = Some(full_prs);
+    *self.issues.lock() = Some(issues.into_iter().map(Issue::from).collect());
+
+    Ok(true)
+  }
+
+  async fn refresh(&self, prs: &[u64], issues: &[u64]) -> Result<()> {
+    let (prs, issues) = try_join!(
+      try_join_all(prs.iter().map(|&number| async move {
+        let route = self.route(&format!("/pulls/{number}"));
+        let pr = self
+          .client
+          .json::<GiteaPull>(Method::GET, &route, None)
+          .await?;
+        Ok::<_, anyhow::Error>((number, pr.map(PullRequest::from)))
+      })),
+      try_join_all(issues.iter().map(|&number| async move {
+        let route = self.route(&format!("/issues/{number}"));
+        let issue = self
+          .client
+          .json::<GiteaIssue>(Method::GET, &route, None)
+          .await?;
+        Ok::<_, anyhow::Error>((number, issue.map(Issue::from)))
+      }))
+    )?;
+    forge::refresh_prs(&self.prs, prs);
+    forge::refresh_issues(&self.issues, issues);
+    Ok(())
+  }
+
+  fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>> {
+    MutexGuard::map(self.prs.lock(), |opt| {
+      opt.as_mut().expect("PRs not populated")
+    })
+  }
+
+  fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>> {
+    MutexGuard::map(self.issues.lock(), |opt| {
+      opt.as_mut().expect("Issues not populated")
+    })
+  }
+
+  async fn labels(&self) -> Result<Vec<Label>> {
+    let labels = self
+      .client
+      .get_all::<GiteaLabel>(&self.route("/labels"))
+      .await?
+      .context("Failed to fetch labels")?;
+    Ok(labels.into_iter().map(Label::from).collect())
+  }
+
+  async fn create_labels(&self, labels: &[Label]) -> Result<()> {
+    let route = self.route("/labels");
+    try_join_all(labels.iter().filter(|label| !label.default).map(|label| {
+      let body = json!({
+        "name": label.name,
+        "color": format!("#{}", label.color),
+        "description": label.description.as_deref().unwrap_or(""),
+      });
+      let route = &route;
+      async move {
+        self
+          .client
+          .send::<serde_json::Value>(Method::POST, route, &body)
+          .await
+      }
+    }))
+    .await
+    .context("Failed to create labels")?;
+    Ok(())
+  }
+
+  async fn create_pr(
+    &self,
+    title: &str,
+    head: &str,
+    base: &str,
+    body: &str,
+  ) -> Result<PullRequest> {
+    let pr = self
+      .client
+      .send::<GiteaPull>(
+        Method::POST,
+        &self.route("/pulls"),
+        &json!({
+          "title": title,
+          "head": head,
+          "base": base,
+          "body": body,
+        }),
+      )
+      .await?;
+    Ok(pr.into())
+  }
+
+  async fn add_pr_labels(&self, pr: u64, labels: &[String]) -> Result<()> {
+    let ids = self.label_ids(labels).await?;
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::POST,
+        &self.route(&format!("/issues/{pr}/labels")),
+        &json!({ "labels": ids }),
+      )
+      .await?;
+    Ok(())
+  }
+
+  async fn create_pr_comment(&self, pr: u64, comment: &Comment, commit: &str) -> Result<()> {
+    let review_json = json!({
+      "event": "COMMENT",
+      "body": "",
+      "commit_id": commit,
+      "comments": [{
+        "path": comment.path,
+        "body": comment.body,
+        "new_position": comment.line,
+      }],
+    });
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::POST,
+        &self.route(&format!("/pulls/{pr}/reviews")),
+        &review_json,
+      )
+      .await
+      .with_context(|| format!("Failed to copy PR comment: {review_json:#?}"))?;
+    Ok(())
+  }
+
+  async fn create_issue(&self, title: &str, body: &str, labels: &[String]) -> Result<Issue> {
+    let ids = self.label_ids(labels).await?;
+    let issue = self
+      .client
+      .send::<GiteaIssue>(
+        Method::POST,
+        &self.route("/issues"),
+        &json!({
+          "title": title,
+          "body": body,
+          "labels": ids,
+        }),
+      )
+      .await?;
+    Ok(issue.into())
+  }
+
+  async fn close_issue(&self, issue: &Issue) -> Result<()> {
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::PATCH,
+        &self.route(&format!("/issues/{}", issue.number)),
+        &json!({ "state": "closed" }),
+      )
+      .await
+      .with_context(|| format!("Failed to close issue: {}", issue.number))?;
+    Ok(())
+  }
+
+  async fn merge_pr(&self, pr: &PullRequest) -> Result<()> {
+    self
+      .client
+      .send_unit(
+        Method::POST,
+        &self.route(&format!("/pulls/{}/merge", pr.number)),
+        Some(&json!({ "Do": "merge" })),
+      )
+      .await
+      .with_context(|| format!("Failed to merge PR: {}", pr.number))
+  }
+
+  async fn delete(&self) -> Result<()> {
+    self
+      .client
+      .send_unit(Method::DELETE, &self.route(""), None)
+      .await
+      .context("Failed to delete repo")
+  }
+}
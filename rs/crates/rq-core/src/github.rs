@@ -1,94 +1,208 @@
 use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
 use futures_util::future::try_join_all;
 use http::StatusCode;
 use octocrab::{
   issues::IssueHandler,
-  models::{
-    issues::Issue,
-    pulls::{self, PullRequest},
-    repos::Branch,
-    IssueState, Label,
-  },
+  models::{self, pulls, repos::Branch},
+  params,
   pulls::PullRequestHandler,
   repos::RepoHandler,
   GitHubError, Octocrab,
 };
 use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use specta::Type;
-use std::{env, fs, path::Path, sync::Arc, time::Duration};
+use std::{env, fs, path::PathBuf, sync::Arc, time::Duration};
 use tokio::{time::timeout, try_join};
-use tracing::warn;
 
 use crate::{
   command::command,
-  git::{GitRepo, MergeType},
-  package::QuestPackage,
-  utils,
+  forge::{
+    self, Comment, Forge, ForgeRepo, FullPullRequest, GitProtocol, Head, Issue, IssueState, Label,
+    PullRequest,
+  },
 };
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct FullPullRequest {
-  pub data: PullRequest,
-  pub comments: Vec<pulls::Comment>,
+impl From<models::Label> for Label {
+  fn from(label: models::Label) -> Self {
+    Label {
+      name: label.name,
+      color: label.color,
+      description: label.description,
+      default: label.default,
+    }
+  }
 }
 
-pub struct GithubRepo {
-  user: String,
-  name: String,
-  gh: Arc<Octocrab>,
-  prs: Mutex<Option<Vec<FullPullRequest>>>,
-  issues: Mutex<Option<Vec<Issue>>>,
+impl From<models::issues::Issue> for Issue {
+  fn from(issue: models::issues::Issue) -> Self {
+    Issue {
+      number: issue.number,
+      title: issue.title,
+      body: issue.body,
+      labels: issue.labels.into_iter().map(Label::from).collect(),
+      state: match issue.state {
+        models::IssueState::Closed => IssueState::Closed,
+        _ => IssueState::Open,
+      },
+      html_url: issue.html_url.to_string(),
+    }
+  }
 }
 
-#[derive(Debug)]
-pub enum PullSelector {
-  Branch(String),
-  Label(String),
+impl From<pulls::PullRequest> for PullRequest {
+  fn from(pr: pulls::PullRequest) -> Self {
+    PullRequest {
+      number: pr.number,
+      title: pr.title,
+      body: pr.body,
+      head: Head {
+        ref_field: pr.head.ref_field,
+      },
+      labels: pr
+        .labels
+        .unwrap_or_default()
+        .into_iter()
+        .map(Label::from)
+        .collect(),
+      html_url: pr.html_url.map(|url| url.to_string()).unwrap_or_default(),
+      merged_at: pr.merged_at.map(|time| time.to_rfc3339()),
+    }
+  }
 }
 
-pub fn find_pr<'a>(
-  selector: &PullSelector,
-  prs: impl IntoIterator<Item = &'a FullPullRequest> + 'a,
-) -> Option<usize> {
-  prs.into_iter().position(|pr| match selector {
-    PullSelector::Branch(branch) => &pr.data.head.ref_field == branch,
-    PullSelector::Label(label) => pr
-      .data
-      .labels
-      .as_ref()
-      .map(|labels| labels.iter().any(|l| &l.name == label))
-      .unwrap_or(false),
-  })
+impl From<pulls::Comment> for Comment {
+  fn from(comment: pulls::Comment) -> Self {
+    Comment {
+      path: comment.path,
+      body: comment.body,
+      line: comment.line,
+    }
+  }
 }
 
-pub fn find_issue<'a>(
-  label_name: &str,
-  issues: impl IntoIterator<Item = &'a Issue> + 'a,
-) -> Option<usize> {
-  issues
-    .into_iter()
-    .position(|issue| issue.labels.iter().any(|label| label.name == label_name))
+const GITHUB_COM: &str = "github.com";
+
+/// The Github instance to talk to: either github.com or a Github Enterprise Server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct GithubHost {
+  /// Hostname used for git remotes, e.g. `github.example.edu`.
+  pub host: String,
+
+  /// Base URL of the REST API. Defaults to `https://api.github.com` for github.com,
+  /// and `https://<host>/api/v3` for Enterprise servers.
+  pub api_base: Option<String>,
 }
 
-const RESET_LABEL: &str = "reset";
+impl Default for GithubHost {
+  fn default() -> Self {
+    GithubHost {
+      host: GITHUB_COM.into(),
+      api_base: None,
+    }
+  }
+}
 
-pub async fn load_user() -> Result<String> {
-  let user = octocrab::instance()
-    .current()
-    .user()
-    .await
-    .context("Failed to query Github connector for current user")?;
-  Ok(user.login)
+impl GithubHost {
+  pub fn new(host: impl Into<String>, api_base: Option<String>) -> Self {
+    GithubHost {
+      host: host.into(),
+      api_base,
+    }
+  }
+
+  fn config_path() -> Option<PathBuf> {
+    Some(home::home_dir()?.join(".rqst-host.toml"))
+  }
+
+  /// Loads the host from `RQST_GITHUB_HOST` and `RQST_GITHUB_API` if set,
+  /// then from `~/.rqst-host.toml`, and otherwise defaults to github.com.
+  pub fn load() -> Result<Self> {
+    if let Ok(host) = env::var("RQST_GITHUB_HOST") {
+      return Ok(GithubHost::new(host, env::var("RQST_GITHUB_API").ok()));
+    }
+
+    match Self::config_path() {
+      Some(path) if path.exists() => {
+        let contents = fs::read_to_string(&path)
+          .with_context(|| format!("Failed to read Github host from: {}", path.display()))?;
+        toml::from_str(&contents)
+          .with_context(|| format!("Failed to parse Github host from: {}", path.display()))
+      }
+      _ => Ok(GithubHost::default()),
+    }
+  }
+
+  /// Saves the host to `~/.rqst-host.toml` so it is used by later sessions.
+  pub fn save(&self) -> Result<()> {
+    let path = Self::config_path().context("Could not find home directory")?;
+    let contents = toml::to_string(self)?;
+    fs::write(&path, contents)
+      .with_context(|| format!("Failed to write Github host to: {}", path.display()))
+  }
+
+  pub fn is_github_com(&self) -> bool {
+    self.host == GITHUB_COM
+  }
+
+  pub fn api_base(&self) -> String {
+    match &self.api_base {
+      Some(base) => base.clone(),
+      None if self.is_github_com() => "https://api.github.com".into(),
+      None => format!("https://{}/api/v3", self.host),
+    }
+  }
+
+  pub fn remote(&self, user: &str, name: &str, protocol: GitProtocol) -> String {
+    match protocol {
+      GitProtocol::Https => format!("https://{}/{user}/{name}", self.host),
+      GitProtocol::Ssh => format!("git@{}:{user}/{name}.git", self.host),
+    }
+  }
 }
 
-pub enum GitProtocol {
-  Ssh,
-  Https,
+pub struct GithubForge {
+  gh: Arc<Octocrab>,
+  host: GithubHost,
 }
 
+impl GithubForge {
+  /// Uses the global Octocrab instance, which must be configured by [`init_octocrab`] for `host`.
+  pub fn new(host: GithubHost) -> Self {
+    GithubForge {
+      gh: octocrab::instance(),
+      host,
+    }
+  }
+
+  pub fn from_octocrab(gh: Octocrab, host: GithubHost) -> Self {
+    GithubForge {
+      gh: Arc::new(gh),
+      host,
+    }
+  }
+}
+
+impl Default for GithubForge {
+  fn default() -> Self {
+    Self::new(GithubHost::default())
+  }
+}
+
+pub struct GithubRepo {
+  user: String,
+  name: String,
+  gh: Arc<Octocrab>,
+  host: GithubHost,
+  prs: Mutex<Option<Vec<FullPullRequest>>>,
+  issues: Mutex<Option<Vec<Issue>>>,
+}
+
+/// Items per page when listing, the most that Github allows.
+const PAGE_SIZE: u8 = 100;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum TestRepoResult {
   HasContent,
@@ -96,71 +210,118 @@ pub enum TestRepoResult {
   NotFound,
 }
 
-impl GithubRepo {
-  pub fn new(user: &str, name: &str) -> Self {
-    GithubRepo {
-      user: user.to_string(),
-      name: name.to_string(),
-      gh: octocrab::instance(),
-      prs: Mutex::new(None),
-      issues: Mutex::new(None),
-    }
+fn is_not_found<T>(res: &Result<T, octocrab::Error>) -> bool {
+  matches!(
+    res,
+    Err(octocrab::Error::GitHub {
+      source: GitHubError {
+        status_code: StatusCode::NOT_FOUND,
+        ..
+      },
+      ..
+    })
+  )
+}
+
+#[async_trait]
+impl Forge for GithubForge {
+  async fn current_user(&self) -> Result<String> {
+    let user = self
+      .gh
+      .current()
+      .user()
+      .await
+      .context("Failed to query Github connector for current user")?;
+    Ok(user.login)
   }
 
-  pub async fn load(user: &str, name: &str) -> Result<Self> {
-    let repo = GithubRepo::new(user, name);
+  async fn load_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let repo = GithubRepo::new(&self.gh, &self.host, owner, name);
     ensure!(repo.fetch().await?, "Not found");
-    Ok(repo)
+    Ok(Box::new(repo))
   }
 
-  /// Returns true if repo
-  pub async fn fetch(&self) -> Result<bool> {
-    let (pr_handler, issue_handler) = (self.pr_handler(), self.issue_handler());
-    let res = try_join!(
-      pr_handler.list().state(octocrab::params::State::All).send(),
-      issue_handler
-        .list()
-        .state(octocrab::params::State::All)
-        .send()
-    );
-    let (mut pr_page, mut issue_page) = match res {
-      Ok(pages) => pages,
-      Err(octocrab::Error::GitHub {
-        source: GitHubError {
-          status_code: StatusCode::NOT_FOUND,
-          ..
-        },
-        ..
-      }) => return Ok(false),
-      Err(e) => return Err(e.into()),
-    };
-    let (prs, mut issues) = (pr_page.take_items(), issue_page.take_items());
+  async fn repo_exists(&self, owner: &str, name: &str) -> Result<bool> {
+    let res = self
+      .gh
+      .get::<serde_json::Value, _, ()>(format!("/repos/{owner}/{name}"), None)
+      .await;
+    if is_not_found(&res) {
+      return Ok(false);
+    }
+    res.with_context(|| format!("Failed to look up repo: {owner}/{name}"))?;
+    Ok(true)
+  }
 
-    let full_prs = try_join_all(prs.into_iter().map(|pr| async move {
-      let comment_pages = self
-        .pr_handler()
-        .list_comments(Some(pr.number))
-        .send()
-        .await
-        .with_context(|| format!("Failed to fetch comments for PR {}", pr.number))?;
-      let comments = comment_pages.into_iter().collect::<Vec<_>>();
-      Ok::<_, anyhow::Error>(FullPullRequest { data: pr, comments })
-    }))
-    .await?;
+  async fn create_repo(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let user = self.current_user().await.context("Failed to load user")?;
+    let params = json!({
+        "name": name,
+    });
+    self
+      .gh
+      .post::<_, serde_json::Value>("/user/repos", Some(&params))
+      .await
+      .context("Failed to create repo")?;
+    let repo = GithubRepo::new(&self.gh, &self.host, &user, name);
+    repo
+      .wait_for_content(TestRepoResult::NoContent)
+      .await
+      .context("Github repo was not properly initialized")?;
+    repo
+      .unsubscribe()
+      .await
+      .context("Failed to unsubscribe from repo")?;
+    Ok(Box::new(repo))
+  }
 
-    // Pull requests are considered issues, so filter them out
-    issues.retain(|issue| issue.pull_request.is_none());
+  async fn create_repo_from_template(
+    &self,
+    base: &dyn ForgeRepo,
+    name: &str,
+  ) -> Result<Box<dyn ForgeRepo>> {
+    let user = self.current_user().await?;
+    self
+      .gh
+      .repos(base.owner(), base.name())
+      .generate(name)
+      .owner(&user)
+      .private(true)
+      .send()
+      .await
+      .with_context(|| {
+        format!(
+          "Failed to clone template repo {}/{}",
+          base.owner(),
+          base.name()
+        )
+      })?;
+
+    let repo = GithubRepo::new(&self.gh, &self.host, &user, name);
+    repo
+      .wait_for_content(TestRepoResult::HasContent)
+      .await
+      .context("Github repo was not properly initialized")?;
 
-    *self.prs.lock() = Some(full_prs);
-    *self.issues.lock() = Some(issues);
+    // Unsubscribe from repo notifications to avoid annoying emails.
+    repo
+      .unsubscribe()
+      .await
+      .context("Failed to unsubscribe from repo")?;
 
-    Ok(true)
+    Ok(Box::new(repo))
   }
+}
 
-  pub fn remote(&self, protocol: GitProtocol) -> String {
-    match protocol {
-      GitProtocol::Https => format!("https://github.com/{}/{}", self.user, self.name),
-      GitProtocol::Ssh => format!("git@github.com:{}/{}.git", self.user, self.name),
+impl GithubRepo {
+  pub fn new(gh: &Arc<Octocrab>, host: &GithubHost, user: &str, name: &str) -> Self {
+    GithubRepo {
+      user: user.to_string(),
+      name: name.to_string(),
+      gh: Arc::clone(gh),
+      host: host.clone(),
+      prs: Mutex::new(None),
+      issues: Mutex::new(None),
     }
   }
 
@@ -197,11 +358,6 @@ impl GithubRepo {
     }
   }
 
-  pub fn clone(&self, path: &Path) -> Result<GitRepo> {
-    let remote = self.remote(GitProtocol::Ssh);
-    GitRepo::clone(&path.join(&self.name), &remote)
-  }
-
   // There is some unknown delay between creating a repo from a template and its contents being added.
   // We have to wait until that happens
   async fn wait_for_content(&self, expected: TestRepoResult) -> Result<()> {
@@ -225,20 +381,6 @@ impl GithubRepo {
     Ok(())
   }
 
-  async fn create_labels(&self, labels: &[Label]) -> Result<()> {
-    let issues = self.issue_handler();
-    try_join_all(labels.iter().filter(|label| !label.default).map(|label| {
-      issues.create_label(
-        &label.name,
-        &label.color,
-        label.description.as_deref().unwrap_or(""),
-      )
-    }))
-    .await
-    .context("Failed to create labels")?;
-    Ok(())
-  }
-
   async fn unsubscribe(&self) -> Result<()> {
     let route = format!("/repos/{}/{}/subscription", self.user, self.name);
     self
@@ -255,72 +397,7 @@ impl GithubRepo {
     Ok(())
   }
 
-  pub async fn instantiate_from_package(package: &QuestPackage) -> Result<GithubRepo> {
-    let user = load_user().await.context("Failed to load user")?;
-    let params = json!({
-        "name": &package.config.repo,
-    });
-    octocrab::instance()
-      .post::<_, serde_json::Value>("/user/repos", Some(&params))
-      .await
-      .context("Failed to create repo")?;
-    let repo = GithubRepo::new(&user, &package.config.repo);
-    repo
-      .wait_for_content(TestRepoResult::NoContent)
-      .await
-      .context("Github repo was not properly initialized")?;
-    repo
-      .unsubscribe()
-      .await
-      .context("Failed to unsubscribe from repo")?;
-    repo
-      .create_labels(&package.labels)
-      .await
-      .context("Failed to transfer package labels to repo")?;
-    Ok(repo)
-  }
-
-  pub async fn instantiate_from_repo(base: &GithubRepo) -> Result<GithubRepo> {
-    let user = load_user().await?;
-    let name = &base.name;
-    base
-      .repo_handler()
-      .generate(name)
-      .owner(&user)
-      .private(true)
-      .send()
-      .await
-      .with_context(|| format!("Failed to clone template repo {}/{}", base.user, base.name))?;
-
-    let repo = GithubRepo::new(&user, name);
-    repo
-      .wait_for_content(TestRepoResult::HasContent)
-      .await
-      .context("Github repo was not properly initialized")?;
-
-    // Unsubscribe from repo notifications to avoid annoying emails.
-    repo
-      .unsubscribe()
-      .await
-      .context("Failed to unsubscribe from repo")?;
-
-    // Copy all issue labels.
-    let mut page = base
-      .issue_handler()
-      .list_labels_for_repo()
-      .send()
-      .await
-      .context("Failed to fetch labels from upstream repo")?;
-    let labels = page.take_items();
-    repo
-      .create_labels(&labels)
-      .await
-      .context("Failed to transfer upstream labels to repo")?;
-
-    Ok(repo)
-  }
-
-  pub fn repo_handler(&self) -> RepoHandler {
+  pub fn repo_handler(&self) -> RepoHandler<'_> {
     self.gh.repos(&self.user, &self.name)
   }
 
@@ -335,116 +412,165 @@ impl GithubRepo {
     Ok(branches)
   }
 
-  pub fn pr_handler(&self) -> PullRequestHandler {
+  pub fn pr_handler(&self) -> PullRequestHandler<'_> {
     self.gh.pulls(&self.user, &self.name)
   }
 
-  pub fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>> {
-    MutexGuard::map(self.prs.lock(), |opt| {
-      opt.as_mut().expect("PRs not populated")
-    })
+  pub fn issue_handler(&self) -> IssueHandler<'_> {
+    self.gh.issues(&self.user, &self.name)
   }
+}
 
-  pub fn pr(&self, selector: &PullSelector) -> Option<MappedMutexGuard<'_, FullPullRequest>> {
-    let prs = self.prs();
-    let idx = find_pr(selector, prs.iter())?;
-    Some(MappedMutexGuard::map(prs, |prs| &mut prs[idx]))
+#[async_trait]
+impl ForgeRepo for GithubRepo {
+  fn owner(&self) -> &str {
+    &self.user
   }
 
-  pub fn issue_handler(&self) -> IssueHandler {
-    self.gh.issues(&self.user, &self.name)
+  fn name(&self) -> &str {
+    &self.name
   }
 
-  pub fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>> {
-    MutexGuard::map(self.issues.lock(), |opt| {
-      opt.as_mut().expect("Issues not populated")
-    })
+  fn remote(&self, protocol: GitProtocol) -> String {
+    self.host.remote(&self.user, &self.name, protocol)
   }
 
-  pub fn issue(&self, label_name: &str) -> Option<MappedMutexGuard<'_, Issue>> {
-    let issues = self.issues();
-    let idx = find_issue(label_name, issues.iter())?;
-    Some(MappedMutexGuard::map(issues, |issues| &mut issues[idx]))
-  }
+  async fn fetch(&self) -> Result<bool> {
+    let (pr_handler, issue_handler) = (self.pr_handler(), self.issue_handler());
+    let res = try_join!(
+      pr_handler
+        .list()
+        .state(params::State::All)
+        .per_page(PAGE_SIZE)
+        .send(),
+      issue_handler
+        .list()
+        .state(params::State::All)
+        .per_page(PAGE_SIZE)
+        .send()
+    );
+    if is_not_found(&res) {
+      return Ok(false);
+    }
+    let (pr_page, issue_page) = res?;
+    let (prs, mut issues) = try_join!(self.gh.all_pages(pr_page), self.gh.all_pages(issue_page))
+      .context("Failed to fetch PRs and issues")?;
 
-  pub async fn copy_pr(
-    &self,
-    pr: &FullPullRequest,
-    head: &str,
-    merge_type: MergeType,
-  ) -> Result<PullRequest> {
-    let pulls = self.pr_handler();
-    let mut body = pr
-      .data
-      .body
-      .as_ref()
-      .expect("Author error: PR missing body")
-      .clone();
+    let full_prs = try_join_all(prs.into_iter().map(|pr| async move {
+      let comment_page = self
+        .pr_handler()
+        .list_comments(Some(pr.number))
+        .per_page(PAGE_SIZE)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch comments for PR {}", pr.number))?;
+      let comments = self
+        .gh
+        .all_pages(comment_page)
+        .await
+        .with_context(|| format!("Failed to fetch comments for PR {}", pr.number))?
+        .into_iter()
+        .map(Comment::from)
+        .collect();
+      Ok::<_, anyhow::Error>(FullPullRequest {
+        data: pr.into(),
+        comments,
+      })
+    }))
+    .await?;
 
-    let is_reset = match merge_type {
-      MergeType::SolutionReset => {
-        body.push_str(r#"
+    // Pull requests are considered issues, so filter them out
+    issues.retain(|issue| issue.pull_request.is_none());
 
-Note: due to a merge conflict, this PR is a hard reset to the reference solution, and may have overwritten your previous changes."#);
-        true
-      }
+    *self.prs.lock() = Some(full_prs);
+    *self.issues.lock() = Some(issues.into_iter().map(Issue::from).collect());
 
-      MergeType::StarterReset => {
-        body.push_str(r#"
+    Ok(true)
+  }
 
-Note: due to a merge conflict, this PR is a hard reset to the starter code, and may have overwritten your previous changes."#);
-        true
-      }
+  async fn refresh(&self, prs: &[u64], issues: &[u64]) -> Result<()> {
+    let (pr_handler, issue_handler) = (&self.pr_handler(), &self.issue_handler());
+    let (prs, issues) = try_join!(
+      try_join_all(prs.iter().map(|&number| async move {
+        let res = pr_handler.get(number).await;
+        if is_not_found(&res) {
+          return Ok((number, None));
+        }
+        let pr = res.with_context(|| format!("Failed to fetch PR {number}"))?;
+        Ok::<_, anyhow::Error>((number, Some(pr.into())))
+      })),
+      try_join_all(issues.iter().map(|&number| async move {
+        let res = issue_handler.get(number).await;
+        if is_not_found(&res) {
+          return Ok((number, None));
+        }
+        let issue = res.with_context(|| format!("Failed to fetch issue {number}"))?;
+        Ok::<_, anyhow::Error>((number, Some(issue.into())))
+      }))
+    )?;
+    forge::refresh_prs(&self.prs, prs);
+    forge::refresh_issues(&self.issues, issues);
+    Ok(())
+  }
 
-      MergeType::Success => false,
-    };
-
-    let request = pulls
-      .create(
-        pr.data
-          .title
-          .as_ref()
-          .expect("Author error: PR missing title"),
-        &pr.data.head.ref_field,
-        "main", // don't copy base
-      )
-      .body(body);
-    let self_pr = request.send().await.context("Failed to create new PR")?;
-
-    // TODO: lots of parallelism below we should exploit
-
-    let mut labels = match &pr.data.labels {
-      Some(labels) => labels
-        .iter()
-        .map(|label| label.name.clone())
-        .collect::<Vec<_>>(),
-      None => Vec::new(),
-    };
-    if is_reset {
-      labels.push(RESET_LABEL.into());
-    }
-    self
+  fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>> {
+    MutexGuard::map(self.prs.lock(), |opt| {
+      opt.as_mut().expect("PRs not populated")
+    })
+  }
+
+  fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>> {
+    MutexGuard::map(self.issues.lock(), |opt| {
+      opt.as_mut().expect("Issues not populated")
+    })
+  }
+
+  async fn labels(&self) -> Result<Vec<Label>> {
+    let mut page = self
       .issue_handler()
-      .add_labels(self_pr.number, &labels)
+      .list_labels_for_repo()
+      .send()
       .await
-      .context("Failed to add labels to PR")?;
-
-    for comment in &pr.comments {
-      self
-        .copy_pr_comment(self_pr.number, comment, head)
-        .await
-        .context("Failed to add comment to PR")?;
-    }
+      .context("Failed to fetch labels")?;
+    Ok(page.take_items().into_iter().map(Label::from).collect())
+  }
 
-    Ok(self_pr)
+  async fn create_labels(&self, labels: &[Label]) -> Result<()> {
+    let issues = self.issue_handler();
+    try_join_all(labels.iter().filter(|label| !label.default).map(|label| {
+      issues.create_label(
+        &label.name,
+        &label.color,
+        label.description.as_deref().unwrap_or(""),
+      )
+    }))
+    .await
+    .context("Failed to create labels")?;
+    Ok(())
   }
 
-  pub async fn copy_pr_comment(
+  async fn create_pr(
     &self,
-    pr: u64,
-    comment: &pulls::Comment,
-    commit: &str,
-  ) -> Result<()> {
+    title: &str,
+    head: &str,
+    base: &str,
+    body: &str,
+  ) -> Result<PullRequest> {
+    let pr = self
+      .pr_handler()
+      .create(title, head, base)
+      .body(body)
+      .send()
+      .await?;
+    Ok(pr.into())
+  }
+
+  async fn add_pr_labels(&self, pr: u64, labels: &[String]) -> Result<()> {
+    self.issue_handler().add_labels(pr, labels).await?;
+    Ok(())
+  }
+
+  async fn create_pr_comment(&self, pr: u64, comment: &Comment, commit: &str) -> Result<()> {
     let route = format!("/repos/{}/{}/pulls/{pr}/comments", self.user, self.name);
     let comment_json = json!({
       "path": comment.path,
@@ -460,70 +586,29 @@ Note: due to a merge conflict, this PR is a hard reset to the starter code, and
     Ok(())
   }
 
-  fn process_issue_body(&self, body: &str) -> String {
-    let re = Regex::new(r"\{\{ (\S+) (\S+) \}\}").unwrap();
-    let mut new_body = body.to_string();
-    let substitutions = re.captures_iter(body).filter_map(|cap| {
-      let full_match = cap.get(0).unwrap();
-      let label = &cap[1];
-      let kind = &cap[2];
-      let number = match kind {
-        "pr" => {
-          let Some(pr) = self.pr(&PullSelector::Label(label.to_string())) else {
-            warn!("No PR with label {label}");
-            return None;
-          };
-          pr.data.number
-        }
-        "issue" => {
-          let Some(issue) = self.issue(label) else {
-            warn!("No issue with label {label}");
-            return None;
-          };
-          issue.number
-        }
-        _ => unimplemented!(),
-      };
-
-      Some((full_match.range(), format!("#{number}")))
-    });
-    utils::replace_many_ranges(&mut new_body, substitutions);
-
-    new_body
-  }
-
-  pub async fn copy_issue(&self, issue: &Issue) -> Result<Issue> {
-    let body = issue.body.as_ref().unwrap();
-    let body_processed = self.process_issue_body(body);
+  async fn create_issue(&self, title: &str, body: &str, labels: &[String]) -> Result<Issue> {
     let issue = self
       .issue_handler()
-      .create(&issue.title)
-      .body(body_processed)
-      .labels(
-        issue
-          .labels
-          .iter()
-          .map(|label| label.name.clone())
-          .collect::<Vec<_>>(),
-      )
+      .create(title)
+      .body(body)
+      .labels(labels.to_vec())
       .send()
-      .await
-      .with_context(|| format!("Failed to create issue: {}", issue.title))?;
-    Ok(issue)
+      .await?;
+    Ok(issue.into())
   }
 
-  pub async fn close_issue(&self, issue: &Issue) -> Result<()> {
+  async fn close_issue(&self, issue: &Issue) -> Result<()> {
     self
       .issue_handler()
       .update(issue.number)
-      .state(IssueState::Closed)
+      .state(models::IssueState::Closed)
       .send()
       .await
       .with_context(|| format!("Failed to close issue: {}", issue.number))?;
     Ok(())
   }
 
-  pub async fn merge_pr(&self, pr: &PullRequest) -> Result<()> {
+  async fn merge_pr(&self, pr: &PullRequest) -> Result<()> {
     self
       .pr_handler()
       .merge(pr.number)
@@ -533,7 +618,7 @@ Note: due to a merge conflict, this PR is a hard reset to the starter code, and
     Ok(())
   }
 
-  pub async fn delete(&self) -> Result<()> {
+  async fn delete(&self) -> Result<()> {
     self
       .repo_handler()
       .delete()
@@ -560,12 +645,17 @@ macro_rules! token_try {
   }};
 }
 
-fn read_github_token_from_fs() -> GithubToken {
+fn read_github_token_from_fs(host: &GithubHost) -> GithubToken {
   let home = match home::home_dir() {
     Some(dir) => dir,
     None => return GithubToken::NotFound,
   };
-  let path = home.join(".rqst-token");
+  let file_name = if host.is_github_com() {
+    ".rqst-token".to_string()
+  } else {
+    format!(".rqst-token-{}", host.host)
+  };
+  let path = home.join(file_name);
   if path.exists() {
     let token = token_try!(fs::read_to_string(path));
     GithubToken::Found(token.trim_end().to_string())
@@ -574,8 +664,13 @@ fn read_github_token_from_fs() -> GithubToken {
   }
 }
 
-fn generate_github_token_from_cli() -> GithubToken {
-  let res = command("gh auth token", &env::current_dir().unwrap()).output();
+fn generate_github_token_from_cli(host: &GithubHost) -> GithubToken {
+  let hostname = token_try!(shlex::try_quote(&host.host));
+  let res = command(
+    &format!("gh auth token --hostname {hostname}"),
+    &env::current_dir().unwrap(),
+  )
+  .output();
   match res {
     Ok(token_output) if token_output.status.success() => {
       let token = token_try!(String::from_utf8(token_output.stdout));
@@ -586,18 +681,196 @@ fn generate_github_token_from_cli() -> GithubToken {
   }
 }
 
-pub fn get_github_token() -> GithubToken {
-  match read_github_token_from_fs() {
-    GithubToken::NotFound => generate_github_token_from_cli(),
+pub fn get_github_token(host: &GithubHost) -> GithubToken {
+  match read_github_token_from_fs(host) {
+    GithubToken::NotFound => generate_github_token_from_cli(host),
     result => result,
   }
 }
 
-pub fn init_octocrab(token: &str) -> Result<()> {
+pub fn init_octocrab(token: &str, host: &GithubHost) -> Result<()> {
   let crab_inst = Octocrab::builder()
+    .base_uri(host.api_base())
+    .with_context(|| format!("Invalid Github API base: {}", host.api_base()))?
     .personal_token(token.to_string())
     .build()
     .context("Failed to build Github connector")?;
   octocrab::initialise(crab_inst);
   Ok(())
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    forge::{PullSelector, RESET_LABEL},
+    git::{git, git_output, GitRepo, MergeType},
+    mock_github::TEMPLATE_REPO,
+    replay::Cassette,
+  };
+  use regex::Regex;
+
+  /// Copies `main` and `branch` of `upstream` to `origin`, so a PR from `branch` can be opened
+  /// there. Returns the head commit of `branch`.
+  fn push_branch(upstream: &dyn ForgeRepo, origin: &dyn ForgeRepo, branch: &str) -> Result<String> {
+    let dir = tempfile::tempdir()?;
+    let repo = GitRepo::new(dir.path());
+    let (upstream, origin) = (
+      upstream.remote(GitProtocol::Ssh),
+      origin.remote(GitProtocol::Ssh),
+    );
+    git!(repo, "init")?;
+    git!(
+      repo,
+      "fetch {upstream} +main:refs/upstream/main +{branch}:refs/upstream/{branch}"
+    )?;
+    git!(
+      repo,
+      "push --force {origin} refs/upstream/main:refs/heads/main refs/upstream/{branch}:refs/heads/{branch}"
+    )?;
+    let head = git_output!(repo, "rev-parse refs/upstream/{branch}")?;
+    Ok(head.trim_end().to_string())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn fetch() -> Result<()> {
+    let cassette = Cassette::load("fetch").await?;
+    let forge = cassette.forge()?;
+    let upstream = forge
+      .load_repo(&cassette.template_owner()?, TEMPLATE_REPO)
+      .await?;
+
+    {
+      let prs = upstream.prs();
+      assert!(!prs.is_empty());
+      assert!(prs.iter().all(|pr| !pr.data.labels.is_empty()));
+      let comment = prs.iter().flat_map(|pr| &pr.comments).next().unwrap();
+      assert!(comment.line.is_some());
+    }
+    let issues = upstream.issues();
+    assert!(!issues.is_empty());
+    assert!(issues.iter().all(|issue| issue.body.is_some()));
+    drop(issues);
+
+    cassette.finish()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn wait_for_content() -> Result<()> {
+    const NAME: &str = "rqst-replay-empty";
+    let cassette = Cassette::load("wait_for_content").await?;
+    let forge = cassette.forge()?;
+    let user = forge.current_user().await?;
+    let repo = GithubRepo::new(&forge.gh, &forge.host, &user, NAME);
+    assert_eq!(repo.test_repo().await?, TestRepoResult::NotFound);
+
+    let origin = forge.create_repo(NAME).await?;
+    assert_eq!(repo.test_repo().await?, TestRepoResult::NoContent);
+
+    origin.delete().await?;
+    assert_eq!(repo.test_repo().await?, TestRepoResult::NotFound);
+
+    cassette.finish()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn instantiate_from_repo() -> Result<()> {
+    let cassette = Cassette::load("instantiate_from_repo").await?;
+    let forge = cassette.forge()?;
+    let upstream = forge
+      .load_repo(&cassette.template_owner()?, TEMPLATE_REPO)
+      .await?;
+
+    let origin = forge
+      .create_repo_from_template(&*upstream, TEMPLATE_REPO)
+      .await?;
+    let labels = upstream.labels().await?;
+    origin.create_labels(&labels).await?;
+
+    let origin_labels = origin.labels().await?;
+    for label in labels.iter().filter(|label| !label.default) {
+      assert!(origin_labels.iter().any(|other| other.name == label.name));
+    }
+
+    origin.delete().await?;
+    cassette.finish()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn copy_pr() -> Result<()> {
+    let cassette = Cassette::load("copy_pr").await?;
+    let forge = cassette.forge()?;
+    let upstream = forge
+      .load_repo(&cassette.template_owner()?, TEMPLATE_REPO)
+      .await?;
+    let origin = forge
+      .create_repo_from_template(&*upstream, TEMPLATE_REPO)
+      .await?;
+    origin.create_labels(&upstream.labels().await?).await?;
+
+    let pr = upstream
+      .prs()
+      .iter()
+      .find(|pr| !pr.comments.is_empty())
+      .cloned()
+      .unwrap();
+    let branch = &pr.data.head.ref_field;
+    let head = cassette.var("head", || push_branch(&*upstream, &*origin, branch))?;
+    let new_pr = origin
+      .copy_pr(&(&pr).into(), &head, MergeType::SolutionReset)
+      .await?;
+
+    origin.fetch().await?;
+    let copied = origin.pr(&PullSelector::Branch(branch.clone())).unwrap();
+    assert_eq!(copied.data.number, new_pr.number);
+    assert!(copied
+      .data
+      .labels
+      .iter()
+      .any(|label| label.name == RESET_LABEL));
+    assert_eq!(copied.comments.len(), pr.comments.len());
+    drop(copied);
+
+    origin.delete().await?;
+    cassette.finish()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn copy_issue() -> Result<()> {
+    let cassette = Cassette::load("copy_issue").await?;
+    let forge = cassette.forge()?;
+    let upstream = forge
+      .load_repo(&cassette.template_owner()?, TEMPLATE_REPO)
+      .await?;
+    let origin = forge
+      .create_repo_from_template(&*upstream, TEMPLATE_REPO)
+      .await?;
+    origin.create_labels(&upstream.labels().await?).await?;
+
+    // Copy the PR that the issue refers to, so the reference can be filled in.
+    let re = Regex::new(r"\{\{ (\S+) pr \}\}").unwrap();
+    let (issue, label) = upstream
+      .issues()
+      .iter()
+      .find_map(|issue| {
+        let cap = re.captures(issue.body.as_deref()?)?;
+        Some((issue.clone(), cap[1].to_string()))
+      })
+      .unwrap();
+    let pr = upstream.pr(&PullSelector::Label(label)).unwrap().clone();
+    let branch = &pr.data.head.ref_field;
+    let head = cassette.var("head", || push_branch(&*upstream, &*origin, branch))?;
+    let new_pr = origin
+      .copy_pr(&(&pr).into(), &head, MergeType::Success)
+      .await?;
+
+    origin.fetch().await?;
+    let new_issue = origin.copy_issue(&(&issue).into()).await?;
+    let body = new_issue.body.unwrap();
+    assert!(body.contains(&origin.pr_reference(new_pr.number)));
+    assert!(!re.is_match(&body));
+
+    origin.delete().await?;
+    cassette.finish()
+  }
+}
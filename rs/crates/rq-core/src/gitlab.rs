@@ -0,0 +1,645 @@
+//! Forge backed by the GitLab REST API. PRs are GitLab merge requests.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use futures_util::future::try_join_all;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{time::timeout, try_join};
+
+use crate::{
+  forge::{
+    self, Comment, Forge, ForgeRepo, FullPullRequest, GitProtocol, Head, Issue, IssueState, Label,
+    PullRequest,
+  },
+  rest::RestClient,
+};
+
+#[derive(Deserialize)]
+struct GitlabUser {
+  username: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabNamespace {
+  full_path: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabProject {
+  id: u64,
+  path: String,
+  namespace: GitlabNamespace,
+  http_url_to_repo: String,
+  ssh_url_to_repo: String,
+  import_status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabLabel {
+  name: String,
+  color: String,
+  description: Option<String>,
+}
+
+impl From<GitlabLabel> for Label {
+  fn from(label: GitlabLabel) -> Self {
+    Label {
+      name: label.name,
+      color: label.color.trim_start_matches('#').to_string(),
+      description: label.description.filter(|desc| !desc.is_empty()),
+      default: false,
+    }
+  }
+}
+
+/// Labels are only returned in full when a list is requested `with_labels_details`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GitlabLabelRef {
+  Detailed(GitlabLabel),
+  Name(String),
+}
+
+impl From<GitlabLabelRef> for Label {
+  fn from(label: GitlabLabelRef) -> Self {
+    match label {
+      GitlabLabelRef::Detailed(label) => label.into(),
+      GitlabLabelRef::Name(name) => Label {
+        name,
+        color: String::new(),
+        description: None,
+        default: false,
+      },
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GitlabIssue {
+  iid: u64,
+  title: String,
+  description: Option<String>,
+  labels: Vec<GitlabLabelRef>,
+  state: String,
+  web_url: String,
+}
+
+impl From<GitlabIssue> for Issue {
+  fn from(issue: GitlabIssue) -> Self {
+    Issue {
+      number: issue.iid,
+      title: issue.title,
+      body: issue.description,
+      labels: issue.labels.into_iter().map(Label::from).collect(),
+      state: if issue.state == "closed" {
+        IssueState::Closed
+      } else {
+        IssueState::Open
+      },
+      html_url: issue.web_url,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequest {
+  iid: u64,
+  title: Option<String>,
+  description: Option<String>,
+  source_branch: String,
+  labels: Vec<GitlabLabelRef>,
+  web_url: String,
+  state: String,
+  merged_at: Option<String>,
+  updated_at: Option<String>,
+}
+
+impl From<GitlabMergeRequest> for PullRequest {
+  fn from(mr: GitlabMergeRequest) -> Self {
+    // Older GitLab versions only report the merge through `state`.
+    let merged_at = match mr.merged_at {
+      Some(merged_at) => Some(merged_at),
+      None if mr.state == "merged" => mr.updated_at.or_else(|| Some(String::new())),
+      None => None,
+    };
+    PullRequest {
+      number: mr.iid,
+      title: mr.title,
+      body: mr.description,
+      head: Head {
+        ref_field: mr.source_branch,
+      },
+      labels: mr.labels.into_iter().map(Label::from).collect(),
+      html_url: mr.web_url,
+      merged_at,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct GitlabDiffRefs {
+  base_sha: String,
+  head_sha: String,
+  start_sha: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequestDiff {
+  diff_refs: Option<GitlabDiffRefs>,
+}
+
+#[derive(Deserialize)]
+struct GitlabPosition {
+  new_path: String,
+  new_line: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct GitlabNote {
+  body: String,
+  position: Option<GitlabPosition>,
+}
+
+#[derive(Deserialize)]
+struct GitlabDiscussion {
+  notes: Vec<GitlabNote>,
+}
+
+/// URL-encodes a project path so it can be used in place of a project id.
+fn project_path(owner: &str, name: &str) -> String {
+  format!("{owner}/{name}").replace('/', "%2F")
+}
+
+pub struct GitlabForge {
+  client: Arc<RestClient>,
+}
+
+impl GitlabForge {
+  /// Connects to the GitLab instance at `url`, e.g. `https://gitlab.com`.
+  pub fn new(url: &str, token: &str) -> Result<Self> {
+    let base = format!("{}/api/v4", url.trim_end_matches('/'));
+    let client = RestClient::new(&base, "PRIVATE-TOKEN", token)?.with_page_size_param("per_page");
+    Ok(GitlabForge {
+      client: Arc::new(client),
+    })
+  }
+
+  async fn project(&self, id: &str) -> Result<Option<GitlabProject>> {
+    self
+      .client
+      .json::<GitlabProject>(Method::GET, &format!("/projects/{id}"), None)
+      .await
+  }
+
+  fn repo(&self, project: GitlabProject) -> GitlabRepo {
+    GitlabRepo {
+      client: Arc::clone(&self.client),
+      id: project.id,
+      owner: project.namespace.full_path,
+      name: project.path,
+      http_url: project.http_url_to_repo,
+      ssh_url: project.ssh_url_to_repo,
+      prs: Mutex::new(None),
+      issues: Mutex::new(None),
+    }
+  }
+
+  async fn init_repo(&self, project: GitlabProject) -> Result<Box<dyn ForgeRepo>> {
+    let repo = self.repo(project);
+
+    // Creators automatically watch new projects, so disable notifications to avoid annoying emails.
+    repo
+      .client
+      .send_unit(
+        Method::PUT,
+        &repo.route("/notification_settings"),
+        Some(&json!({ "level": "disabled" })),
+      )
+      .await
+      .context("Failed to disable notifications for project")?;
+
+    repo.fetch().await?;
+    Ok(Box::new(repo))
+  }
+
+  // Forks are imported in the background, so wait until the contents have been copied.
+  async fn wait_for_import(&self, id: u64) -> Result<()> {
+    const RETRY_INTERVAL: u64 = 500;
+    const RETRY_TIMEOUT: u64 = 30000;
+
+    let strategy = tokio_retry::strategy::FixedInterval::from_millis(RETRY_INTERVAL);
+    let import_status = tokio_retry::Retry::spawn(strategy, || async {
+      let status = self
+        .project(&id.to_string())
+        .await
+        .map(|project| project.and_then(|project| project.import_status));
+      match status {
+        Ok(Some(status)) if status == "finished" || status == "failed" => Ok(status),
+        result => {
+          tracing::debug!("import status: {result:?}");
+          Err(())
+        }
+      }
+    });
+    let import_status = timeout(Duration::from_millis(RETRY_TIMEOUT), import_status)
+      .await
+      .context("Fork is still importing after timeout")?;
+    ensure!(
+      import_status.as_deref() == Ok("finished"),
+      "Failed to import fork"
+    );
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl Forge for GitlabForge {
+  async fn current_user(&self) -> Result<String> {
+    let user = self
+      .client
+      .get::<GitlabUser>("/user")
+      .await
+      .context("Failed to query GitLab for current user")?;
+    Ok(user.username)
+  }
+
+  async fn load_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let project = self.project(&project_path(owner, name)).await?;
+    let project = project.with_context(|| format!("Not found: {owner}/{name}"))?;
+    let repo = self.repo(project);
+    ensure!(repo.fetch().await?, "Not found");
+    Ok(Box::new(repo))
+  }
+
+  async fn repo_exists(&self, owner: &str, name: &str) -> Result<bool> {
+    Ok(self.project(&project_path(owner, name)).await?.is_some())
+  }
+
+  async fn create_repo(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let project = self
+      .client
+      .send::<GitlabProject>(
+        Method::POST,
+        "/projects",
+        &json!({
+          "name": name,
+          "path": name,
+          "visibility": "private",
+        }),
+      )
+      .await
+      .context("Failed to create project")?;
+    self.init_repo(project).await
+  }
+
+  async fn create_repo_from_template(
+    &self,
+    base: &dyn ForgeRepo,
+    name: &str,
+  ) -> Result<Box<dyn ForgeRepo>> {
+    let base_id = project_path(base.owner(), base.name());
+
+    // GitLab has no template repos, so fork only the main branch and then sever the fork
+    // relationship, which would otherwise make new MRs target the base project.
+    let fork = self
+      .client
+      .send::<GitlabProject>(
+        Method::POST,
+        &format!("/projects/{base_id}/fork"),
+        &json!({
+          "name": name,
+          "path": name,
+          "visibility": "private",
+          "branches": "main",
+        }),
+      )
+      .await
+      .with_context(|| {
+        format!(
+          "Failed to fork template project {}/{}",
+          base.owner(),
+          base.name()
+        )
+      })?;
+    self.wait_for_import(fork.id).await?;
+    self
+      .client
+      .send_unit(Method::DELETE, &format!("/projects/{}/fork", fork.id), None)
+      .await
+      .context("Failed to remove fork relationship")?;
+
+    self.init_repo(fork).await
+  }
+}
+
+pub struct GitlabRepo {
+  client: Arc<RestClient>,
+  id: u64,
+  owner: String,
+  name: String,
+  http_url: String,
+  ssh_url: String,
+  prs: Mutex<Option<Vec<FullPullRequest>>>,
+  issues: Mutex<Option<Vec<Issue>>>,
+}
+
+impl GitlabRepo {
+  fn route(&self, suffix: &str) -> String {
+    format!("/projects/{}{suffix}", self.id)
+  }
+
+  async fn list_merge_requests(&self) -> Result<Option<Vec<GitlabMergeRequest>>> {
+    self
+      .client
+      .get_all(&self.route("/merge_requests?state=all&with_labels_details=true"))
+      .await
+  }
+
+  async fn list_issues(&self) -> Result<Option<Vec<GitlabIssue>>> {
+    self
+      .client
+      .get_all(&self.route("/issues?state=all&with_labels_details=true"))
+      .await
+  }
+
+  /// Review comments are the diff notes of an MR's discussions.
+  async fn mr_comments(&self, iid: u64) -> Result<Vec<Comment>> {
+    let discussions = self
+      .client
+      .get_all::<GitlabDiscussion>(&self.route(&format!("/merge_requests/{iid}/discussions")))
+      .await?
+      .unwrap_or_default();
+    Ok(
+      discussions
+        .into_iter()
+        .flat_map(|discussion| discussion.notes)
+        .filter_map(|note| {
+          let position = note.position?;
+          Some(Comment {
+            path: position.new_path,
+            body: note.body,
+            line: position.new_line,
+          })
+        })
+        .collect(),
+    )
+  }
+
+  // GitLab computes an MR's diff in the background after it is created, and comments
+  // can't be positioned on the diff until that finishes.
+  async fn diff_refs(&self, iid: u64) -> Result<GitlabDiffRefs> {
+    const RETRY_INTERVAL: u64 = 500;
+    const RETRY_TIMEOUT: u64 = 10000;
+
+    let route = self.route(&format!("/merge_requests/{iid}"));
+    let strategy = tokio_retry::strategy::FixedInterval::from_millis(RETRY_INTERVAL);
+    let diff_refs = tokio_retry::Retry::spawn(strategy, || async {
+      match self.client.get::<GitlabMergeRequestDiff>(&route).await {
+        Ok(GitlabMergeRequestDiff {
+          diff_refs: Some(diff_refs),
+        }) => Ok(diff_refs),
+        result => {
+          tracing::debug!("diff refs missing: {:?}", result.err());
+          Err(())
+        }
+      }
+    });
+    timeout(Duration::from_millis(RETRY_TIMEOUT), diff_refs)
+      .await
+      .ok()
+      .and_then(Result::ok)
+      .with_context(|| format!("MR {iid} still has no diff after timeout"))
+  }
+}
+
+#[async_trait]
+impl ForgeRepo for GitlabRepo {
+  fn owner(&self) -> &str {
+    &self.owner
+  }
+
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn remote(&self, protocol: GitProtocol) -> String {
+    match protocol {
+      GitProtocol::Ssh => self.ssh_url.clone(),
+      GitProtocol::Https => self.http_url.clone(),
+    }
+  }
+
+  async fn fetch(&self) -> Result<bool> {
+    let (mrs, issues) = try_join!(self.list_merge_requests(), self.list_issues())?;
+    let (Some(mrs), Some(issues)) = (mrs, issues) else {
+      return Ok(false);
+    };
+
+    let full_prs = try_join_all(mrs.into_iter().map(|mr| async move {
+      let comments = self
+        .mr_comments(mr.iid)
+        .await
+        .with_context(|| format!("Failed to fetch discussions for MR {}", mr.iid))?;
+      Ok::<_, anyhow::Error>(FullPullRequest {
+        data: mr.into(),
+        comments,
+      })
+    }))
+    .await?;
+
+    *self.prs.lock() = Some(full_prs);
+    *self.issues.lock() = Some(issues.into_iter().map(Issue::from).collect());
+
+    Ok(true)
+  }
+
+  async fn refresh(&self, prs: &[u64], issues: &[u64]) -> Result<()> {
+    let (prs, issues) = try_join!(
+      try_join_all(prs.iter().map(|&number| async move {
+        let route = self.route(&format!("/merge_requests/{number}"));
+        let pr = self
+          .client
+          .json::<GitlabMergeRequest>(Method::GET, &route, None)
+          .await?;
+        Ok::<_, anyhow::Error>((number, pr.map(PullRequest::from)))
+      })),
+      try_join_all(issues.iter().map(|&number| async move {
+        let route = self.route(&format!("/issues/{number}"));
+        let issue = self
+          .client
+          .json::<GitlabIssue>(Method::GET, &route, None)
+          .await?;
+        Ok::<_, anyhow::Error>((number, issue.map(Issue::from)))
+      }))
+    )?;
+    forge::refresh_prs(&self.prs, prs);
+    forge::refresh_issues(&self.issues, issues);
+    Ok(())
+  }
+
+  fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>> {
+    MutexGuard::map(self.prs.lock(), |opt| {
+      opt.as_mut().expect("PRs not populated")
+    })
+  }
+
+  fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>> {
+    MutexGuard::map(self.issues.lock(), |opt| {
+      opt.as_mut().expect("Issues not populated")
+    })
+  }
+
+  async fn labels(&self) -> Result<Vec<Label>> {
+    let labels = self
+      .client
+      .get_all::<GitlabLabel>(&self.route("/labels"))
+      .await?
+      .context("Failed to fetch labels")?;
+    Ok(labels.into_iter().map(Label::from).collect())
+  }
+
+  async fn create_labels(&self, labels: &[Label]) -> Result<()> {
+    // Forks inherit the labels of their base project.
+    let existing = self.labels().await?;
+    let route = self.route("/labels");
+    let missing = labels
+      .iter()
+      .filter(|label| !label.default && !existing.iter().any(|l| l.name == label.name));
+    try_join_all(missing.map(|label| {
+      let body = json!({
+        "name": label.name,
+        "color": format!("#{}", label.color),
+        "description": label.description.as_deref().unwrap_or(""),
+      });
+      let route = &route;
+      async move {
+        self
+          .client
+          .send::<serde_json::Value>(Method::POST, route, &body)
+          .await
+      }
+    }))
+    .await
+    .context("Failed to create labels")?;
+    Ok(())
+  }
+
+  async fn create_pr(
+    &self,
+    title: &str,
+    head: &str,
+    base: &str,
+    body: &str,
+  ) -> Result<PullRequest> {
+    let mr = self
+      .client
+      .send::<GitlabMergeRequest>(
+        Method::POST,
+        &self.route("/merge_requests"),
+        &json!({
+          "title": title,
+          "source_branch": head,
+          "target_branch": base,
+          "description": body,
+        }),
+      )
+      .await?;
+    Ok(mr.into())
+  }
+
+  async fn add_pr_labels(&self, pr: u64, labels: &[String]) -> Result<()> {
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::PUT,
+        &self.route(&format!("/merge_requests/{pr}")),
+        &json!({ "add_labels": labels.join(",") }),
+      )
+      .await?;
+    Ok(())
+  }
+
+  async fn create_pr_comment(&self, pr: u64, comment: &Comment, _commit: &str) -> Result<()> {
+    let diff_refs = self.diff_refs(pr).await?;
+    let discussion_json = json!({
+      "body": comment.body,
+      "position": {
+        "position_type": "text",
+        "base_sha": diff_refs.base_sha,
+        "start_sha": diff_refs.start_sha,
+        "head_sha": diff_refs.head_sha,
+        "old_path": comment.path,
+        "new_path": comment.path,
+        "new_line": comment.line,
+      },
+    });
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::POST,
+        &self.route(&format!("/merge_requests/{pr}/discussions")),
+        &discussion_json,
+      )
+      .await
+      .with_context(|| format!("Failed to copy MR comment: {discussion_json:#?}"))?;
+    Ok(())
+  }
+
+  async fn create_issue(&self, title: &str, body: &str, labels: &[String]) -> Result<Issue> {
+    let issue = self
+      .client
+      .send::<GitlabIssue>(
+        Method::POST,
+        &self.route("/issues"),
+        &json!({
+          "title": title,
+          "description": body,
+          "labels": labels.join(","),
+        }),
+      )
+      .await?;
+    Ok(issue.into())
+  }
+
+  async fn close_issue(&self, issue: &Issue) -> Result<()> {
+    self
+      .client
+      .send::<serde_json::Value>(
+        Method::PUT,
+        &self.route(&format!("/issues/{}", issue.number)),
+        &json!({ "state_event": "close" }),
+      )
+      .await
+      .with_context(|| format!("Failed to close issue: {}", issue.number))?;
+    Ok(())
+  }
+
+  async fn merge_pr(&self, pr: &PullRequest) -> Result<()> {
+    self
+      .client
+      .send_unit(
+        Method::PUT,
+        &self.route(&format!("/merge_requests/{}/merge", pr.number)),
+        None,
+      )
+      .await
+      .with_context(|| format!("Failed to merge MR: {}", pr.number))
+  }
+
+  async fn delete(&self) -> Result<()> {
+    self
+      .client
+      .send_unit(Method::DELETE, &self.route(""), None)
+      .await
+      .context("Failed to delete project")
+  }
+
+  fn pr_reference(&self, number: u64) -> String {
+    format!("!{number}")
+  }
+}
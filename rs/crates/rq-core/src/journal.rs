@@ -0,0 +1,278 @@
+//! A record of the actions a [`Quest`](crate::quest::Quest) has taken in the learner's repo.
+//!
+//! The journal is stored in `.git/rqst/journal.json`, so it stays with the learner's clone
+//! and is never committed. It identifies which issues and PRs belong to the quest, while
+//! whether they are merged or closed still comes from the forge.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{git::GitRepo, stage::StagePart};
+
+/// Version of the journal file format, bumped on incompatible changes.
+pub const JOURNAL_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum JournalEvent {
+  IssueFiled {
+    stage: String,
+    issue: u64,
+  },
+  StarterFiled {
+    stage: String,
+    pr: u64,
+  },
+  SolutionFiled {
+    stage: String,
+    pr: u64,
+  },
+  /// The learner skipped to `stage` by resetting to the solution of the stage before it.
+  Skipped {
+    stage: String,
+  },
+  /// A PR was filed or later reset as a hard reset, overwriting the learner's changes.
+  Reset {
+    stage: String,
+    part: StagePart,
+  },
+  /// A PR was filed with conflicts in `files`, because the learner's changes conflicted with it.
+  Conflicted {
+    stage: String,
+    part: StagePart,
+    files: Vec<String>,
+  },
+  /// The learner's code was saved to `branch` before RepoQuest overwrote it.
+  BackedUp {
+    stage: String,
+    branch: String,
+  },
+  /// The learner started `stage` with `commit` at the head of main.
+  StageStarted {
+    stage: String,
+    commit: String,
+  },
+  /// The learner rewound to `stage`, discarding the issues and PRs of `dropped`.
+  Rewound {
+    stage: String,
+    dropped: Vec<String>,
+    /// The discarded issues and PRs, to close and label as rewound on the forge.
+    #[serde(default)]
+    issues: Vec<u64>,
+    #[serde(default)]
+    prs: Vec<u64>,
+  },
+  /// The issues and PRs discarded by every earlier rewind were closed and labeled.
+  RewindCleanedUp,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+  /// When the event was recorded, in RFC 3339 format.
+  pub time: String,
+  pub event: JournalEvent,
+}
+
+/// The issue and PRs most recently filed for a stage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StageRecord {
+  pub issue: Option<u64>,
+  pub starter_pr: Option<u64>,
+  pub solution_pr: Option<u64>,
+  /// Whether the starter PR was filed with unresolved conflicts and not since reset.
+  pub starter_conflict: bool,
+  pub solution_conflict: bool,
+}
+
+impl StageRecord {
+  fn conflict_mut(&mut self, part: StagePart) -> &mut bool {
+    match part {
+      StagePart::Starter => &mut self.starter_conflict,
+      StagePart::Solution => &mut self.solution_conflict,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalFile {
+  version: u32,
+  entries: Vec<JournalEntry>,
+}
+
+pub struct Journal {
+  path: PathBuf,
+  entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+  fn path(repo: &GitRepo) -> Result<PathBuf> {
+    Ok(repo.git_dir()?.join("rqst").join("journal.json"))
+  }
+
+  /// Loads the journal of `repo`, or returns `None` if it has none yet.
+  pub fn load(repo: &GitRepo) -> Result<Option<Self>> {
+    let path = Self::path(repo)?;
+    if !path.exists() {
+      return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+      .with_context(|| format!("Failed to read quest journal: {}", path.display()))?;
+    let file = serde_json::from_str::<JournalFile>(&contents)
+      .with_context(|| format!("Failed to parse quest journal: {}", path.display()))?;
+    ensure!(
+      file.version <= JOURNAL_VERSION,
+      "Quest journal has version {}, but this version of RepoQuest only supports up to {JOURNAL_VERSION}",
+      file.version
+    );
+    Ok(Some(Journal {
+      path,
+      entries: file.entries,
+    }))
+  }
+
+  /// An empty journal for `repo`, which is written on the first [`Journal::record`].
+  pub fn new(repo: &GitRepo) -> Result<Self> {
+    Ok(Journal {
+      path: Self::path(repo)?,
+      entries: Vec::new(),
+    })
+  }
+
+  pub fn entries(&self) -> &[JournalEntry] {
+    &self.entries
+  }
+
+  /// Appends `events` and saves the journal.
+  pub fn record(&mut self, events: impl IntoIterator<Item = JournalEvent>) -> Result<()> {
+    let time = chrono::Utc::now().to_rfc3339();
+    self
+      .entries
+      .extend(events.into_iter().map(|event| JournalEntry {
+        time: time.clone(),
+        event,
+      }));
+    self.save()
+  }
+
+  fn save(&self) -> Result<()> {
+    let dir = self.path.parent().unwrap();
+    fs::create_dir_all(dir)
+      .with_context(|| format!("Failed to create journal directory: {}", dir.display()))?;
+    let file = JournalFile {
+      version: JOURNAL_VERSION,
+      entries: self.entries.clone(),
+    };
+    fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+      .with_context(|| format!("Failed to write quest journal: {}", self.path.display()))
+  }
+
+  /// The latest issue and PRs filed for each stage, keyed by stage label.
+  pub fn stage_records(&self) -> HashMap<String, StageRecord> {
+    let mut records = HashMap::<String, StageRecord>::new();
+    for entry in &self.entries {
+      match &entry.event {
+        JournalEvent::IssueFiled { stage, issue } => {
+          records.entry(stage.clone()).or_default().issue = Some(*issue);
+        }
+        JournalEvent::StarterFiled { stage, pr } => {
+          let record = records.entry(stage.clone()).or_default();
+          record.starter_pr = Some(*pr);
+          record.starter_conflict = false;
+        }
+        JournalEvent::SolutionFiled { stage, pr } => {
+          let record = records.entry(stage.clone()).or_default();
+          record.solution_pr = Some(*pr);
+          record.solution_conflict = false;
+        }
+        JournalEvent::Conflicted { stage, part, .. } => {
+          *records
+            .entry(stage.clone())
+            .or_default()
+            .conflict_mut(*part) = true;
+        }
+        JournalEvent::Reset { stage, part } => {
+          *records
+            .entry(stage.clone())
+            .or_default()
+            .conflict_mut(*part) = false;
+        }
+        JournalEvent::Rewound { dropped, .. } => {
+          for stage in dropped {
+            records.remove(stage);
+          }
+        }
+        JournalEvent::Skipped { .. }
+        | JournalEvent::StageStarted { .. }
+        | JournalEvent::BackedUp { .. }
+        | JournalEvent::RewindCleanedUp => {}
+      }
+    }
+    records
+  }
+
+  /// Every backup branch made during the quest, keyed by stage label.
+  pub fn backups(&self) -> HashMap<String, Vec<String>> {
+    let mut backups = HashMap::<String, Vec<String>>::new();
+    for entry in &self.entries {
+      if let JournalEvent::BackedUp { stage, branch } = &entry.event {
+        backups
+          .entry(stage.clone())
+          .or_default()
+          .push(branch.clone());
+      }
+    }
+    backups
+  }
+
+  /// The issues and PRs discarded by rewinds that haven't been cleaned up on the forge yet.
+  pub fn rewind_cleanup(&self) -> (Vec<u64>, Vec<u64>) {
+    let (mut issues, mut prs) = (Vec::new(), Vec::new());
+    for entry in &self.entries {
+      match &entry.event {
+        JournalEvent::Rewound {
+          issues: i, prs: p, ..
+        } => {
+          issues.extend(i);
+          prs.extend(p);
+        }
+        JournalEvent::RewindCleanedUp => {
+          issues.clear();
+          prs.clear();
+        }
+        _ => {}
+      }
+    }
+    (issues, prs)
+  }
+
+  /// The head of main when the learner last started `stage`, if recorded.
+  pub fn stage_start(&self, stage: &str) -> Option<&str> {
+    self
+      .entries
+      .iter()
+      .rev()
+      .find_map(|entry| match &entry.event {
+        JournalEvent::StageStarted { stage: s, commit } if s == stage => Some(commit.as_str()),
+        _ => None,
+      })
+  }
+
+  /// Whether the issue numbered `number` was filed by the quest.
+  pub fn has_issue(&self, number: u64) -> bool {
+    self.entries.iter().any(
+      |entry| matches!(&entry.event, JournalEvent::IssueFiled { issue, .. } if *issue == number),
+    )
+  }
+
+  /// Whether the PR numbered `number` was filed by the quest.
+  pub fn has_pr(&self, number: u64) -> bool {
+    self.entries.iter().any(|entry| match &entry.event {
+      JournalEvent::StarterFiled { pr, .. } | JournalEvent::SolutionFiled { pr, .. } => {
+        *pr == number
+      }
+      _ => false,
+    })
+  }
+}
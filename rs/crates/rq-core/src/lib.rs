@@ -1,8 +1,24 @@
 mod command;
+mod create;
+pub mod forge;
 pub mod git;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
+pub mod journal;
+pub mod local;
+#[cfg(test)]
+mod mock_github;
 pub mod package;
+pub mod plan;
 pub mod quest;
+#[cfg(test)]
+mod replay;
+mod rest;
+pub mod settings;
+pub mod sign;
 pub mod stage;
 mod template;
+pub mod unpack;
 mod utils;
+pub mod validate;
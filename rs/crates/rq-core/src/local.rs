@@ -0,0 +1,488 @@
+//! A forge that lives entirely on the local filesystem, for learners without network access.
+//!
+//! Each repo is a bare git repo at `<root>/<owner>/<name>.git` that stands in for `origin`.
+//! Issues and PRs are stored next to the git data under `rqst/` as Markdown files with a
+//! TOML header, so learners can read them in any browser or editor.
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+  forge::{
+    Comment, Forge, ForgeRepo, FullPullRequest, GitProtocol, Head, Issue, IssueState, Label,
+    PullRequest,
+  },
+  git::{git, git_output, GitRepo},
+};
+
+const DATA_DIR: &str = "rqst";
+const LABELS_FILE: &str = "labels.toml";
+const FRONT_MATTER_DELIM: &str = "+++";
+
+pub struct LocalForge {
+  root: PathBuf,
+  user: String,
+}
+
+impl LocalForge {
+  pub fn new(root: PathBuf, user: &str) -> Self {
+    LocalForge {
+      root,
+      user: user.to_string(),
+    }
+  }
+
+  /// Opens the forge at `~/.rqst-forge`, owned by the current OS user.
+  pub fn open_default() -> Result<Self> {
+    let home = home::home_dir().context("Failed to find home directory")?;
+    let user = ["USER", "USERNAME"]
+      .into_iter()
+      .find_map(|var| std::env::var(var).ok())
+      .unwrap_or_else(|| "learner".into());
+    Ok(LocalForge::new(home.join(".rqst-forge"), &user))
+  }
+
+  fn repo_path(&self, owner: &str, name: &str) -> PathBuf {
+    self.root.join(owner).join(format!("{name}.git"))
+  }
+}
+
+#[async_trait]
+impl Forge for LocalForge {
+  async fn current_user(&self) -> Result<String> {
+    Ok(self.user.clone())
+  }
+
+  async fn load_repo(&self, owner: &str, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let repo = LocalRepo::new(self.repo_path(owner, name), owner, name);
+    ensure!(repo.fetch().await?, "Not found");
+    Ok(Box::new(repo))
+  }
+
+  async fn repo_exists(&self, owner: &str, name: &str) -> Result<bool> {
+    Ok(self.repo_path(owner, name).exists())
+  }
+
+  async fn create_repo(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    let path = self.repo_path(&self.user, name);
+    ensure!(!path.exists(), "Repo already exists: {}", path.display());
+    fs::create_dir_all(&path)
+      .with_context(|| format!("Failed to create directory: {}", path.display()))?;
+    let git_repo = GitRepo::new(&path);
+    git!(git_repo, "init --bare --initial-branch=main")?;
+
+    let repo = LocalRepo::new(path, &self.user, name);
+    repo.init_data()?;
+    Ok(Box::new(repo))
+  }
+
+  async fn create_repo_from_template(
+    &self,
+    base: &dyn ForgeRepo,
+    name: &str,
+  ) -> Result<Box<dyn ForgeRepo>> {
+    let path = self.repo_path(&self.user, name);
+    ensure!(!path.exists(), "Repo already exists: {}", path.display());
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent)
+      .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+
+    // Like a GitHub template, only the default branch is copied.
+    let remote = shlex::try_quote(&base.remote(GitProtocol::Https))?.into_owned();
+    let dst = shlex::try_quote(&path.display().to_string())?.into_owned();
+    let parent_repo = GitRepo::new(parent);
+    git!(
+      parent_repo,
+      "clone --bare --single-branch --branch main {remote} {dst}"
+    )?;
+
+    let repo = LocalRepo::new(path, &self.user, name);
+    repo.init_data()?;
+    Ok(Box::new(repo))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IssueHeader {
+  number: u64,
+  title: String,
+  state: IssueState,
+  labels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PullHeader {
+  number: u64,
+  title: String,
+  head: String,
+  base: String,
+  state: IssueState,
+  merged_at: Option<String>,
+  labels: Vec<String>,
+  #[serde(default)]
+  comments: Vec<Comment>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LabelsFile {
+  #[serde(default)]
+  labels: Vec<Label>,
+}
+
+fn read_document<T: DeserializeOwned>(path: &Path) -> Result<(T, String)> {
+  let contents =
+    fs::read_to_string(path).with_context(|| format!("Failed to read: {}", path.display()))?;
+  let parse = || {
+    let rest = contents.strip_prefix(FRONT_MATTER_DELIM)?;
+    let (header, body) = rest.split_once(&format!("\n{FRONT_MATTER_DELIM}\n"))?;
+    Some((header, body.strip_prefix('\n').unwrap_or(body)))
+  };
+  let Some((header, body)) = parse() else {
+    bail!("Missing header in: {}", path.display())
+  };
+  let header = toml::de::from_str::<T>(header)
+    .with_context(|| format!("Failed to parse header in: {}", path.display()))?;
+  Ok((header, body.to_string()))
+}
+
+pub(crate) fn write_document<T: Serialize>(path: &Path, header: &T, body: &str) -> Result<()> {
+  let header = toml::to_string_pretty(header).context("Failed to serialize header")?;
+  let contents = format!("{FRONT_MATTER_DELIM}\n{header}{FRONT_MATTER_DELIM}\n\n{body}");
+  fs::write(path, contents).with_context(|| format!("Failed to write: {}", path.display()))
+}
+
+pub struct LocalRepo {
+  path: PathBuf,
+  user: String,
+  name: String,
+  prs: Mutex<Option<Vec<FullPullRequest>>>,
+  issues: Mutex<Option<Vec<Issue>>>,
+}
+
+impl LocalRepo {
+  fn new(path: PathBuf, user: &str, name: &str) -> Self {
+    LocalRepo {
+      path,
+      user: user.to_string(),
+      name: name.to_string(),
+      prs: Mutex::new(None),
+      issues: Mutex::new(None),
+    }
+  }
+
+  fn data_dir(&self) -> PathBuf {
+    self.path.join(DATA_DIR)
+  }
+
+  fn issues_dir(&self) -> PathBuf {
+    self.data_dir().join("issues")
+  }
+
+  fn pulls_dir(&self) -> PathBuf {
+    self.data_dir().join("pulls")
+  }
+
+  fn init_data(&self) -> Result<()> {
+    for dir in [self.issues_dir(), self.pulls_dir()] {
+      fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    Ok(())
+  }
+
+  fn read_labels(&self) -> Result<Vec<Label>> {
+    let path = self.data_dir().join(LABELS_FILE);
+    if !path.exists() {
+      return Ok(Vec::new());
+    }
+    let contents =
+      fs::read_to_string(&path).with_context(|| format!("Failed to read: {}", path.display()))?;
+    let file = toml::de::from_str::<LabelsFile>(&contents)
+      .with_context(|| format!("Failed to parse: {}", path.display()))?;
+    Ok(file.labels)
+  }
+
+  fn write_labels(&self, labels: Vec<Label>) -> Result<()> {
+    let path = self.data_dir().join(LABELS_FILE);
+    let contents = toml::to_string_pretty(&LabelsFile { labels })?;
+    fs::write(&path, contents).with_context(|| format!("Failed to write: {}", path.display()))
+  }
+
+  fn resolve_labels(&self, names: &[String]) -> Result<Vec<Label>> {
+    let labels = self.read_labels()?;
+    Ok(
+      names
+        .iter()
+        .map(|name| {
+          labels
+            .iter()
+            .find(|label| &label.name == name)
+            .cloned()
+            .unwrap_or_else(|| Label {
+              name: name.clone(),
+              color: "ededed".into(),
+              description: None,
+              default: false,
+            })
+        })
+        .collect(),
+    )
+  }
+
+  fn url(path: &Path) -> String {
+    format!("file://{}", path.display())
+  }
+
+  fn document_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries =
+      fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    let mut paths = entries
+      .map(|entry| Ok(entry?.path()))
+      .collect::<Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "md"));
+    Ok(paths)
+  }
+
+  fn issue_path(&self, number: u64) -> PathBuf {
+    self.issues_dir().join(format!("{number}.md"))
+  }
+
+  fn pull_path(&self, number: u64) -> PathBuf {
+    self.pulls_dir().join(format!("{number}.md"))
+  }
+
+  fn read_issue(&self, path: &Path) -> Result<Issue> {
+    let (header, body) = read_document::<IssueHeader>(path)?;
+    Ok(Issue {
+      number: header.number,
+      title: header.title,
+      body: Some(body),
+      labels: self.resolve_labels(&header.labels)?,
+      state: header.state,
+      html_url: Self::url(path),
+    })
+  }
+
+  fn read_pull(&self, path: &Path) -> Result<FullPullRequest> {
+    let (header, body) = read_document::<PullHeader>(path)?;
+    let data = PullRequest {
+      number: header.number,
+      title: Some(header.title),
+      body: Some(body),
+      head: Head {
+        ref_field: header.head,
+      },
+      labels: self.resolve_labels(&header.labels)?,
+      html_url: Self::url(path),
+      merged_at: header.merged_at,
+    };
+    Ok(FullPullRequest {
+      data,
+      comments: header.comments,
+    })
+  }
+
+  fn read_all(&self) -> Result<(Vec<FullPullRequest>, Vec<Issue>)> {
+    let mut prs = Self::document_paths(&self.pulls_dir())?
+      .iter()
+      .map(|path| self.read_pull(path))
+      .collect::<Result<Vec<_>>>()?;
+    prs.sort_by_key(|pr| pr.data.number);
+    let mut issues = Self::document_paths(&self.issues_dir())?
+      .iter()
+      .map(|path| self.read_issue(path))
+      .collect::<Result<Vec<_>>>()?;
+    issues.sort_by_key(|issue| issue.number);
+    Ok((prs, issues))
+  }
+
+  /// Like GitHub, issues and PRs share a single sequence of numbers.
+  fn next_number(&self) -> Result<u64> {
+    let (prs, issues) = self.read_all()?;
+    let max_pr = prs.iter().map(|pr| pr.data.number).max();
+    let max_issue = issues.iter().map(|issue| issue.number).max();
+    Ok(max_pr.max(max_issue).unwrap_or(0) + 1)
+  }
+
+  fn update_pull(&self, number: u64, f: impl FnOnce(&mut PullHeader)) -> Result<()> {
+    let path = self.pull_path(number);
+    let (mut header, body) = read_document::<PullHeader>(&path)?;
+    f(&mut header);
+    write_document(&path, &header, &body)
+  }
+
+  fn update_issue(&self, number: u64, f: impl FnOnce(&mut IssueHeader)) -> Result<()> {
+    let path = self.issue_path(number);
+    let (mut header, body) = read_document::<IssueHeader>(&path)?;
+    f(&mut header);
+    write_document(&path, &header, &body)
+  }
+
+  fn git_repo(&self) -> GitRepo {
+    GitRepo::new(&self.path)
+  }
+}
+
+#[async_trait]
+impl ForgeRepo for LocalRepo {
+  fn owner(&self) -> &str {
+    &self.user
+  }
+
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn remote(&self, _protocol: GitProtocol) -> String {
+    self.path.display().to_string()
+  }
+
+  async fn fetch(&self) -> Result<bool> {
+    if !self.path.exists() {
+      return Ok(false);
+    }
+    let (prs, issues) = self.read_all()?;
+    *self.prs.lock() = Some(prs);
+    *self.issues.lock() = Some(issues);
+    Ok(true)
+  }
+
+  async fn refresh(&self, _prs: &[u64], _issues: &[u64]) -> Result<()> {
+    // Reading the local files costs nothing, so everything is reread.
+    self.fetch().await?;
+    Ok(())
+  }
+
+  fn prs(&self) -> MappedMutexGuard<'_, Vec<FullPullRequest>> {
+    MutexGuard::map(self.prs.lock(), |opt| {
+      opt.as_mut().expect("PRs not populated")
+    })
+  }
+
+  fn issues(&self) -> MappedMutexGuard<'_, Vec<Issue>> {
+    MutexGuard::map(self.issues.lock(), |opt| {
+      opt.as_mut().expect("Issues not populated")
+    })
+  }
+
+  async fn labels(&self) -> Result<Vec<Label>> {
+    self.read_labels()
+  }
+
+  async fn create_labels(&self, labels: &[Label]) -> Result<()> {
+    let mut existing = self.read_labels()?;
+    for label in labels.iter().filter(|label| !label.default) {
+      if !existing.iter().any(|other| other.name == label.name) {
+        existing.push(label.clone());
+      }
+    }
+    self.write_labels(existing)
+  }
+
+  async fn create_pr(
+    &self,
+    title: &str,
+    head: &str,
+    base: &str,
+    body: &str,
+  ) -> Result<PullRequest> {
+    let git_repo = self.git_repo();
+    git_output!(git_repo, "rev-parse --verify refs/heads/{head}")
+      .with_context(|| format!("Missing head branch for PR: {head}"))?;
+
+    let number = self.next_number()?;
+    let header = PullHeader {
+      number,
+      title: title.to_string(),
+      head: head.to_string(),
+      base: base.to_string(),
+      state: IssueState::Open,
+      merged_at: None,
+      labels: Vec::new(),
+      comments: Vec::new(),
+    };
+    let path = self.pull_path(number);
+    write_document(&path, &header, body)?;
+    Ok(self.read_pull(&path)?.data)
+  }
+
+  async fn add_pr_labels(&self, pr: u64, labels: &[String]) -> Result<()> {
+    self.update_pull(pr, |header| {
+      for label in labels {
+        if !header.labels.contains(label) {
+          header.labels.push(label.clone());
+        }
+      }
+    })
+  }
+
+  async fn create_pr_comment(&self, pr: u64, comment: &Comment, _commit: &str) -> Result<()> {
+    self.update_pull(pr, |header| header.comments.push(comment.clone()))
+  }
+
+  async fn create_issue(&self, title: &str, body: &str, labels: &[String]) -> Result<Issue> {
+    let number = self.next_number()?;
+    let header = IssueHeader {
+      number,
+      title: title.to_string(),
+      state: IssueState::Open,
+      labels: labels.to_vec(),
+    };
+    let path = self.issue_path(number);
+    write_document(&path, &header, body)?;
+    self.read_issue(&path)
+  }
+
+  async fn close_issue(&self, issue: &Issue) -> Result<()> {
+    self
+      .update_issue(issue.number, |header| header.state = IssueState::Closed)
+      .with_context(|| format!("Failed to close issue: {}", issue.number))
+  }
+
+  async fn merge_pr(&self, pr: &PullRequest) -> Result<()> {
+    let (header, _) = read_document::<PullHeader>(&self.pull_path(pr.number))?;
+    ensure!(
+      header.merged_at.is_none(),
+      "PR already merged: {}",
+      pr.number
+    );
+
+    // A bare repo has no working tree, so merge in a temporary worktree of the base branch.
+    let worktree_dir = tempfile::tempdir().context("Failed to create merge directory")?;
+    let worktree_path = worktree_dir.path().join(&self.name);
+    let worktree_arg = shlex::try_quote(&worktree_path.display().to_string())?.into_owned();
+    let git_repo = self.git_repo();
+    git!(git_repo, "worktree add {worktree_arg} {}", header.base)?;
+
+    let worktree = GitRepo::new(&worktree_path);
+    let merge_res = git!(
+      worktree,
+      "merge --no-ff {} -m 'Merge pull request #{}'",
+      header.head,
+      pr.number
+    );
+    if merge_res.is_err() {
+      let _ = git!(worktree, "merge --abort");
+    }
+    git!(git_repo, "worktree remove --force {worktree_arg}")?;
+    merge_res.with_context(|| format!("Failed to merge PR: {}", pr.number))?;
+
+    let merged_at = chrono::Utc::now().to_rfc3339();
+    self.update_pull(pr.number, |header| {
+      header.state = IssueState::Closed;
+      header.merged_at = Some(merged_at);
+    })
+  }
+
+  async fn delete(&self) -> Result<()> {
+    fs::remove_dir_all(&self.path)
+      .with_context(|| format!("Failed to delete repo: {}", self.path.display()))
+  }
+}
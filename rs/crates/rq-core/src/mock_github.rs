@@ -0,0 +1,869 @@
+//! An in-process fake of the Github REST API, so [`GithubForge`] can be tested offline.
+//!
+//! Repos and their issues and PRs are stored by a [`LocalForge`] under a shared root. Git
+//! remotes on [`MOCK_HOST`] are redirected to that root by [`git_env`] with `insteadOf`.
+
+use std::{
+  env, fs,
+  net::SocketAddr,
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, LazyLock, Once,
+  },
+};
+
+use anyhow::{Context, Result};
+use axum::{
+  extract::{Path as UrlPath, Query, State},
+  http::{header, HeaderMap, StatusCode},
+  response::{IntoResponse, Response},
+  routing::{get, post, put},
+  Json, Router,
+};
+use octocrab::Octocrab;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::{net::TcpListener, sync::Mutex, task::JoinHandle};
+use tracing_subscriber::{fmt, layer::SubscriberExt, prelude::*, EnvFilter};
+
+use crate::{
+  forge::{
+    Comment, Forge, ForgeRepo, FullPullRequest, GitProtocol, Issue, IssueState, Label, PullRequest,
+  },
+  git::{git, git_output, GitRepo},
+  github::{GithubForge, GithubHost},
+  local::LocalForge,
+  quest::QuestConfig,
+  stage::{Stage, StagePart},
+};
+
+/// Hostname of the fake Github, used in git remotes and HTML URLs.
+pub const MOCK_HOST: &str = "github.mock";
+
+/// Name of the quest template that tests publish under [`MockGithub::org`].
+pub const TEMPLATE_REPO: &str = "rqst-test";
+
+static GIT_ROOT: LazyLock<TempDir> =
+  LazyLock::new(|| tempfile::tempdir().expect("Failed to create mock git root"));
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Environment variables that point git remotes on [`MOCK_HOST`] at the mock's bare repos.
+/// These must be set before the first git command runs.
+pub fn git_env() -> Vec<(String, String)> {
+  let root = format!("{}/", GIT_ROOT.path().display());
+  let key = format!("url.{root}.insteadOf");
+  vec![
+    ("GIT_CONFIG_COUNT".into(), "2".into()),
+    ("GIT_CONFIG_KEY_0".into(), key.clone()),
+    ("GIT_CONFIG_VALUE_0".into(), format!("https://{MOCK_HOST}/")),
+    ("GIT_CONFIG_KEY_1".into(), key),
+    ("GIT_CONFIG_VALUE_1".into(), format!("git@{MOCK_HOST}:")),
+  ]
+}
+
+/// Configures logging and git for tests. Safe to call from every test.
+pub fn setup() {
+  static SETUP: Once = Once::new();
+  SETUP.call_once(|| {
+    tracing_subscriber::registry()
+      .with(fmt::layer())
+      .with(EnvFilter::from_default_env())
+      .init();
+
+    // Tests make commits, so they can't depend on the machine's git config.
+    for (key, value) in [
+      ("GIT_AUTHOR_NAME", "rqst-test"),
+      ("GIT_AUTHOR_EMAIL", "rqst-test@example.com"),
+      ("GIT_COMMITTER_NAME", "rqst-test"),
+      ("GIT_COMMITTER_EMAIL", "rqst-test@example.com"),
+    ] {
+      env::set_var(key, value);
+    }
+    for (key, value) in git_env() {
+      env::set_var(key, value);
+    }
+  });
+}
+
+/// A three-stage quest where the first stage has no starter code.
+pub fn test_config(author: &str, repo: &str) -> QuestConfig {
+  let stages = ["s0", "s1", "s2"]
+    .into_iter()
+    .enumerate()
+    .map(|(i, label)| Stage {
+      label: label.into(),
+      name: format!("Chapter {i}"),
+      no_starter: Some(i == 0),
+    })
+    .collect::<Vec<_>>();
+  QuestConfig {
+    title: "Offline test".into(),
+    author: author.into(),
+    repo: repo.into(),
+    stages,
+    read_only: None,
+    r#final: None,
+  }
+}
+
+/// A running fake Github server. Its repos are deleted when it is dropped.
+///
+/// Each server has its own learner account and template organization,
+/// so servers in concurrent tests don't see each other's repos.
+pub struct MockGithub {
+  pub user: String,
+  pub org: String,
+  addr: SocketAddr,
+  server: JoinHandle<()>,
+}
+
+impl MockGithub {
+  pub async fn start() -> Result<Self> {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let user = format!("learner{id}");
+    let org = format!("rqst-org{id}");
+    let state = Arc::new(MockState {
+      user: user.clone(),
+      lock: Mutex::new(()),
+    });
+
+    let repo_routes = Router::new()
+      .route("/generate", post(generate_repo))
+      .route("/commits", get(list_commits))
+      .route("/subscription", put(subscribe))
+      .route("/labels", get(list_labels).post(create_label))
+      .route("/pulls", get(list_pulls).post(create_pull))
+      .route("/pulls/:number", get(get_pull))
+      .route(
+        "/pulls/:number/comments",
+        get(list_pull_comments).post(create_pull_comment),
+      )
+      .route("/pulls/:number/merge", put(merge_pull))
+      .route("/issues", get(list_issues).post(create_issue))
+      .route("/issues/:number", get(get_issue).patch(update_issue))
+      .route("/issues/:number/labels", post(add_labels));
+    let app = Router::new()
+      .route("/user", get(current_user))
+      .route("/user/repos", post(create_repo))
+      .route("/repos/:owner/:repo", get(get_repo).delete(delete_repo))
+      .nest("/repos/:owner/:repo", repo_routes)
+      .with_state(state);
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+      .await
+      .context("Failed to bind mock Github server")?;
+    let addr = listener.local_addr()?;
+    let server = tokio::spawn(async move {
+      axum::serve(listener, app).await.unwrap();
+    });
+
+    Ok(MockGithub {
+      user,
+      org,
+      addr,
+      server,
+    })
+  }
+
+  pub fn host(&self) -> GithubHost {
+    GithubHost::new(MOCK_HOST, Some(format!("http://{}", self.addr)))
+  }
+
+  /// A [`GithubForge`] authenticated as [`MockGithub::user`].
+  pub fn forge(&self) -> Result<GithubForge> {
+    let host = self.host();
+    let gh = Octocrab::builder()
+      .base_uri(host.api_base())?
+      .personal_token("mock-token".to_string())
+      .build()
+      .context("Failed to build mock Github connector")?;
+    Ok(GithubForge::from_octocrab(gh, host))
+  }
+
+  /// Direct access to the repos of `owner`, e.g. to seed a template under [`MockGithub::org`].
+  pub fn local_forge(&self, owner: &str) -> LocalForge {
+    LocalForge::new(GIT_ROOT.path().to_path_buf(), owner)
+  }
+
+  /// Publishes a quest repo for `config` under its author, which should be [`MockGithub::org`].
+  /// Each starter branch adds `<label>.txt`, and each solution branch appends to it.
+  pub async fn seed_template(&self, config: &QuestConfig) -> Result<()> {
+    let forge = self.local_forge(&config.author);
+    let upstream = forge.create_repo(&config.repo).await?;
+
+    let dir = tempfile::tempdir()?;
+    let work = GitRepo::new(dir.path());
+    let commit = |file: &str, contents: &str, message: &str| -> Result<()> {
+      fs::write(dir.path().join(file), contents)?;
+      git!(work, "add .")?;
+      git!(work, "commit -m {}", shlex::try_quote(message)?)
+    };
+    git!(work, "init --initial-branch=main")?;
+    commit("README.md", "Hello\n", "Initial commit")?;
+
+    let mut base = "main".to_string();
+    for (i, stage) in config.stages.iter().enumerate() {
+      let file = format!("{}.txt", stage.label);
+      if !stage.no_starter() {
+        let starter = stage.branch_name(StagePart::Starter);
+        git!(work, "checkout -b {starter} {base}")?;
+        commit(&file, &format!("Chapter {i}\n"), "Starter code")?;
+        base = starter;
+      }
+      let solution = stage.branch_name(StagePart::Solution);
+      git!(work, "checkout -b {solution} {base}")?;
+      let starter_contents = fs::read_to_string(dir.path().join(&file)).unwrap_or_default();
+      commit(&file, &format!("{starter_contents}solved\n"), "Solution")?;
+      base = solution;
+    }
+
+    git!(work, "checkout -b meta main")?;
+    commit("rqst.toml", &toml::to_string(config)?, "Add meta")?;
+
+    let remote = shlex::try_quote(&upstream.remote(GitProtocol::Https))?.into_owned();
+    git!(work, "push --all {remote}")?;
+
+    let labels = config
+      .stages
+      .iter()
+      .map(|stage| Label {
+        name: stage.label.clone(),
+        color: "ededed".into(),
+        description: None,
+        default: false,
+      })
+      .collect::<Vec<_>>();
+    upstream.create_labels(&labels).await?;
+
+    let mut base = "main".to_string();
+    for stage in &config.stages {
+      let label = vec![stage.label.clone()];
+      let parts = [StagePart::Starter, StagePart::Solution];
+      for part in parts
+        .into_iter()
+        .filter(|part| !(stage.no_starter() && *part == StagePart::Starter))
+      {
+        let branch = stage.branch_name(part);
+        let title = format!("{} {part:?}", stage.name);
+        let pr = upstream.create_pr(&title, &branch, &base, &title).await?;
+        upstream.add_pr_labels(pr.number, &label).await?;
+        if part == StagePart::Solution {
+          let comment = Comment {
+            path: format!("{}.txt", stage.label),
+            body: "This line is the solution.".into(),
+            line: Some(1),
+          };
+          upstream
+            .create_pr_comment(pr.number, &comment, &branch)
+            .await?;
+        }
+        base = branch;
+      }
+      let body = format!("Starter code is in {{{{ {} pr }}}}.", stage.label);
+      upstream.create_issue(&stage.name, &body, &label).await?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for MockGithub {
+  fn drop(&mut self) {
+    self.server.abort();
+    for owner in [&self.user, &self.org] {
+      let _ = std::fs::remove_dir_all(GIT_ROOT.path().join(owner));
+    }
+  }
+}
+
+struct MockState {
+  user: String,
+  // The local forge's files aren't safe for concurrent writers, so requests are handled one at a time.
+  lock: Mutex<()>,
+}
+
+type AppState = State<Arc<MockState>>;
+
+enum MockError {
+  Status(StatusCode, String),
+  Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MockError {
+  fn from(err: anyhow::Error) -> Self {
+    MockError::Internal(err)
+  }
+}
+
+impl IntoResponse for MockError {
+  fn into_response(self) -> Response {
+    let (status, message) = match self {
+      MockError::Status(status, message) => (status, message),
+      MockError::Internal(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:?}")),
+    };
+    let body = json!({
+      "message": message,
+      "documentation_url": "https://docs.github.com/rest",
+    });
+    (status, Json(body)).into_response()
+  }
+}
+
+type MockResult<T = Json<Value>> = Result<T, MockError>;
+
+fn not_found() -> MockError {
+  MockError::Status(StatusCode::NOT_FOUND, "Not Found".into())
+}
+
+fn html_url(path: &str) -> String {
+  format!("https://{MOCK_HOST}/{path}")
+}
+
+fn repo_path(owner: &str, repo: &str) -> PathBuf {
+  GIT_ROOT.path().join(owner).join(format!("{repo}.git"))
+}
+
+async fn load_repo(owner: &str, repo: &str) -> MockResult<Box<dyn ForgeRepo>> {
+  if !repo_path(owner, repo).exists() {
+    return Err(not_found());
+  }
+  let forge = LocalForge::new(GIT_ROOT.path().to_path_buf(), owner);
+  Ok(forge.load_repo(owner, repo).await?)
+}
+
+fn author_json(login: &str) -> Value {
+  let url = html_url(login);
+  json!({
+    "login": login,
+    "id": 1,
+    "node_id": format!("U_{login}"),
+    "avatar_url": url,
+    "gravatar_id": "",
+    "url": url,
+    "html_url": url,
+    "followers_url": url,
+    "following_url": url,
+    "gists_url": url,
+    "starred_url": url,
+    "subscriptions_url": url,
+    "organizations_url": url,
+    "repos_url": url,
+    "events_url": url,
+    "received_events_url": url,
+    "type": "User",
+    "site_admin": false,
+  })
+}
+
+fn label_json(owner: &str, repo: &str, id: usize, label: &Label) -> Value {
+  json!({
+    "id": id + 1,
+    "node_id": format!("LA_{id}"),
+    "url": html_url(&format!("{owner}/{repo}/labels/{}", label.name)),
+    "name": label.name,
+    "description": label.description,
+    "color": label.color,
+    "default": label.default,
+  })
+}
+
+fn labels_json(owner: &str, repo: &str, labels: &[Label]) -> Vec<Value> {
+  labels
+    .iter()
+    .enumerate()
+    .map(|(i, label)| label_json(owner, repo, i, label))
+    .collect()
+}
+
+fn now() -> String {
+  chrono::Utc::now().to_rfc3339()
+}
+
+fn issue_json(owner: &str, repo: &str, issue: &Issue) -> Value {
+  let url = html_url(&format!("{owner}/{repo}/issues/{}", issue.number));
+  json!({
+    "id": issue.number,
+    "node_id": format!("I_{}", issue.number),
+    "url": url,
+    "repository_url": html_url(&format!("{owner}/{repo}")),
+    "labels_url": url,
+    "comments_url": url,
+    "events_url": url,
+    "html_url": url,
+    "number": issue.number,
+    "state": issue.state,
+    "state_reason": null,
+    "title": issue.title,
+    "body": issue.body,
+    "user": author_json(owner),
+    "labels": labels_json(owner, repo, &issue.labels),
+    "assignees": [],
+    "author_association": "OWNER",
+    "locked": false,
+    "comments": 0,
+    "created_at": now(),
+    "updated_at": now(),
+  })
+}
+
+/// Github lists PRs among issues, marked with a `pull_request` field.
+fn pull_issue_json(owner: &str, repo: &str, pr: &PullRequest) -> Value {
+  let mut value = issue_json(
+    owner,
+    repo,
+    &Issue {
+      number: pr.number,
+      title: pr.title.clone().unwrap_or_default(),
+      body: pr.body.clone(),
+      labels: pr.labels.clone(),
+      state: if pr.is_merged() {
+        IssueState::Closed
+      } else {
+        IssueState::Open
+      },
+      html_url: String::new(),
+    },
+  );
+  let url = html_url(&format!("{owner}/{repo}/pull/{}", pr.number));
+  value["pull_request"] = json!({
+    "url": url,
+    "html_url": url,
+    "diff_url": format!("{url}.diff"),
+    "patch_url": format!("{url}.patch"),
+  });
+  value
+}
+
+fn pull_json(owner: &str, repo: &str, pr: &PullRequest) -> Value {
+  let url = html_url(&format!("{owner}/{repo}/pull/{}", pr.number));
+  json!({
+    "url": url,
+    "id": pr.number,
+    "number": pr.number,
+    "html_url": url,
+    "state": if pr.is_merged() { "closed" } else { "open" },
+    "title": pr.title,
+    "body": pr.body,
+    "labels": labels_json(owner, repo, &pr.labels),
+    "merged_at": pr.merged_at,
+    "head": { "ref": pr.head.ref_field, "sha": "" },
+    "base": { "ref": "main", "sha": "" },
+  })
+}
+
+fn comment_json(owner: &str, repo: &str, pr: u64, id: usize, comment: &Comment) -> Value {
+  let url = html_url(&format!("{owner}/{repo}/pull/{pr}#discussion_r{id}"));
+  json!({
+    "url": url,
+    "pull_request_review_id": null,
+    "id": id + 1,
+    "node_id": format!("PRRC_{id}"),
+    "diff_hunk": "",
+    "path": comment.path,
+    "position": comment.line,
+    "original_position": comment.line,
+    "commit_id": "",
+    "original_commit_id": "",
+    "user": author_json(owner),
+    "body": comment.body,
+    "created_at": now(),
+    "updated_at": now(),
+    "html_url": url,
+    "author_association": "OWNER",
+    "_links": {},
+    "start_line": null,
+    "original_start_line": null,
+    "start_side": null,
+    "line": comment.line,
+    "original_line": comment.line,
+    "side": "RIGHT",
+  })
+}
+
+fn find_pr(repo: &dyn ForgeRepo, number: u64) -> MockResult<FullPullRequest> {
+  let prs = repo.prs();
+  let pr = prs.iter().find(|pr| pr.data.number == number);
+  pr.cloned().ok_or_else(not_found)
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+  state: Option<String>,
+  direction: Option<String>,
+  per_page: Option<usize>,
+  page: Option<usize>,
+}
+
+impl ListParams {
+  /// Orders items by number, which matches creation order, newest first unless `direction=asc`.
+  /// Like Github, returns one page with a `Link` header pointing to the next page, if any.
+  fn page(&self, headers: &HeaderMap, path: &str, mut items: Vec<(u64, Value)>) -> Response {
+    items.sort_by_key(|(number, _)| *number);
+    if self.direction.as_deref() != Some("asc") {
+      items.reverse();
+    }
+    let per_page = self.per_page.unwrap_or(30);
+    let page = self.page.unwrap_or(1);
+    let has_next = page * per_page < items.len();
+    let items = items
+      .into_iter()
+      .skip((page - 1) * per_page)
+      .take(per_page)
+      .map(|(_, item)| item)
+      .collect::<Vec<_>>();
+
+    let mut response = Json(items).into_response();
+    if has_next {
+      let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+      let mut query = vec![format!("per_page={per_page}"), format!("page={}", page + 1)];
+      if let Some(state) = &self.state {
+        query.push(format!("state={state}"));
+      }
+      if let Some(direction) = &self.direction {
+        query.push(format!("direction={direction}"));
+      }
+      let link = format!(r#"<http://{host}{path}?{}>; rel="next""#, query.join("&"));
+      response
+        .headers_mut()
+        .insert(header::LINK, link.parse().unwrap());
+    }
+    response
+  }
+}
+
+async fn current_user(State(state): AppState) -> Json<Value> {
+  Json(author_json(&state.user))
+}
+
+#[derive(Deserialize)]
+struct CreateRepo {
+  name: String,
+}
+
+async fn create_repo(State(state): AppState, Json(body): Json<CreateRepo>) -> MockResult {
+  let _lock = state.lock.lock().await;
+  let forge = LocalForge::new(GIT_ROOT.path().to_path_buf(), &state.user);
+  forge.create_repo(&body.name).await?;
+  Ok(Json(json!({ "name": body.name })))
+}
+
+#[derive(Deserialize)]
+struct GenerateRepo {
+  owner: String,
+  name: String,
+}
+
+async fn generate_repo(
+  State(state): AppState,
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Json(body): Json<GenerateRepo>,
+) -> MockResult {
+  let _lock = state.lock.lock().await;
+  let base = load_repo(&owner, &repo).await?;
+  let forge = LocalForge::new(GIT_ROOT.path().to_path_buf(), &body.owner);
+  forge.create_repo_from_template(&*base, &body.name).await?;
+  Ok(Json(json!({ "name": body.name })))
+}
+
+async fn list_commits(UrlPath((owner, repo)): UrlPath<(String, String)>) -> MockResult {
+  let path = repo_path(&owner, &repo);
+  if !path.exists() {
+    return Err(not_found());
+  }
+  let git_repo = GitRepo::new(&path);
+  let commits = git_output!(git_repo, "rev-list --all --max-count=1")?;
+  if commits.trim().is_empty() {
+    return Err(MockError::Status(
+      StatusCode::CONFLICT,
+      "Git Repository is empty.".into(),
+    ));
+  }
+  // The forge only checks whether there are commits, not what they are.
+  Ok(Json(json!([])))
+}
+
+async fn subscribe(UrlPath((owner, repo)): UrlPath<(String, String)>) -> MockResult {
+  load_repo(&owner, &repo).await?;
+  Ok(Json(json!({ "subscribed": false, "ignored": true })))
+}
+
+async fn get_repo(UrlPath((owner, repo)): UrlPath<(String, String)>) -> MockResult {
+  load_repo(&owner, &repo).await?;
+  Ok(Json(json!({
+    "name": repo,
+    "full_name": format!("{owner}/{repo}"),
+    "owner": author_json(&owner),
+    "html_url": html_url(&format!("{owner}/{repo}")),
+  })))
+}
+
+async fn delete_repo(
+  State(state): AppState,
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+) -> MockResult<StatusCode> {
+  let _lock = state.lock.lock().await;
+  load_repo(&owner, &repo).await?.delete().await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_labels(UrlPath((owner, repo)): UrlPath<(String, String)>) -> MockResult {
+  let labels = load_repo(&owner, &repo).await?.labels().await?;
+  Ok(Json(json!(labels_json(&owner, &repo, &labels))))
+}
+
+#[derive(Deserialize)]
+struct CreateLabel {
+  name: String,
+  color: String,
+  description: Option<String>,
+}
+
+async fn create_label(
+  State(state): AppState,
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Json(body): Json<CreateLabel>,
+) -> MockResult<(StatusCode, Json<Value>)> {
+  let _lock = state.lock.lock().await;
+  let label = Label {
+    name: body.name,
+    color: body.color,
+    description: body.description.filter(|desc| !desc.is_empty()),
+    default: false,
+  };
+  let forge_repo = load_repo(&owner, &repo).await?;
+  forge_repo
+    .create_labels(std::slice::from_ref(&label))
+    .await?;
+  let id = forge_repo.labels().await?.len() - 1;
+  Ok((
+    StatusCode::CREATED,
+    Json(label_json(&owner, &repo, id, &label)),
+  ))
+}
+
+async fn list_pulls(
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Query(params): Query<ListParams>,
+  headers: HeaderMap,
+) -> MockResult<Response> {
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let prs = forge_repo
+    .prs()
+    .iter()
+    .map(|pr| (pr.data.number, pull_json(&owner, &repo, &pr.data)))
+    .collect();
+  let path = format!("/repos/{owner}/{repo}/pulls");
+  Ok(params.page(&headers, &path, prs))
+}
+
+async fn get_pull(UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>) -> MockResult {
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let pr = find_pr(&*forge_repo, number)?;
+  Ok(Json(pull_json(&owner, &repo, &pr.data)))
+}
+
+#[derive(Deserialize)]
+struct CreatePull {
+  title: String,
+  head: String,
+  base: String,
+  body: Option<String>,
+}
+
+async fn create_pull(
+  State(state): AppState,
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Json(body): Json<CreatePull>,
+) -> MockResult<(StatusCode, Json<Value>)> {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let pr = forge_repo
+    .create_pr(
+      &body.title,
+      &body.head,
+      &body.base,
+      body.body.as_deref().unwrap_or(""),
+    )
+    .await
+    .map_err(|err| MockError::Status(StatusCode::UNPROCESSABLE_ENTITY, format!("{err:?}")))?;
+  Ok((StatusCode::CREATED, Json(pull_json(&owner, &repo, &pr))))
+}
+
+async fn list_pull_comments(
+  UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>,
+) -> MockResult {
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let pr = find_pr(&*forge_repo, number)?;
+  let comments = pr
+    .comments
+    .iter()
+    .enumerate()
+    .map(|(i, comment)| comment_json(&owner, &repo, number, i, comment))
+    .collect::<Vec<_>>();
+  Ok(Json(json!(comments)))
+}
+
+#[derive(Deserialize)]
+struct CreatePullComment {
+  path: String,
+  body: String,
+  line: Option<u64>,
+  commit_id: String,
+}
+
+async fn create_pull_comment(
+  State(state): AppState,
+  UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>,
+  Json(body): Json<CreatePullComment>,
+) -> MockResult<(StatusCode, Json<Value>)> {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let comment = Comment {
+    path: body.path,
+    body: body.body,
+    line: body.line,
+  };
+  forge_repo
+    .create_pr_comment(number, &comment, &body.commit_id)
+    .await?;
+  let id = find_pr(&*forge_repo, number)?.comments.len();
+  Ok((
+    StatusCode::CREATED,
+    Json(comment_json(&owner, &repo, number, id, &comment)),
+  ))
+}
+
+async fn merge_pull(
+  State(state): AppState,
+  UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>,
+) -> MockResult {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let pr = find_pr(&*forge_repo, number)?;
+  forge_repo.merge_pr(&pr.data).await.map_err(|err| {
+    MockError::Status(
+      StatusCode::METHOD_NOT_ALLOWED,
+      format!("Pull Request is not mergeable: {err:?}"),
+    )
+  })?;
+  Ok(Json(json!({
+    "sha": null,
+    "merged": true,
+    "message": "Pull Request successfully merged",
+  })))
+}
+
+async fn list_issues(
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Query(params): Query<ListParams>,
+  headers: HeaderMap,
+) -> MockResult<Response> {
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let mut items = forge_repo
+    .issues()
+    .iter()
+    .map(|issue| (issue.number, issue_json(&owner, &repo, issue)))
+    .collect::<Vec<_>>();
+  items.extend(
+    forge_repo
+      .prs()
+      .iter()
+      .map(|pr| (pr.data.number, pull_issue_json(&owner, &repo, &pr.data))),
+  );
+  let path = format!("/repos/{owner}/{repo}/issues");
+  Ok(params.page(&headers, &path, items))
+}
+
+/// Like Github, PRs can also be fetched as issues.
+async fn get_issue(UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>) -> MockResult {
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let issue = forge_repo
+    .issues()
+    .iter()
+    .find(|issue| issue.number == number)
+    .map(|issue| issue_json(&owner, &repo, issue));
+  match issue {
+    Some(issue) => Ok(Json(issue)),
+    None => {
+      let pr = find_pr(&*forge_repo, number)?;
+      Ok(Json(pull_issue_json(&owner, &repo, &pr.data)))
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct CreateIssue {
+  title: String,
+  body: Option<String>,
+  #[serde(default)]
+  labels: Vec<String>,
+}
+
+async fn create_issue(
+  State(state): AppState,
+  UrlPath((owner, repo)): UrlPath<(String, String)>,
+  Json(body): Json<CreateIssue>,
+) -> MockResult<(StatusCode, Json<Value>)> {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let issue = forge_repo
+    .create_issue(
+      &body.title,
+      body.body.as_deref().unwrap_or(""),
+      &body.labels,
+    )
+    .await?;
+  Ok((StatusCode::CREATED, Json(issue_json(&owner, &repo, &issue))))
+}
+
+#[derive(Deserialize)]
+struct UpdateIssue {
+  state: Option<IssueState>,
+}
+
+async fn update_issue(
+  State(state): AppState,
+  UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>,
+  Json(body): Json<UpdateIssue>,
+) -> MockResult {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  let issue = forge_repo
+    .issues()
+    .iter()
+    .find(|issue| issue.number == number)
+    .cloned()
+    .ok_or_else(not_found)?;
+  if body.state == Some(IssueState::Closed) {
+    forge_repo.close_issue(&issue).await?;
+  }
+  let issue = Issue {
+    state: body.state.unwrap_or(issue.state),
+    ..issue
+  };
+  Ok(Json(issue_json(&owner, &repo, &issue)))
+}
+
+#[derive(Deserialize)]
+struct AddLabels {
+  labels: Vec<String>,
+}
+
+async fn add_labels(
+  State(state): AppState,
+  UrlPath((owner, repo, number)): UrlPath<(String, String, u64)>,
+  Json(body): Json<AddLabels>,
+) -> MockResult {
+  let _lock = state.lock.lock().await;
+  let forge_repo = load_repo(&owner, &repo).await?;
+  forge_repo.add_pr_labels(number, &body.labels).await?;
+  forge_repo.fetch().await?;
+  let pr = find_pr(&*forge_repo, number)?;
+  Ok(Json(json!(labels_json(&owner, &repo, &pr.data.labels))))
+}
@@ -2,39 +2,387 @@ use std::{
   collections::HashMap,
   fs::File,
   io::{BufReader, BufWriter, Read},
+  num::NonZeroU32,
   path::{Path, PathBuf},
 };
 
 use crate::{
+  forge::{Comment, Forge, FullPullRequest, Issue, Label, PullSelector},
   git::GitRepo,
-  github::{FullPullRequest, GithubRepo},
   quest::QuestConfig,
+  sign::{PackageSignature, Signer, SigningKey, Verification, SIGNATURE_FIELD},
   stage::StagePart,
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
-use octocrab::models::{issues::Issue, Label};
-use semver::{Version, VersionReq};
+use ring::{
+  aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+  pbkdf2,
+  rand::{SecureRandom, SystemRandom},
+};
+use semver::Version;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-#[derive(Serialize, Deserialize)]
+/// Version of the package file format, bumped on incompatible changes.
+///
+/// 1. The initial tree is a map from path to text contents.
+/// 2. The initial tree holds binary-safe contents, file modes and symlinks.
+/// 3. Issues, PRs and labels only hold what is copied to the learner's repo.
+///
+/// Packages of older formats are upgraded by [`MIGRATIONS`] when loaded.
+pub const PACKAGE_FORMAT: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// A migration upgrades a package from one format to the next, in its JSON form.
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// The migration at index `i` upgrades a package from format `i + 1` to `i + 2`. To change
+/// the format, add a migration here and describe the new format on [`PACKAGE_FORMAT`].
+const MIGRATIONS: &[Migration] = &[upgrade_v1, upgrade_v2];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Patch {
   pub base: String,
   pub head: String,
   pub patch: String,
 }
 
+/// A label of the quest repo, copied to the learner's repo.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageLabel {
+  pub name: String,
+  pub color: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+}
+
+impl From<&Label> for PackageLabel {
+  fn from(label: &Label) -> Self {
+    PackageLabel {
+      name: label.name.clone(),
+      color: label.color.clone(),
+      description: label.description.clone(),
+    }
+  }
+}
+
+impl From<&PackageLabel> for Label {
+  fn from(label: &PackageLabel) -> Self {
+    Label {
+      name: label.name.clone(),
+      color: label.color.clone(),
+      description: label.description.clone(),
+      default: false,
+    }
+  }
+}
+
+/// An issue of the quest repo, filed in the learner's repo when they start its stage.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageIssue {
+  pub title: String,
+  pub body: Option<String>,
+  /// The names of the issue's labels, including its stage's label.
+  pub labels: Vec<String>,
+}
+
+impl From<&Issue> for PackageIssue {
+  fn from(issue: &Issue) -> Self {
+    PackageIssue {
+      title: issue.title.clone(),
+      body: issue.body.clone(),
+      labels: issue
+        .labels
+        .iter()
+        .map(|label| label.name.clone())
+        .collect(),
+    }
+  }
+}
+
+/// A review comment of a [`PackagePr`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageComment {
+  pub path: String,
+  pub body: String,
+  pub line: Option<u64>,
+}
+
+impl From<&Comment> for PackageComment {
+  fn from(comment: &Comment) -> Self {
+    PackageComment {
+      path: comment.path.clone(),
+      body: comment.body.clone(),
+      line: comment.line,
+    }
+  }
+}
+
+impl From<&PackageComment> for Comment {
+  fn from(comment: &PackageComment) -> Self {
+    Comment {
+      path: comment.path.clone(),
+      body: comment.body.clone(),
+      line: comment.line,
+    }
+  }
+}
+
+/// A PR of the quest repo, filed in the learner's repo from the branch of a stage.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackagePr {
+  pub title: Option<String>,
+  pub body: Option<String>,
+  /// The stage branch the PR was made from, e.g. `s1-a`.
+  pub branch: String,
+  pub labels: Vec<String>,
+  /// The PR in the quest repo, linked as the reference solution.
+  pub html_url: String,
+  pub comments: Vec<PackageComment>,
+}
+
+impl PackagePr {
+  pub fn matches(&self, selector: &PullSelector) -> bool {
+    match selector {
+      PullSelector::Branch(branch) => &self.branch == branch,
+      PullSelector::Label(label) => self.labels.contains(label),
+    }
+  }
+}
+
+impl From<&FullPullRequest> for PackagePr {
+  fn from(pr: &FullPullRequest) -> Self {
+    PackagePr {
+      title: pr.data.title.clone(),
+      body: pr.data.body.clone(),
+      branch: pr.data.head.ref_field.clone(),
+      labels: pr
+        .data
+        .labels
+        .iter()
+        .map(|label| label.name.clone())
+        .collect(),
+      html_url: pr.data.html_url.clone(),
+      comments: pr.comments.iter().map(PackageComment::from).collect(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileMode {
+  Regular,
+  Executable,
+  /// The contents are the path the link points to.
+  Symlink,
+}
+
+impl FileMode {
+  /// Parses a mode from `git ls-tree`, or returns `None` for submodules.
+  pub fn from_git(mode: &str) -> Option<Self> {
+    match mode {
+      "100644" => Some(FileMode::Regular),
+      "100755" => Some(FileMode::Executable),
+      "120000" => Some(FileMode::Symlink),
+      _ => None,
+    }
+  }
+
+  pub fn git_mode(self) -> &'static str {
+    match self {
+      FileMode::Regular => "100644",
+      FileMode::Executable => "100755",
+      FileMode::Symlink => "120000",
+    }
+  }
+}
+
+/// The contents of a file, stored as text when possible so packages stay readable.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileContents {
+  Text(String),
+  Base64(String),
+}
+
+impl FileContents {
+  pub fn from_bytes(bytes: Vec<u8>) -> Self {
+    match String::from_utf8(bytes) {
+      Ok(text) => FileContents::Text(text),
+      Err(e) => FileContents::Base64(BASE64.encode(e.as_bytes())),
+    }
+  }
+
+  pub fn to_bytes(&self) -> Result<Vec<u8>> {
+    match self {
+      FileContents::Text(text) => Ok(text.as_bytes().to_vec()),
+      FileContents::Base64(data) => BASE64
+        .decode(data)
+        .context("Invalid base64 in package file"),
+    }
+  }
+}
+
+/// A file in the initial tree of a quest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageFile {
+  pub mode: FileMode,
+  pub contents: FileContents,
+}
+
+impl PackageFile {
+  pub fn new(mode: FileMode, contents: Vec<u8>) -> Self {
+    PackageFile {
+      mode,
+      contents: FileContents::from_bytes(contents),
+    }
+  }
+
+  pub fn regular(contents: impl Into<Vec<u8>>) -> Self {
+    Self::new(FileMode::Regular, contents.into())
+  }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct QuestPackage {
+  /// The version of RepoQuest that made the package.
   pub version: Version,
+  pub format: u32,
   pub config: QuestConfig,
-  pub issues: Vec<Issue>,
-  pub prs: Vec<FullPullRequest>,
-  pub initial: HashMap<PathBuf, String>,
+  pub issues: Vec<PackageIssue>,
+  pub prs: Vec<PackagePr>,
+  pub initial: HashMap<PathBuf, PackageFile>,
   pub patches: Vec<Patch>,
+  pub labels: Vec<PackageLabel>,
+  /// A base64 git bundle of the quest repo's main, meta and stage branches, if packed with one.
+  /// Older packages and packages built without one only have `initial` and `patches`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub bundle: Option<String>,
+  /// Patches from each stage's base to its solution branch, if packed with them.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub solutions: Option<PackageSolutions>,
+  /// The author's signature over the rest of the package, if signed with [`QuestPackage::sign`].
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub signature: Option<PackageSignature>,
+  /// Who signed the package, once its signature is verified.
   #[serde(skip)]
-  patch_map: HashMap<(String, String), usize>,
-  pub labels: Vec<Label>,
+  signer: Option<Signer>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PackageSolutions {
+  Plain {
+    patches: Vec<Patch>,
+  },
+  /// The patches as JSON, encrypted with ChaCha20-Poly1305 under a key derived from a
+  /// password with PBKDF2. All fields are base64.
+  Encrypted {
+    salt: String,
+    nonce: String,
+    data: String,
+  },
+}
+
+/// The reference solutions of a package, once decrypted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Solutions {
+  pub patches: Vec<Patch>,
+  /// The solution PRs, if they were kept out of the package's `prs` to keep them secret.
+  pub prs: Vec<PackagePr>,
+}
+
+/// How [`QuestPackage::build`] packs the reference solutions.
+#[derive(Clone, Debug, Default)]
+pub enum SolutionPolicy {
+  #[default]
+  Include,
+  /// Encrypt them and the solution PRs with a password, which an instructor can hand out
+  /// when learners need it.
+  Encrypt(String),
+  /// Leave them and the solution PRs out, so learners can only see them in the quest repo.
+  Omit,
+}
+
+/// Options for [`QuestPackage::build`].
+#[derive(Clone, Debug, Default)]
+pub struct PackOptions {
+  /// Embed a git bundle of the quest repo, so quests keep its real history.
+  pub bundle: bool,
+  pub solutions: SolutionPolicy,
+}
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+fn solutions_key(password: &str, salt: &[u8]) -> LessSafeKey {
+  let mut key = [0; 32];
+  pbkdf2::derive(
+    pbkdf2::PBKDF2_HMAC_SHA256,
+    NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+    salt,
+    password.as_bytes(),
+    &mut key,
+  );
+  LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+impl PackageSolutions {
+  fn encrypt(solutions: &Solutions, password: &str) -> Result<Self> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; 16];
+    let mut nonce = [0; NONCE_LEN];
+    rng
+      .fill(&mut salt)
+      .and_then(|_| rng.fill(&mut nonce))
+      .map_err(|_| anyhow!("Failed to generate random bytes"))?;
+    let mut data = serde_json::to_vec(solutions)?;
+    solutions_key(password, &salt)
+      .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+      .map_err(|_| anyhow!("Failed to encrypt solutions"))?;
+    Ok(PackageSolutions::Encrypted {
+      salt: BASE64.encode(salt),
+      nonce: BASE64.encode(nonce),
+      data: BASE64.encode(data),
+    })
+  }
+
+  pub fn is_encrypted(&self) -> bool {
+    matches!(self, PackageSolutions::Encrypted { .. })
+  }
+
+  /// The solutions, decrypted with `password` if they are encrypted. Plain solutions have
+  /// no PRs of their own, since theirs are in the package's `prs`.
+  pub fn open(&self, password: Option<&str>) -> Result<Solutions> {
+    match self {
+      PackageSolutions::Plain { patches } => Ok(Solutions {
+        patches: patches.clone(),
+        prs: Vec::new(),
+      }),
+      PackageSolutions::Encrypted { salt, nonce, data } => {
+        let password = password.context("Reference solutions are locked with a password")?;
+        let salt = BASE64.decode(salt).context("Invalid base64 in solutions")?;
+        let nonce = BASE64
+          .decode(nonce)
+          .context("Invalid base64 in solutions")?;
+        let nonce =
+          Nonce::try_assume_unique_for_key(&nonce).map_err(|_| anyhow!("Invalid nonce"))?;
+        let mut data = BASE64.decode(data).context("Invalid base64 in solutions")?;
+        let plaintext = solutions_key(password, &salt)
+          .open_in_place(nonce, Aad::empty(), &mut data)
+          .map_err(|_| anyhow!("Wrong password for reference solutions"))?;
+        serde_json::from_slice(plaintext).context("Failed to parse solutions")
+      }
+    }
+  }
+}
+
+/// The branch that stage `index` starts from.
+pub(crate) fn prev_solution(config: &QuestConfig, index: usize) -> String {
+  match index.checked_sub(1) {
+    Some(prev) => config.stages[prev].branch_name(StagePart::Solution),
+    None => "main".into(),
+  }
 }
 
 fn version() -> Version {
@@ -42,79 +390,254 @@ fn version() -> Version {
 }
 
 impl QuestPackage {
-  pub async fn build(path: &Path) -> Result<Self> {
+  pub async fn build(path: &Path, forge: &dyn Forge, options: &PackOptions) -> Result<Self> {
     let git_repo = GitRepo::new(path);
     let config = QuestConfig::load(&git_repo, None)?;
-    let gh_repo = GithubRepo::load(&config.author, &config.repo).await?;
+    let repo = forge.load_repo(&config.author, &config.repo).await?;
 
     let initial = git_repo.read_initial_files()?;
-    let issues = gh_repo.issues().clone();
-    let prs = gh_repo.prs().clone();
-    let labels = gh_repo
-      .issue_handler()
-      .list_labels_for_repo()
-      .send()
+    let issues = repo.issues().iter().map(PackageIssue::from).collect();
+    let mut prs = repo.prs().iter().map(PackagePr::from).collect::<Vec<_>>();
+    // Default labels already exist in every new repo.
+    let labels = repo
+      .labels()
       .await?
-      .take_items();
+      .iter()
+      .filter(|label| !label.default)
+      .map(PackageLabel::from)
+      .collect();
     let patches = config
       .stages
       .iter()
       .enumerate()
-      .filter(|(_, stage)| !matches!(stage.no_starter, Some(true)))
+      .filter(|(_, stage)| !stage.no_starter())
       .map(|(i, stage)| {
-        let prev_stage = (i > 0).then(|| &config.stages[i - 1]);
-        let base = match prev_stage {
-          Some(stage) => stage.branch_name(StagePart::Solution),
-          None => "main".into(),
-        };
+        let base = prev_solution(&config, i);
         let head = stage.branch_name(StagePart::Starter);
-        let patch = git_repo.diff(&base, &head)?;
+        let patch = git_repo.binary_diff(&base, &head)?;
         Ok(Patch { base, head, patch })
       })
       .collect::<Result<Vec<_>>>()?;
 
-    Ok(QuestPackage {
+    // Solution PRs would give the solutions away, so unless they are included, the PRs are
+    // encrypted with them or left out too.
+    let solution_prs = match &options.solutions {
+      SolutionPolicy::Include => Vec::new(),
+      _ => {
+        let solution_branches = config
+          .stages
+          .iter()
+          .map(|stage| stage.branch_name(StagePart::Solution))
+          .collect::<Vec<_>>();
+        let (solution_prs, others) = prs
+          .into_iter()
+          .partition(|pr: &PackagePr| solution_branches.contains(&pr.branch));
+        prs = others;
+        solution_prs
+      }
+    };
+
+    let solutions = match &options.solutions {
+      SolutionPolicy::Omit => None,
+      policy => {
+        let mut patches = Vec::new();
+        for (i, stage) in config.stages.iter().enumerate() {
+          let head = stage.branch_name(StagePart::Solution);
+          if !git_repo.has_branch(&head)? {
+            continue;
+          }
+          let base = if stage.no_starter() {
+            prev_solution(&config, i)
+          } else {
+            stage.branch_name(StagePart::Starter)
+          };
+          let patch = git_repo.binary_diff(&base, &head)?;
+          patches.push(Patch { base, head, patch });
+        }
+        Some(match policy {
+          SolutionPolicy::Encrypt(password) => {
+            let solutions = Solutions {
+              patches,
+              prs: solution_prs,
+            };
+            PackageSolutions::encrypt(&solutions, password)?
+          }
+          _ => PackageSolutions::Plain { patches },
+        })
+      }
+    };
+
+    let bundle = if options.bundle {
+      // Later starter branches contain earlier solutions, so they can't be hidden in a bundle.
+      ensure!(
+        matches!(options.solutions, SolutionPolicy::Include),
+        "Packages with a bundle always include the reference solutions"
+      );
+      let mut branches = vec!["main".to_string(), "meta".to_string()];
+      for stage in &config.stages {
+        for part in [StagePart::Starter, StagePart::Solution] {
+          let branch = stage.branch_name(part);
+          if git_repo.has_branch(&branch)? {
+            branches.push(branch);
+          }
+        }
+      }
+      Some(BASE64.encode(git_repo.create_bundle(&branches)?))
+    } else {
+      None
+    };
+
+    let mut package = QuestPackage::new(config, issues, prs, initial, patches, labels);
+    package.bundle = bundle;
+    package.solutions = solutions;
+    Ok(package)
+  }
+
+  pub fn new(
+    config: QuestConfig,
+    issues: Vec<PackageIssue>,
+    prs: Vec<PackagePr>,
+    initial: HashMap<PathBuf, PackageFile>,
+    patches: Vec<Patch>,
+    labels: Vec<PackageLabel>,
+  ) -> Self {
+    QuestPackage {
       version: version(),
+      format: PACKAGE_FORMAT,
       config,
       initial,
       issues,
       prs,
       labels,
       patches,
-      patch_map: HashMap::default(),
-    })
+      bundle: None,
+      solutions: None,
+      signature: None,
+      signer: None,
+    }
+  }
+
+  /// Signs the package with `key`, replacing any earlier signature.
+  pub fn sign(&mut self, key: &SigningKey) -> Result<()> {
+    self.signature = None;
+    let Value::Object(fields) = serde_json::to_value(&*self)? else {
+      unreachable!()
+    };
+    let signature = key.sign(&fields)?;
+    self.signer = Some(signature.signer.clone());
+    self.signature = Some(signature);
+    Ok(())
+  }
+
+  /// Who signed the package, if it was signed and its signature was verified.
+  pub fn signer(&self) -> Option<&Signer> {
+    self.signer.as_ref()
+  }
+
+  /// Checks that the package is still signed with `public_key`, the key that signed it when
+  /// the quest was created. Otherwise its signature could be removed or replaced unnoticed.
+  pub fn check_signer(&self, public_key: &str, verification: Verification) -> Result<()> {
+    let error = match &self.signer {
+      Some(signer) if signer.public_key == public_key => return Ok(()),
+      Some(signer) => anyhow!(
+        "Quest package is now signed by {} ({}), not with the key it was signed with",
+        signer.name,
+        signer.fingerprint()
+      ),
+      None => anyhow!("Quest package was signed, but its signature has been removed"),
+    };
+    match verification {
+      Verification::Strict => Err(error),
+      Verification::Warn => {
+        tracing::warn!("{error:?}");
+        Ok(())
+      }
+    }
   }
 
-  pub fn patch(&self, key: &(String, String)) -> Option<usize> {
-    self.patch_map.get(key).copied()
+  pub fn pr(&self, selector: &PullSelector) -> Option<&PackagePr> {
+    self.prs.iter().find(|pr| pr.matches(selector))
   }
 
-  fn deserialize<T: Read>(t: T) -> Result<Self> {
+  /// Decrypts the reference solutions with `password`, and puts their PRs back with the
+  /// others, as if the package had been packed with [`SolutionPolicy::Include`].
+  pub fn unlock_solutions(&mut self, password: &str) -> Result<()> {
+    let solutions = self
+      .solutions
+      .as_ref()
+      .context("This quest package has no reference solutions")?
+      .open(Some(password))?;
+    self.prs.extend(solutions.prs);
+    self.solutions = Some(PackageSolutions::Plain {
+      patches: solutions.patches,
+    });
+    Ok(())
+  }
+
+  /// The issue with the label `label`, usually a stage label.
+  pub fn issue(&self, label: &str) -> Option<&PackageIssue> {
+    self
+      .issues
+      .iter()
+      .find(|issue| issue.labels.iter().any(|l| l == label))
+  }
+
+  /// The decoded git bundle, if the package has one.
+  pub fn bundle(&self) -> Result<Option<Vec<u8>>> {
+    self
+      .bundle
+      .as_ref()
+      .map(|bundle| {
+        BASE64
+          .decode(bundle)
+          .context("Invalid base64 in package bundle")
+      })
+      .transpose()
+  }
+
+  fn deserialize<T: Read>(t: T, verification: Verification) -> Result<Self> {
     let mut decoder = GzDecoder::new(t);
+    let mut value: Value = serde_json::from_reader(&mut decoder).context("Failed to parse JSON")?;
+    let format = match value.get("format") {
+      Some(format) => format.as_u64().context("Invalid package format")?,
+      None => 1,
+    };
+    // A format too large for a u32 is still a newer one, not one to wrap around.
+    let supported = u32::try_from(format)
+      .ok()
+      .filter(|format| *format <= PACKAGE_FORMAT);
+    let Some(format) = supported else {
+      let made_with = match value.get("version").and_then(Value::as_str) {
+        Some(version) => format!("RepoQuest {version}"),
+        None => "a newer version of RepoQuest".into(),
+      };
+      bail!(
+        "This quest package was made with {made_with}, which uses package format {format}. \
+        This is RepoQuest {}, which only supports formats up to {PACKAGE_FORMAT}. \
+        Update RepoQuest to play this quest.",
+        version()
+      );
+    };
+    // Signatures are of the package as it was signed, so they're checked before migrating it.
+    let signer = verify(&mut value, verification)?;
+    migrate(&mut value, format)?;
     let mut package: QuestPackage =
-      serde_json::from_reader(&mut decoder).context("Failed to parse JSON")?;
-    package.patch_map = package
-      .patches
-      .iter()
-      .enumerate()
-      .map(|(i, patch)| ((patch.base.clone(), patch.head.clone()), i))
-      .collect();
-    let version = version();
-    let req = VersionReq::parse(&format!("^{version}")).unwrap();
-    if !req.matches(&package.version) {
-      tracing::warn!("Loaded package has potentially incompatible version: {version}");
-    }
+      serde_json::from_value(value).context("Failed to parse package")?;
+    package.signer = signer;
     Ok(package)
   }
 
+  /// Loads a package, verifying its signature as configured by [`Verification::from_env`].
   pub fn load_from_file(path: &Path) -> Result<Self> {
     let mut f = BufReader::new(File::open(path)?);
-    Self::deserialize(&mut f)
+    Self::deserialize(&mut f, Verification::from_env()?)
       .with_context(|| format!("Failed to load quest package: {}", path.display()))
   }
 
+  /// Loads a package, verifying its signature as configured by [`Verification::from_env`].
   pub fn load_from_blob(blob: &[u8]) -> Result<Self> {
-    Self::deserialize(blob).context("Failed to load quest package from blob")
+    Self::deserialize(blob, Verification::from_env()?)
+      .context("Failed to load quest package from blob")
   }
 
   pub fn save(&self, path: &Path) -> Result<()> {
@@ -124,3 +647,231 @@ impl QuestPackage {
     Ok(())
   }
 }
+
+/// Verifies the signature of a package, if it has one, and returns who signed it. With
+/// [`Verification::Warn`], a package that fails verification has its signature removed, so it
+/// is treated as unsigned.
+fn verify(package: &mut Value, verification: Verification) -> Result<Option<Signer>> {
+  let Value::Object(fields) = package else {
+    bail!("Package is not a JSON object");
+  };
+  let Some(signature) = fields.get(SIGNATURE_FIELD) else {
+    return Ok(None);
+  };
+  let result = serde_json::from_value::<PackageSignature>(signature.clone())
+    .context("Failed to parse package signature")
+    .and_then(|signature| {
+      let signer = signature.signer.clone();
+      signature.verify(fields).with_context(|| {
+        format!(
+          "Failed to verify package signed by {} ({})",
+          signer.name,
+          signer.fingerprint()
+        )
+      })?;
+      Ok(signer)
+    });
+  match (result, verification) {
+    (Ok(signer), _) => Ok(Some(signer)),
+    (Err(e), Verification::Strict) => Err(e),
+    (Err(e), Verification::Warn) => {
+      tracing::warn!("Loading package as unsigned: {e:?}");
+      fields.remove(SIGNATURE_FIELD);
+      Ok(None)
+    }
+  }
+}
+
+/// Upgrades a package of format `format` to [`PACKAGE_FORMAT`].
+fn migrate(package: &mut Value, format: u32) -> Result<()> {
+  ensure!(format >= 1, "Invalid package format: {format}");
+  if format == PACKAGE_FORMAT {
+    return Ok(());
+  }
+  for (from, migration) in (format..).zip(&MIGRATIONS[format as usize - 1..]) {
+    migration(package).with_context(|| format!("Failed to upgrade package from format {from}"))?;
+    package["format"] = (from + 1).into();
+  }
+  // The signature is of the old format, so it would no longer match when saved again.
+  package.as_object_mut().unwrap().remove(SIGNATURE_FIELD);
+  Ok(())
+}
+
+/// Converts the text-only initial tree of a format 1 package to format 2.
+fn upgrade_v1(package: &mut Value) -> Result<()> {
+  let Some(Value::Object(initial)) = package.get_mut("initial") else {
+    bail!("Package is missing its initial files");
+  };
+  for (path, file) in initial.iter_mut() {
+    let Value::String(contents) = file.take() else {
+      bail!("Invalid contents for {path}");
+    };
+    // Format 1 had no modes, so git hooks were made executable when writing them out.
+    let mode = if path.starts_with(".githooks/") {
+      FileMode::Executable
+    } else {
+      FileMode::Regular
+    };
+    *file = serde_json::to_value(PackageFile {
+      mode,
+      contents: FileContents::Text(contents),
+    })?;
+  }
+  Ok(())
+}
+
+/// Strips format 2 issues, PRs and labels, which were whole forge models, down to format 3.
+fn upgrade_v2(package: &mut Value) -> Result<()> {
+  fn label_names(item: &Value) -> Value {
+    let names = item["labels"].as_array().into_iter().flatten();
+    names.map(|label| label["name"].clone()).collect()
+  }
+
+  let Some(Value::Array(issues)) = package.get_mut("issues") else {
+    bail!("Package is missing its issues");
+  };
+  for issue in issues.iter_mut() {
+    *issue = json!({
+      "title": issue["title"],
+      "body": issue["body"],
+      "labels": label_names(issue),
+    });
+  }
+
+  let Some(Value::Array(prs)) = package.get_mut("prs") else {
+    bail!("Package is missing its PRs");
+  };
+  for pr in prs.iter_mut() {
+    let data = &pr["data"];
+    *pr = json!({
+      "title": data["title"],
+      "body": data["body"],
+      "branch": data["head"]["ref"],
+      "labels": label_names(data),
+      "html_url": data["html_url"],
+      "comments": pr["comments"],
+    });
+  }
+
+  let Some(Value::Array(labels)) = package.get_mut("labels") else {
+    bail!("Package is missing its labels");
+  };
+  labels.retain(|label| label["default"] != true);
+  for label in labels.iter_mut() {
+    if let Value::Object(label) = label {
+      label.remove("default");
+      if label.get("description") == Some(&Value::Null) {
+        label.remove("description");
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use flate2::write::GzEncoder;
+  use std::{fs, io::Write};
+
+  /// A package of each historical format, from `fixtures/packages`.
+  fn fixture(format: u32) -> Result<Value> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("fixtures/packages")
+      .join(format!("format-{format}.json"));
+    let contents = fs::read_to_string(&path)
+      .with_context(|| format!("Missing fixture for format {format}: {}", path.display()))?;
+    Ok(serde_json::from_str(&contents)?)
+  }
+
+  fn gzip(package: &Value) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(package.to_string().as_bytes())?;
+    Ok(encoder.finish()?)
+  }
+
+  /// Every format can be loaded, and saves as the current format without losing anything.
+  #[test]
+  fn migrate_formats() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    for format in 1..=PACKAGE_FORMAT {
+      let package = QuestPackage::load_from_blob(&gzip(&fixture(format)?)?)?;
+      assert_eq!(package.format, PACKAGE_FORMAT);
+      let path = dir.path().join(format!("format-{format}.json.gz"));
+      package.save(&path)?;
+      let reloaded = QuestPackage::load_from_file(&path)?;
+      assert_eq!(
+        serde_json::to_value(&package)?,
+        serde_json::to_value(&reloaded)?,
+        "format {format} does not round-trip"
+      );
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn migrate_format_1() -> Result<()> {
+    let package = QuestPackage::load_from_blob(&gzip(&fixture(1)?)?)?;
+    assert_eq!(
+      package.initial[Path::new("README.md")],
+      PackageFile::regular("Hello\n")
+    );
+    assert_eq!(
+      package.initial[Path::new(".githooks/post-checkout")].mode,
+      FileMode::Executable
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn migrate_format_2() -> Result<()> {
+    let migrated = QuestPackage::load_from_blob(&gzip(&fixture(2)?)?)?;
+    let expected = QuestPackage::load_from_blob(&gzip(&fixture(3)?)?)?;
+    assert_eq!(
+      serde_json::to_value(&migrated)?,
+      serde_json::to_value(&expected)?
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn newer_format() -> Result<()> {
+    let mut package = fixture(PACKAGE_FORMAT)?;
+    package["format"] = (PACKAGE_FORMAT + 1).into();
+    package["version"] = "99.0.0".into();
+    let err = QuestPackage::load_from_blob(&gzip(&package)?)
+      .err()
+      .unwrap();
+    assert!(format!("{err:?}").contains("This quest package was made with RepoQuest 99.0.0"));
+
+    // Formats that don't fit in a u32 mustn't wrap around to a supported one.
+    package["format"] = (u64::from(u32::MAX) + 2).into();
+    let err = QuestPackage::load_from_blob(&gzip(&package)?)
+      .err()
+      .unwrap();
+    assert!(format!("{err:?}").contains("which uses package format 4294967297"));
+    Ok(())
+  }
+
+  #[test]
+  fn verify_signature() -> Result<()> {
+    let key = SigningKey::generate("Test Author")?;
+    let mut package = fixture(PACKAGE_FORMAT)?;
+    let signature = key.sign(package.as_object().unwrap())?;
+    package[SIGNATURE_FIELD] = serde_json::to_value(signature)?;
+    let loaded = QuestPackage::deserialize(gzip(&package)?.as_slice(), Verification::Strict)?;
+    assert_eq!(loaded.signer(), Some(&key.public()?));
+
+    package["initial"]["README.md"]["contents"]["text"] = "Goodbye\n".into();
+    let tampered = gzip(&package)?;
+    let err = QuestPackage::deserialize(tampered.as_slice(), Verification::Strict)
+      .err()
+      .unwrap();
+    assert!(format!("{err:?}").contains("Package field `initial` was changed after it was signed"));
+
+    // In warn mode, the package is loaded as if it were unsigned.
+    let loaded = QuestPackage::deserialize(tampered.as_slice(), Verification::Warn)?;
+    assert!(loaded.signer().is_none());
+    Ok(())
+  }
+}
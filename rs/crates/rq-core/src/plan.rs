@@ -0,0 +1,71 @@
+//! Previews of what a quest action would do, without changing the learner's repo.
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use crate::git::MergeType;
+
+/// An action that changes the learner's repo, which can be planned with
+/// [`Quest::plan`](crate::quest::Quest::plan) before it is run.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(tag = "type")]
+pub enum QuestAction {
+  FileFeatureAndIssue { stage: u32 },
+  FileSolution { stage: u32 },
+  SkipToStage { stage: u32 },
+}
+
+/// Where the changes on a new branch come from.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct PatchPlan {
+  /// A description of the changes, e.g. the range of upstream commits to cherry-pick.
+  pub source: String,
+  pub diff: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(tag = "type")]
+pub enum PlanStep {
+  /// Save `rev` to a backup branch for `stage`.
+  Backup {
+    stage: String,
+    rev: String,
+  },
+  /// Create `branch` from main with the changes from `base` to `branch` in the quest.
+  CreateBranch {
+    branch: String,
+    base: String,
+    patch: PatchPlan,
+    merge_type: MergeType,
+  },
+  /// Hard reset main to `target` and force-push it.
+  ResetMain {
+    target: String,
+  },
+  FilePr {
+    head: String,
+    title: String,
+    body: String,
+    labels: Vec<String>,
+  },
+  /// File an issue. References in the body to PRs filed earlier in the same plan
+  /// are left unresolved, since those PRs don't exist yet.
+  FileIssue {
+    title: String,
+    body: String,
+    labels: Vec<String>,
+  },
+  CloseIssue {
+    title: String,
+  },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct ActionPlan {
+  pub action: QuestAction,
+  pub steps: Vec<PlanStep>,
+  /// Whether the merge types of [`PlanStep::CreateBranch`] may be wrong, because origin's main
+  /// has changed on the forge since it was last fetched, or the forge couldn't be reached.
+  /// Planning doesn't fetch, so that it changes nothing in the learner's repo.
+  pub stale: bool,
+}
@@ -1,26 +1,36 @@
-use std::{borrow::Cow, collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+  borrow::Cow,
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
 
 use crate::{
-  git::{GitRepo, UPSTREAM},
-  github::{load_user, GithubRepo, PullSelector},
+  create::{resolve_existing, CreateProgress, CreateStep, RepoTarget},
+  forge::{
+    copied_pr_body, copied_pr_labels, Forge, ForgeRepo, GitProtocol, Issue, IssueState, Label,
+    PullRequest, PullSelector, RESET_LABEL, REWOUND_LABEL,
+  },
+  git::{GitRepo, MergeType, UPSTREAM},
+  journal::{Journal, JournalEvent, StageRecord},
   package::QuestPackage,
+  plan::{ActionPlan, PlanStep, QuestAction},
+  sign::Verification,
   stage::{Stage, StagePart, StagePartStatus},
-  template::{InstanceOutputs, PackageTemplate, QuestTemplate, RepoTemplate},
-};
-use anyhow::{Context, Result};
-use http::StatusCode;
-use octocrab::{
-  models::{issues::Issue, pulls::PullRequest, IssueState},
-  params::{issues, pulls, Direction},
-  GitHubError,
+  template::{self, PackageTemplate, QuestTemplate, RepoTemplate},
 };
+use anyhow::{bail, ensure, Context, Result};
+use parking_lot::Mutex;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use specta::Type;
-use tokio::{time::sleep, try_join};
+use tokio::time::sleep;
 
 pub trait StateEmitter: Send + Sync + 'static {
   fn emit(&self, state: StateDescriptor) -> Result<()>;
+  /// Reports that [`Quest::infer_state_loop`] failed to update the state. It keeps polling.
+  fn emit_error(&self, _error: &anyhow::Error) {}
 }
 
 pub struct NoopEmitter;
@@ -42,13 +52,27 @@ pub struct QuestConfig {
   pub r#final: Option<serde_json::Value>,
 }
 
+/// Where the learner can see the reference solution of a stage.
+#[derive(Serialize, Deserialize, Type, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", content = "value")]
+pub enum ReferenceSolution {
+  /// The solution PR in the quest repo, at this URL.
+  Pr(String),
+  /// The solution in the quest package, shown by [`Quest::reference_solution`].
+  Package,
+}
+
 #[derive(Serialize, Deserialize, Type, Clone)]
 pub struct StageState {
-  stage: Stage,
-  issue_url: Option<String>,
-  feature_pr_url: Option<String>,
-  solution_pr_url: Option<String>,
-  reference_solution_pr_url: Option<String>,
+  pub stage: Stage,
+  pub issue_url: Option<String>,
+  pub feature_pr_url: Option<String>,
+  pub solution_pr_url: Option<String>,
+  pub reference_solution: Option<ReferenceSolution>,
+  pub feature_pr_conflict: bool,
+  pub solution_pr_conflict: bool,
+  /// Branches holding the learner's code from before RepoQuest overwrote it.
+  pub backups: Vec<String>,
 }
 
 impl QuestConfig {
@@ -75,28 +99,61 @@ pub enum QuestState {
   Completed,
 }
 
+/// What code to restore when rewinding to a stage.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Type, PartialEq, Eq)]
+pub enum RewindTarget {
+  /// The learner's own code from when they started the stage.
+  Own,
+  /// The reference solution of the stage before it.
+  Reference,
+}
+
 pub struct Quest {
   template: Box<dyn QuestTemplate>,
-  origin: GithubRepo,
+  origin: Box<dyn ForgeRepo>,
   origin_git: GitRepo,
   stage_index: HashMap<String, usize>,
   dir: PathBuf,
   state_event: Box<dyn StateEmitter>,
+  journal: Mutex<Journal>,
 
   pub config: QuestConfig,
 }
 
 #[derive(Serialize, Deserialize, Clone, Type)]
 pub struct StateDescriptor {
-  dir: PathBuf,
-  stages: Vec<StageState>,
-  state: QuestState,
-  can_skip: bool,
+  pub dir: PathBuf,
+  pub stages: Vec<StageState>,
+  pub state: QuestState,
+  pub can_skip: bool,
+  /// Whether the quest has reference solutions that need a password to unlock.
+  pub solutions_locked: bool,
 }
 
 pub enum CreateSource {
   Remote { user: String, repo: String },
-  Package(QuestPackage),
+  Package(Box<QuestPackage>),
+}
+
+/// What to do when the learner already has a repo or directory with the new repo's name.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Type, PartialEq, Eq)]
+pub enum ExistingRepo {
+  /// Stop with an error, so the learner can choose one of the others.
+  #[default]
+  Fail,
+  /// Use the existing repo, if it was made from the same quest, and its directory if cloned.
+  Adopt,
+  /// Create the repo under the first free name with a `-2`, `-3`, ... suffix.
+  Rename,
+  /// Delete the existing repo and directory, then create them again.
+  Replace,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+pub struct CreateOptions {
+  /// The name of the learner's repo, if not the quest's own.
+  pub repo_name: Option<String>,
+  pub existing: ExistingRepo,
 }
 
 impl Quest {
@@ -105,7 +162,7 @@ impl Quest {
     config: QuestConfig,
     state_event: Box<dyn StateEmitter>,
     template: Box<dyn QuestTemplate>,
-    origin: GithubRepo,
+    origin: Box<dyn ForgeRepo>,
     origin_git: GitRepo,
   ) -> Result<Self> {
     let stage_index = config
@@ -115,6 +172,13 @@ impl Quest {
       .map(|(i, stage)| (stage.label.clone(), i))
       .collect::<HashMap<_, _>>();
 
+    let journal = Journal::load(&origin_git).context("Failed to load quest journal")?;
+    let needs_bootstrap = journal.is_none();
+    let journal = match journal {
+      Some(journal) => journal,
+      None => Journal::new(&origin_git)?,
+    };
+
     let q = Quest {
       dir,
       config,
@@ -123,65 +187,203 @@ impl Quest {
       origin_git,
       stage_index,
       state_event,
+      journal: Mutex::new(journal),
     };
 
+    if needs_bootstrap {
+      q.bootstrap_journal()
+        .await
+        .context("Failed to reconstruct quest journal")?;
+    }
+
     q.infer_state_update().await?;
 
     Ok(q)
   }
 
   pub async fn create(
+    forge: Arc<dyn Forge>,
     dir: PathBuf,
     source: CreateSource,
+    options: CreateOptions,
     state_event: Box<dyn StateEmitter>,
   ) -> Result<Self> {
     let template: Box<dyn QuestTemplate> = match source {
       CreateSource::Remote { user, repo } => {
-        let upstream = GithubRepo::load(&user, &repo).await?;
-        Box::new(RepoTemplate(upstream))
+        let upstream = forge.load_repo(&user, &repo).await?;
+        Box::new(RepoTemplate {
+          forge: forge.clone(),
+          repo: upstream,
+        })
+      }
+      CreateSource::Package(package) => {
+        Box::new(PackageTemplate::new(forge.clone(), *package, None))
       }
-      CreateSource::Package(package) => Box::new(PackageTemplate(package)),
     };
 
-    let InstanceOutputs {
-      origin,
-      origin_git,
-      config,
-    } = template.instantiate(&dir).await?;
+    let name = options
+      .repo_name
+      .unwrap_or_else(|| template.repo_name().to_string());
+    let target = if CreateProgress::exists(&dir, &name) {
+      RepoTarget::Create(name)
+    } else {
+      let user = forge.current_user().await?;
+      resolve_existing(&*forge, &user, &dir, &name, options.existing).await?
+    };
+
+    let (origin, origin_git, progress) = match target {
+      RepoTarget::Adopt(name) => {
+        let (origin, origin_git) = Self::adopt(&*forge, &*template, &dir, &name).await?;
+        (origin, origin_git, None)
+      }
+      RepoTarget::Create(name) => {
+        let mut progress = CreateProgress::load(&dir, &name)?;
+        let (origin, origin_git) = match (progress.step(), progress.owner()) {
+          (Some(CreateStep::Initialized), Some(owner)) => {
+            tracing::info!("Resuming creation of quest repo {owner}/{name}");
+            let origin = forge
+              .load_repo(owner, &name)
+              .await
+              .context("Failed to load origin repo")?;
+            (origin, GitRepo::new(&dir.join(&name)))
+          }
+          _ => {
+            progress
+              .rollback(&*forge)
+              .await
+              .context("Failed to clean up an earlier attempt to create the quest")?;
+            match Self::instantiate(&*template, &dir, &name, &mut progress).await {
+              Ok(outputs) => outputs,
+              Err(e) => {
+                if let Err(rollback_err) = progress.rollback(&*forge).await {
+                  tracing::warn!("Failed to roll back quest creation: {rollback_err:?}");
+                }
+                return Err(e);
+              }
+            }
+          }
+        };
+        (origin, origin_git, Some(progress))
+      }
+    };
 
     origin_git.install_hooks()?;
+    let config = template.config(&origin_git)?;
 
-    Self::load_core(
-      dir.join(&config.repo),
+    let quest = Self::load_core(
+      origin_git.path().to_path_buf(),
       config,
       state_event,
       template,
       origin,
       origin_git,
     )
-    .await
+    .await?;
+    if let Some(progress) = progress {
+      progress.finish()?;
+    }
+    Ok(quest)
   }
 
-  pub async fn load(dir: PathBuf, state_event: Box<dyn StateEmitter>) -> Result<Self> {
-    let user = load_user().await?;
-    let origin_git = GitRepo::new(&dir);
-    let config = QuestConfig::load(&origin_git, None).context("Failed to load quest config")?;
-    let origin = GithubRepo::load(&user, &config.repo)
+  /// Loads the learner's existing repo `name`, and clones it unless `dir` already has a clone.
+  async fn adopt(
+    forge: &dyn Forge,
+    template: &dyn QuestTemplate,
+    dir: &Path,
+    name: &str,
+  ) -> Result<(Box<dyn ForgeRepo>, GitRepo)> {
+    let user = forge.current_user().await?;
+    let origin = forge
+      .load_repo(&user, name)
       .await
-      .context("Failed to load GitHub repo")?;
+      .context("Failed to load origin repo")?;
+
+    let repo_dir = dir.join(name);
+    let origin_git = if repo_dir.exists() {
+      let origin_git = GitRepo::new(&repo_dir);
+      let url = origin_git.remote_url("origin")?;
+      let remotes = [
+        origin.remote(GitProtocol::Ssh),
+        origin.remote(GitProtocol::Https),
+      ];
+      ensure!(
+        url.is_some_and(|url| remotes.contains(&url)),
+        "{} is not a clone of {user}/{name}",
+        repo_dir.display()
+      );
+      origin_git
+    } else {
+      origin.clone(dir).context("Failed to clone repo")?
+    };
+
+    template
+      .adopt_clone(&origin_git)
+      .with_context(|| format!("Cannot adopt {user}/{name}"))?;
+    origin_git.set_repo_name(name)?;
+    Ok((origin, origin_git))
+  }
+
+  /// Creates and clones the learner's repo, recording each step in `progress`.
+  async fn instantiate(
+    template: &dyn QuestTemplate,
+    dir: &Path,
+    name: &str,
+    progress: &mut CreateProgress,
+  ) -> Result<(Box<dyn ForgeRepo>, GitRepo)> {
+    let origin = template.create_origin(name).await?;
+    progress.repo_created(origin.owner())?;
+
+    template.copy_labels(&*origin).await?;
+    progress.advance(CreateStep::LabelsCopied)?;
+
+    let origin_git = origin.clone(dir).context("Failed to clone repo")?;
+    progress.advance(CreateStep::Cloned)?;
+
+    template.init_clone(&origin_git)?;
+    origin_git.set_repo_name(name)?;
+    progress.advance(CreateStep::Initialized)?;
+
+    Ok((origin, origin_git))
+  }
+
+  pub async fn load(
+    forge: Arc<dyn Forge>,
+    dir: PathBuf,
+    state_event: Box<dyn StateEmitter>,
+  ) -> Result<Self> {
+    let user = forge.current_user().await?;
+    let origin_git = GitRepo::new(&dir);
     let has_upstream = origin_git
       .has_upstream()
       .context("Failed to test for upstream")?;
+    // Only package quests have a local meta branch, others read it from the upstream.
+    let config = QuestConfig::load(&origin_git, has_upstream.then_some(UPSTREAM))
+      .context("Failed to load quest config")?;
+    let name = origin_git
+      .repo_name()?
+      .unwrap_or_else(|| config.repo.clone());
+    let origin = forge
+      .load_repo(&user, &name)
+      .await
+      .context("Failed to load origin repo")?;
     let template: Box<dyn QuestTemplate> = if has_upstream {
-      let upstream = GithubRepo::load(&config.author, &config.repo)
+      let upstream = forge
+        .load_repo(&config.author, &config.repo)
         .await
-        .context("Failed to load upstream GitHub repo")?;
-      Box::new(RepoTemplate(upstream))
+        .context("Failed to load upstream repo")?;
+      Box::new(RepoTemplate {
+        forge,
+        repo: upstream,
+      })
     } else {
       let contents = origin_git.show_bin("meta", "package.json.gz")?;
       let package =
         QuestPackage::load_from_blob(&contents).context("Failed to load quest package")?;
-      Box::new(PackageTemplate(package))
+      if let Some(public_key) = origin_git.signer_key()? {
+        package.check_signer(&public_key, Verification::from_env()?)?;
+      }
+      let unlocked = template::unlocked_solutions(&origin_git)?;
+      Box::new(PackageTemplate::new(forge, package, unlocked))
     };
 
     Self::load_core(dir, config, state_event, template, origin, origin_git).await
@@ -205,94 +407,48 @@ impl Quest {
   }
 
   async fn infer_state(&self) -> Result<QuestState> {
-    let pr_handler = self.origin.pr_handler();
-    let pr_page_future = pr_handler
-      .list()
-      .state(octocrab::params::State::All)
-      .sort(pulls::Sort::Created)
-      .direction(Direction::Descending)
-      .per_page(10)
-      .send();
-
-    let issue_handler = self.origin.issue_handler();
-    let issue_page_future = issue_handler
-      .list()
-      .state(octocrab::params::State::All)
-      .sort(issues::Sort::Created)
-      .direction(Direction::Descending)
-      .per_page(10)
-      .send();
-
-    let (mut pr_page, mut issue_page) = match try_join!(pr_page_future, issue_page_future) {
-      Ok(result) => result,
-      Err(octocrab::Error::GitHub {
-        source: GitHubError {
-          status_code: StatusCode::NOT_FOUND,
-          ..
-        },
-        ..
-      }) => {
-        return Ok(QuestState::Ongoing {
-          stage: 0,
-          part: StagePart::Starter,
-          status: StagePartStatus::Start,
-        })
+    // The journal says which PRs and issues belong to the quest, and the forge says
+    // whether they have been merged or closed. Only those are refetched, so polling
+    // costs a few requests however busy the repo is.
+    let records = self.journal.lock().stage_records();
+    let prs = records
+      .values()
+      .flat_map(|record| [record.starter_pr, record.solution_pr])
+      .flatten()
+      .collect::<Vec<_>>();
+    let issues = records
+      .values()
+      .filter_map(|record| record.issue)
+      .collect::<Vec<_>>();
+    self.origin.refresh(&prs, &issues).await?;
+
+    let mut parts = Vec::new();
+    for (label, record) in &records {
+      let Some(&stage_idx) = self.stage_index.get(label) else {
+        continue;
+      };
+      let stage = self.stage(stage_idx);
+      let issue_closed = self
+        .journal_issue(record.issue)
+        .map(|issue| matches!(issue.state, IssueState::Closed));
+      match issue_closed {
+        Some(true) => parts.push((stage_idx, StagePart::Solution, true)),
+        Some(false) => parts.push((stage_idx, StagePart::Starter, stage.no_starter())),
+        None => {}
       }
-      Err(e) => return Err(e.into()),
-    };
-
-    let prs = pr_page.take_items();
-    let issues = issue_page.take_items();
-
-    let issue_map = issues
-      .into_iter()
-      .filter_map(|issue| {
-        let label = issue.labels.first()?;
-        if issue.pull_request.is_none() {
-          Some((label.name.clone(), issue))
-        } else {
-          None
-        }
-      })
-      .collect::<HashMap<_, _>>();
-
-    let stage_map = self
-      .stages()
-      .iter()
-      .map(|stage| (stage.label.clone(), stage))
-      .collect::<HashMap<_, _>>();
-
-    let pr_stages = prs.iter().filter_map(|pr| {
-      let (stage, part) = self.parse_stage(pr)?;
-      let finished = pr.merged_at.is_some()
-        && match part {
-          StagePart::Solution => {
-            let issue = issue_map.get(&stage.label)?;
-            matches!(issue.state, IssueState::Closed)
-          }
-          StagePart::Starter => true,
-        };
-      Some((stage, part, finished))
-    });
-
-    let issue_stages = issue_map.iter().filter_map(|(label, issue)| {
-      let stage = (*stage_map.get(label)?).clone();
-      Some(if matches!(issue.state, IssueState::Closed) {
-        (stage, StagePart::Solution, true)
-      } else {
-        let no_starter = stage.no_starter();
-        (stage, StagePart::Starter, no_starter)
-      })
-    });
+      if let Some(pr) = self.journal_pr(record.starter_pr) {
+        parts.push((stage_idx, StagePart::Starter, pr.is_merged()));
+      }
+      if let Some(pr) = self.journal_pr(record.solution_pr) {
+        let finished = pr.is_merged() && issue_closed == Some(true);
+        parts.push((stage_idx, StagePart::Solution, finished));
+      }
+    }
+    self.report_drift();
 
-    tracing::trace!("PRs: {:#?}", pr_stages.clone().collect::<Vec<_>>());
-    tracing::trace!("Issues: {:#?}", issue_stages.clone().collect::<Vec<_>>());
+    tracing::trace!("Stage parts: {parts:#?}");
 
-    let stage_idx = |stage: &Stage| self.stage_index[&stage.label];
-    let Some((stage, part, finished)) = pr_stages
-      .chain(issue_stages)
-      .max_by_key(|(stage, part, finished)| (stage_idx(stage), *part, *finished))
-    else {
+    let Some((stage, part, finished)) = parts.into_iter().max() else {
       return Ok(QuestState::Ongoing {
         stage: 0,
         part: StagePart::Starter,
@@ -300,8 +456,6 @@ impl Quest {
       });
     };
 
-    let stage = stage_idx(&stage);
-
     Ok(if finished {
       match part.next_part() {
         Some(next_part) => QuestState::Ongoing {
@@ -330,6 +484,127 @@ impl Quest {
     })
   }
 
+  /// The issue numbered `number` from the quest journal, if it is still in the repo.
+  fn journal_issue(&self, number: Option<u64>) -> Option<Issue> {
+    let number = number?;
+    self
+      .origin
+      .issues()
+      .iter()
+      .find(|issue| issue.number == number)
+      .cloned()
+  }
+
+  /// The PR numbered `number` from the quest journal, if it is still in the repo.
+  fn journal_pr(&self, number: Option<u64>) -> Option<PullRequest> {
+    let number = number?;
+    self
+      .origin
+      .prs()
+      .iter()
+      .find(|pr| pr.data.number == number)
+      .map(|pr| pr.data.clone())
+  }
+
+  fn stage_record(&self, stage_index: usize) -> StageRecord {
+    let label = &self.stage(stage_index).label;
+    let records = self.journal.lock().stage_records();
+    records.get(label).copied().unwrap_or_default()
+  }
+
+  fn record(&self, event: JournalEvent) -> Result<()> {
+    self.journal.lock().record([event])
+  }
+
+  /// Saves the learner's code at `rev` to a backup branch before RepoQuest overwrites it.
+  fn backup(&self, stage_index: usize, rev: &str) -> Result<()> {
+    let stage = self.stage(stage_index).label.clone();
+    let branch = self
+      .origin_git
+      .backup(&stage, rev)
+      .with_context(|| format!("Failed to back up {rev}"))?;
+    tracing::info!("Backed up {rev} to {branch}");
+    self.record(JournalEvent::BackedUp { stage, branch })
+  }
+
+  /// The stage the learner is on, or the last stage if the quest is completed.
+  async fn current_stage(&self) -> Result<usize> {
+    Ok(match self.infer_state().await? {
+      QuestState::Ongoing { stage, .. } => stage as usize,
+      QuestState::Completed => self.stages().len() - 1,
+    })
+  }
+
+  /// Logs where the repo has drifted from the journal: PRs and issues filed by the quest
+  /// that have been deleted, and ones that look like part of the quest but were not filed
+  /// by it, such as a learner's own branch named like a stage.
+  fn report_drift(&self) {
+    let journal = self.journal.lock();
+    for (label, record) in journal.stage_records() {
+      for pr in [record.starter_pr, record.solution_pr]
+        .into_iter()
+        .flatten()
+      {
+        if self.journal_pr(Some(pr)).is_none() {
+          tracing::warn!("PR #{pr} for stage {label} is missing from the repo");
+        }
+      }
+      if let Some(issue) = record.issue {
+        if self.journal_issue(Some(issue)).is_none() {
+          tracing::warn!("Issue #{issue} for stage {label} is missing from the repo");
+        }
+      }
+    }
+    for pr in self.origin.prs().iter() {
+      if self.parse_stage(&pr.data).is_some() && !journal.has_pr(pr.data.number) {
+        tracing::debug!("PR #{} is not in the quest journal", pr.data.number);
+      }
+    }
+    for issue in self.origin.issues().iter() {
+      let has_stage_label =
+        (issue.labels.iter()).any(|label| self.stage_index.contains_key(&label.name));
+      if has_stage_label && !journal.has_issue(issue.number) {
+        tracing::debug!("Issue #{} is not in the quest journal", issue.number);
+      }
+    }
+  }
+
+  /// Reconstructs the journal of a quest started before journals existed,
+  /// from the branch names and labels of the repo's PRs and issues.
+  async fn bootstrap_journal(&self) -> Result<()> {
+    self.origin.fetch().await?;
+    let mut events = Vec::new();
+    for pr in self.origin.prs().iter() {
+      let Some((stage, part)) = self.parse_stage(&pr.data) else {
+        continue;
+      };
+      let (stage, pr) = (stage.label, pr.data.number);
+      let event = match part {
+        StagePart::Starter => JournalEvent::StarterFiled { stage, pr },
+        StagePart::Solution => JournalEvent::SolutionFiled { stage, pr },
+      };
+      events.push((pr, event));
+    }
+    for issue in self.origin.issues().iter() {
+      let Some(label) = issue.labels.first() else {
+        continue;
+      };
+      if self.stage_index.contains_key(&label.name) {
+        let event = JournalEvent::IssueFiled {
+          stage: label.name.clone(),
+          issue: issue.number,
+        };
+        events.push((issue.number, event));
+      }
+    }
+    // Later items should win, and numbers increase over time.
+    events.sort_by_key(|(number, _)| *number);
+    self
+      .journal
+      .lock()
+      .record(events.into_iter().map(|(_, event)| event))
+  }
+
   pub async fn state_descriptor(&self) -> Result<StateDescriptor> {
     let state = self.infer_state().await?;
     Ok(StateDescriptor {
@@ -337,11 +612,11 @@ impl Quest {
       stages: self.stage_states(),
       state,
       can_skip: self.template.can_skip(),
+      solutions_locked: self.template.solutions_locked(),
     })
   }
 
   pub async fn infer_state_update(&self) -> Result<()> {
-    self.origin.fetch().await?;
     let state = self.state_descriptor().await?;
     self.state_event.emit(state)?;
 
@@ -350,12 +625,31 @@ impl Quest {
 
   pub async fn infer_state_loop(&self) {
     loop {
-      self.infer_state_update().await.unwrap();
+      self.poll_state().await;
       sleep(Duration::from_secs(10)).await;
     }
   }
 
-  async fn file_pr(&self, base_branch: &str, target_branch: &str) -> Result<PullRequest> {
+  /// Updates the state for [`Quest::infer_state_loop`], reporting errors instead of returning
+  /// them, since e.g. a network outage should only delay the next update.
+  async fn poll_state(&self) {
+    if let Err(e) = self.clean_up_rewinds().await {
+      tracing::warn!("Failed to clean up after rewinding: {e:?}");
+    }
+    if let Err(e) = self.infer_state_update().await {
+      tracing::warn!("Failed to update quest state: {e:?}");
+      self.state_event.emit_error(&e);
+    }
+  }
+
+  async fn file_pr(
+    &self,
+    stage_index: usize,
+    part: StagePart,
+    base_branch: &str,
+  ) -> Result<PullRequest> {
+    let stage = self.stage(stage_index);
+    let target_branch = &stage.branch_name(part);
     self
       .origin_git
       .checkout_main_and_pull()
@@ -370,12 +664,43 @@ impl Quest {
       .template
       .pull_request(&PullSelector::Branch(target_branch.into()))
       .with_context(|| format!("Failed to fetch pull request for {target_branch}"))?;
+    let merge_event = match &merge_type {
+      MergeType::Success => None,
+      MergeType::SolutionReset | MergeType::StarterReset => {
+        // The reset only touched the new branch, but merging it will overwrite main.
+        self.backup(stage_index, "main")?;
+        Some(JournalEvent::Reset {
+          stage: stage.label.clone(),
+          part,
+        })
+      }
+      MergeType::Conflict(files) => Some(JournalEvent::Conflicted {
+        stage: stage.label.clone(),
+        part,
+        files: files.clone(),
+      }),
+    };
     let new_pr = self
       .origin
       .copy_pr(&pr, &branch_head, merge_type)
       .await
       .context("Failed to copy PR to repo")?;
 
+    let (label, number) = (stage.label.clone(), new_pr.number);
+    self.record(match part {
+      StagePart::Starter => JournalEvent::StarterFiled {
+        stage: label,
+        pr: number,
+      },
+      StagePart::Solution => JournalEvent::SolutionFiled {
+        stage: label,
+        pr: number,
+      },
+    })?;
+    if let Some(event) = merge_event {
+      self.record(event)?;
+    }
+
     tracing::debug!("Filed PR: {base_branch} -> {target_branch}");
 
     Ok(new_pr)
@@ -392,25 +717,46 @@ impl Quest {
       .copy_issue(&issue)
       .await
       .context("Failed to copy issue to repo")?;
+    self.record(JournalEvent::IssueFiled {
+      stage: stage.label.clone(),
+      issue: new_issue.number,
+    })?;
     self.infer_state_update().await?;
     Ok(new_issue)
   }
 
-  pub async fn file_feature_and_issue(
-    &self,
-    stage_index: usize,
-  ) -> Result<(Option<PullRequest>, Issue)> {
+  /// The branch that the PR for `part` of a stage builds on.
+  fn base_branch(&self, stage_index: usize, part: StagePart) -> String {
     let stage = self.stage(stage_index);
-    let base_branch = if stage_index > 0 {
+    if part == StagePart::Solution && !stage.no_starter() {
+      stage.branch_name(StagePart::Starter)
+    } else if stage_index > 0 {
       let prev_stage = self.stage(stage_index - 1);
       prev_stage.branch_name(StagePart::Solution)
     } else {
       "main".into()
-    };
+    }
+  }
+
+  pub async fn file_feature_and_issue(
+    &self,
+    stage_index: usize,
+  ) -> Result<(Option<PullRequest>, Issue)> {
+    let stage = self.stage(stage_index);
+    let base_branch = self.base_branch(stage_index, StagePart::Starter);
+
+    self
+      .origin_git
+      .checkout_main_and_pull()
+      .context("Failed to checkout main and pull")?;
+    self.record(JournalEvent::StageStarted {
+      stage: stage.label.clone(),
+      commit: self.origin_git.head_commit()?,
+    })?;
 
     let pr = if !stage.no_starter() {
       let pr = self
-        .file_pr(&base_branch, &stage.branch_name(StagePart::Starter))
+        .file_pr(stage_index, StagePart::Starter, &base_branch)
         .await
         .context("Failed to file starter PR")?;
       Some(pr)
@@ -429,20 +775,13 @@ impl Quest {
   }
 
   pub async fn file_solution(&self, stage_index: usize) -> Result<PullRequest> {
-    let stage = self.stage(stage_index);
-    let base = if stage.no_starter() {
-      // TODO: repeats w/ file_feature
-      if stage_index > 0 {
-        let prev_stage = self.stage(stage_index - 1);
-        prev_stage.branch_name(StagePart::Solution)
-      } else {
-        "main".into()
-      }
-    } else {
-      stage.branch_name(StagePart::Starter)
-    };
+    ensure!(
+      !self.template.solutions_locked(),
+      "The reference solutions are locked, ask your instructor for the password"
+    );
+    let base = self.base_branch(stage_index, StagePart::Solution);
     let pr = self
-      .file_pr(&base, &stage.branch_name(StagePart::Solution))
+      .file_pr(stage_index, StagePart::Solution, &base)
       .await
       .context("Failed to file solution PR")?;
 
@@ -451,147 +790,454 @@ impl Quest {
     Ok(pr)
   }
 
+  /// The diff of the reference solution of a stage, from the code the stage starts with.
+  pub fn reference_solution(&self, stage_index: usize) -> Result<String> {
+    ensure!(
+      !self.template.solutions_locked(),
+      "The reference solutions are locked, ask your instructor for the password"
+    );
+    let base = self.base_branch(stage_index, StagePart::Solution);
+    let target = self.stage(stage_index).branch_name(StagePart::Solution);
+    let plan = self.template.plan_patch(&self.origin_git, &base, &target)?;
+    Ok(plan.diff)
+  }
+
   pub fn stage_states(&self) -> Vec<StageState> {
+    let records = self.journal.lock().stage_records();
+    let mut backups = self.journal.lock().backups();
     self
       .stages()
       .iter()
       .map(|stage| {
-        let issue_url = self
-          .origin
-          .issue(&stage.label)
-          .map(|issue| issue.html_url.to_string());
-
-        let feature_pr_url = self
-          .origin
-          .pr(&PullSelector::Branch(stage.branch_name(StagePart::Starter)))
-          .map(|pr| pr.data.html_url.as_ref().unwrap().to_string());
-
-        let solution_pr_url = self
-          .origin
-          .pr(&PullSelector::Branch(
-            stage.branch_name(StagePart::Solution),
-          ))
-          .map(|pr| pr.data.html_url.as_ref().unwrap().to_string());
-
-        let reference_solution_pr_url = self.template.reference_solution_pr_url(stage);
+        let record = records.get(&stage.label).copied().unwrap_or_default();
+        let pr_url = |number| self.journal_pr(number).map(|pr| pr.html_url);
+        let reference_solution = self.template.reference_solution(stage);
 
         StageState {
           stage: stage.clone(),
-          issue_url,
-          feature_pr_url,
-          solution_pr_url,
-          reference_solution_pr_url,
+          issue_url: self.journal_issue(record.issue).map(|issue| issue.html_url),
+          feature_pr_url: pr_url(record.starter_pr),
+          solution_pr_url: pr_url(record.solution_pr),
+          reference_solution,
+          feature_pr_conflict: record.starter_conflict,
+          solution_pr_conflict: record.solution_conflict,
+          backups: backups.remove(&stage.label).unwrap_or_default(),
         }
       })
       .collect()
   }
 
-  pub async fn skip_to_stage(&self, stage_index: usize) -> Result<()> {
-    let prev_stage = self.stage(stage_index - 1);
-    let branch = format!("{UPSTREAM}/{}", prev_stage.branch_name(StagePart::Solution));
-    self
-      .origin_git
-      .reset(&branch)
-      .with_context(|| format!("Failed to reset to branch: {branch}"))?;
-    let issue = self
-      .file_issue(stage_index - 1)
-      .await
-      .context("Failed to file issue for preceding stage")?;
-    self
-      .origin
-      .issue_handler()
-      .update(issue.number)
-      .state(IssueState::Closed)
-      .send()
-      .await
-      .with_context(|| format!("Failed to close issue: {}", issue.number))?;
+  /// Merges the learner's PR for one part of a stage. This is how learners merge PRs
+  /// on forges without a web interface, like [`LocalForge`](crate::local::LocalForge).
+  pub async fn merge_stage_pr(&self, stage_index: usize, part: StagePart) -> Result<()> {
+    let record = self.stage_record(stage_index);
+    let number = match part {
+      StagePart::Starter => record.starter_pr,
+      StagePart::Solution => record.solution_pr,
+    };
+    let branch = self.stage(stage_index).branch_name(part);
+    let pr = self
+      .journal_pr(number)
+      .with_context(|| format!("Missing PR for branch: {branch}"))?;
+    self.origin.merge_pr(&pr).await?;
+    self.infer_state_update().await?;
+    Ok(())
+  }
 
+  /// Closes the learner's issue for a stage, analogous to [`Quest::merge_stage_pr`].
+  pub async fn close_stage_issue(&self, stage_index: usize) -> Result<()> {
+    let label = &self.stage(stage_index).label;
+    let issue = self
+      .journal_issue(self.stage_record(stage_index).issue)
+      .with_context(|| format!("Missing issue for stage: {label}"))?;
+    self.origin.close_issue(&issue).await?;
     self.infer_state_update().await?;
     Ok(())
   }
-}
 
-#[cfg(test)]
-mod test {
-  use super::*;
-  use crate::github::{self, GithubToken};
-  use anyhow::ensure;
-  use env::current_dir;
-  use std::{
-    env, fs,
-    path::Path,
-    process::Command,
-    sync::{Arc, Once},
-  };
-  use tracing_subscriber::{fmt, layer::SubscriberExt, prelude::*, EnvFilter};
+  /// Plans `action` without running it, predicting how its changes will merge with the
+  /// learner's code.
+  pub async fn plan(&self, action: QuestAction) -> Result<ActionPlan> {
+    let steps = match action {
+      QuestAction::FileFeatureAndIssue { stage } => {
+        let stage_index = stage as usize;
+        let mut steps = Vec::new();
+        if !self.stage(stage_index).no_starter() {
+          steps.extend(self.plan_pr(stage_index, StagePart::Starter)?);
+        }
+        steps.push(self.plan_issue(stage_index)?);
+        steps
+      }
+      QuestAction::FileSolution { stage } => self.plan_pr(stage as usize, StagePart::Solution)?,
+      QuestAction::SkipToStage { stage } => {
+        let stage_index = stage as usize;
+        ensure!(stage_index > 0, "Cannot skip to the first stage");
+        ensure!(
+          self.template.can_skip(),
+          "This quest does not allow skipping stages"
+        );
+        let prev_stage = self.stage(stage_index - 1);
+        let current = self.current_stage().await?;
+        let issue = self.plan_issue(stage_index - 1)?;
+        let PlanStep::FileIssue { title, .. } = &issue else {
+          unreachable!()
+        };
+        let close = PlanStep::CloseIssue {
+          title: title.clone(),
+        };
+        vec![
+          PlanStep::Backup {
+            stage: self.stage(current).label.clone(),
+            rev: "main".into(),
+          },
+          PlanStep::ResetMain {
+            target: format!("{UPSTREAM}/{}", prev_stage.branch_name(StagePart::Solution)),
+          },
+          issue,
+          close,
+        ]
+      }
+    };
+    let predicts_merge = steps
+      .iter()
+      .any(|step| matches!(step, PlanStep::CreateBranch { .. }));
+    let stale = predicts_merge && self.origin_git.origin_main_stale();
+    Ok(ActionPlan {
+      action,
+      steps,
+      stale,
+    })
+  }
+
+  fn plan_pr(&self, stage_index: usize, part: StagePart) -> Result<Vec<PlanStep>> {
+    let stage = self.stage(stage_index);
+    let base_branch = self.base_branch(stage_index, part);
+    let target_branch = stage.branch_name(part);
+    let patch = self
+      .template
+      .plan_patch(&self.origin_git, &base_branch, &target_branch)?;
+    let merge_type = self
+      .origin_git
+      .predict_merge(&*self.template, &base_branch, &target_branch)
+      .with_context(|| format!("Failed to predict merge: {base_branch} -> {target_branch}"))?;
+    let pr = self
+      .template
+      .pull_request(&PullSelector::Branch(target_branch.clone()))
+      .with_context(|| format!("Failed to fetch pull request for {target_branch}"))?;
 
-  const TEST_ORG: &str = "cognitive-engineering-lab";
-  const TEST_REPO: &str = "rqst-test";
+    let file_pr = PlanStep::FilePr {
+      head: target_branch.clone(),
+      title: pr.title.clone().unwrap_or_default(),
+      body: copied_pr_body(&pr, &merge_type),
+      labels: copied_pr_labels(&pr, &merge_type),
+    };
 
-  struct DeleteRemoteRepo(Arc<Quest>);
-  impl Drop for DeleteRemoteRepo {
-    fn drop(&mut self) {
-      tokio::task::block_in_place(move || {
-        tokio::runtime::Handle::current().block_on(async move {
-          self.0.origin.delete().await.unwrap();
-        })
-      })
+    let mut steps = Vec::new();
+    if matches!(
+      merge_type,
+      MergeType::SolutionReset | MergeType::StarterReset
+    ) {
+      steps.push(PlanStep::Backup {
+        stage: stage.label.clone(),
+        rev: "main".into(),
+      });
     }
+    steps.push(PlanStep::CreateBranch {
+      branch: target_branch,
+      base: base_branch,
+      patch,
+      merge_type,
+    });
+    steps.push(file_pr);
+    Ok(steps)
   }
 
-  struct DeleteLocalRepo(PathBuf);
-  impl Drop for DeleteLocalRepo {
-    fn drop(&mut self) {
-      fs::remove_dir_all(&self.0).unwrap();
-    }
+  fn plan_issue(&self, stage_index: usize) -> Result<PlanStep> {
+    let label = &self.stage(stage_index).label;
+    let issue = self
+      .template
+      .issue(label)
+      .with_context(|| format!("Failed to get issue for stage: {label}"))?;
+    Ok(PlanStep::FileIssue {
+      title: issue.title.clone(),
+      body: self
+        .origin
+        .process_issue_body(issue.body.as_deref().unwrap_or_default()),
+      labels: issue.labels,
+    })
   }
 
-  fn setup() {
-    static SETUP: Once = Once::new();
-    SETUP.call_once(|| {
-      tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+  /// Resets the learner's unmerged PR for one part of a stage to the quest's code, discarding
+  /// their changes. This is how learners give up on resolving a PR's merge conflicts.
+  pub async fn reset_stage_pr(&self, stage_index: usize, part: StagePart) -> Result<()> {
+    let record = self.stage_record(stage_index);
+    let number = match part {
+      StagePart::Starter => record.starter_pr,
+      StagePart::Solution => record.solution_pr,
+    };
+    let stage = self.stage(stage_index);
+    let branch = stage.branch_name(part);
+    let pr = self
+      .journal_pr(number)
+      .with_context(|| format!("Missing PR for branch: {branch}"))?;
+    ensure!(!pr.is_merged(), "PR #{} is already merged", pr.number);
 
-      let token = github::get_github_token();
-      match token {
-        GithubToken::Found(token) => github::init_octocrab(&token).unwrap(),
-        other => panic!("Failed to get github token: {other:?}"),
-      }
-    });
-  }
+    let base_branch = self.base_branch(stage_index, part);
+    self
+      .origin_git
+      .checkout_main_and_pull()
+      .context("Failed to checkout main and pull")?;
+    self.backup(stage_index, &format!("origin/{branch}"))?;
+    self
+      .origin_git
+      .reset_branch(&*self.template, &base_branch, &branch)
+      .with_context(|| format!("Failed to reset branch: {branch}"))?;
+    self
+      .origin
+      .add_pr_labels(pr.number, &[RESET_LABEL.into()])
+      .await
+      .context("Failed to add labels to PR")?;
+    self.record(JournalEvent::Reset {
+      stage: stage.label.clone(),
+      part,
+    })?;
 
-  async fn create_test_quest(source: CreateSource) -> Result<Arc<Quest>> {
-    let dir = current_dir()?;
-    let quest = Quest::create(dir, source, Box::new(NoopEmitter)).await?;
-    Ok(Arc::new(quest))
+    self.infer_state_update().await?;
+    Ok(())
   }
 
-  macro_rules! test_quest {
-    ($id:ident, $source:expr) => {
-      setup();
-
-      let $id = create_test_quest($source).await?;
-      let _remote = DeleteRemoteRepo(Arc::clone(&$id));
-      let _local = DeleteLocalRepo($id.dir.clone());
-    };
-    ($id:ident) => {
-      test_quest!(
-        $id,
-        CreateSource::Remote {
-          user: TEST_ORG.into(),
-          repo: TEST_REPO.into(),
-        }
-      )
-    };
+  /// Unlocks the package's encrypted reference solutions, and keeps them unlocked in the clone.
+  pub async fn unlock_solutions(&self, password: &str) -> Result<()> {
+    self.template.unlock_solutions(password, &self.origin_git)?;
+    self.infer_state_update().await
   }
 
-  macro_rules! state_is {
-    ($quest:expr, $a:expr, $b:expr, $c:expr) => {{
-      let state = $quest.infer_state().await?;
-      match state {
-        QuestState::Ongoing {
+  /// Skips to `stage_index` by resetting main to the solution of the stage before it.
+  /// The skip is only recorded once that stage's issue is filed and closed.
+  pub async fn skip_to_stage(&self, stage_index: usize) -> Result<()> {
+    ensure!(stage_index > 0, "Cannot skip to the first stage");
+    ensure!(
+      self.template.can_skip(),
+      "This quest does not allow skipping stages"
+    );
+    let prev_stage = self.stage(stage_index - 1);
+    let branch = format!("{UPSTREAM}/{}", prev_stage.branch_name(StagePart::Solution));
+    let current = self.current_stage().await?;
+    self
+      .origin_git
+      .checkout_main_and_pull()
+      .context("Failed to checkout main and pull")?;
+    self.backup(current, "main")?;
+    self
+      .origin_git
+      .reset(&branch)
+      .with_context(|| format!("Failed to reset to branch: {branch}"))?;
+    let issue = self
+      .file_issue(stage_index - 1)
+      .await
+      .context("Failed to file issue for preceding stage")?;
+    self.origin.close_issue(&issue).await?;
+    self.record(JournalEvent::Skipped {
+      stage: self.stage(stage_index).label.clone(),
+    })?;
+
+    self.infer_state_update().await?;
+    Ok(())
+  }
+
+  /// Rewinds the quest so that `stage_index` is the current stage again. The stage's
+  /// branches and those of every later stage are deleted, their open issues are closed,
+  /// and their PRs are labeled as rewound.
+  ///
+  /// The rewind is recorded as soon as the branches are reset. Closing and labeling the
+  /// issues and PRs is only cleanup, so if the forge fails, it is retried by
+  /// [`Quest::clean_up_rewinds`] on the next poll instead.
+  pub async fn rewind_to_stage(&self, stage_index: usize, target: RewindTarget) -> Result<()> {
+    let current = self.current_stage().await?;
+    ensure!(
+      stage_index <= current,
+      "Cannot rewind to stage {stage_index}, which is after the current stage"
+    );
+
+    let label = &self.stage(stage_index).label;
+    let commit = if stage_index == 0 {
+      self.origin_git.root_commit()?
+    } else {
+      match target {
+        RewindTarget::Own => {
+          let journal = self.journal.lock();
+          let Some(commit) = journal.stage_start(label) else {
+            bail!("No record of your code at the start of stage {label}, rewind to the reference solution instead");
+          };
+          commit.to_string()
+        }
+        RewindTarget::Reference => {
+          ensure!(
+            self.template.can_skip(),
+            "This quest does not have reference solutions to rewind to"
+          );
+          let prev_stage = self.stage(stage_index - 1);
+          format!("{UPSTREAM}/{}", prev_stage.branch_name(StagePart::Solution))
+        }
+      }
+    };
+
+    let records = self.journal.lock().stage_records();
+    let dropped = &self.stages()[stage_index..];
+    self
+      .origin_git
+      .checkout_main_and_pull()
+      .context("Failed to checkout main and pull")?;
+    self.backup(current, "main")?;
+    for stage in dropped {
+      for part in [StagePart::Starter, StagePart::Solution] {
+        let branch = stage.branch_name(part);
+        self
+          .origin_git
+          .delete_branch(&branch)
+          .with_context(|| format!("Failed to delete branch: {branch}"))?;
+      }
+    }
+    self
+      .origin_git
+      .reset(&commit)
+      .with_context(|| format!("Failed to reset to commit: {commit}"))?;
+
+    let dropped_records = dropped
+      .iter()
+      .map(|stage| records.get(&stage.label).copied().unwrap_or_default())
+      .collect::<Vec<_>>();
+    self.record(JournalEvent::Rewound {
+      stage: label.clone(),
+      dropped: dropped.iter().map(|stage| stage.label.clone()).collect(),
+      issues: dropped_records.iter().filter_map(|r| r.issue).collect(),
+      prs: (dropped_records.iter())
+        .flat_map(|r| [r.starter_pr, r.solution_pr])
+        .flatten()
+        .collect(),
+    })?;
+    if let Err(e) = self.clean_up_rewinds().await {
+      tracing::warn!("Failed to clean up after rewinding, will retry: {e:?}");
+    }
+
+    self.infer_state_update().await?;
+    Ok(())
+  }
+
+  /// Closes the issues and labels the PRs discarded by rewinds, unless that was already done.
+  async fn clean_up_rewinds(&self) -> Result<()> {
+    let (issues, prs) = self.journal.lock().rewind_cleanup();
+    if issues.is_empty() && prs.is_empty() {
+      return Ok(());
+    }
+
+    let has_label = (self.origin.labels().await?.iter()).any(|label| label.name == REWOUND_LABEL);
+    if !has_label {
+      let label = Label {
+        name: REWOUND_LABEL.into(),
+        color: "cccccc".into(),
+        description: Some("Filed for a stage the learner has rewound".into()),
+        default: false,
+      };
+      self.origin.create_labels(&[label]).await?;
+    }
+    for issue in issues {
+      if let Some(issue) = self.journal_issue(Some(issue)) {
+        if matches!(issue.state, IssueState::Open) {
+          self.origin.close_issue(&issue).await?;
+        }
+      }
+    }
+    for pr in prs {
+      let Some(pr) = self.journal_pr(Some(pr)) else {
+        continue;
+      };
+      self
+        .origin
+        .add_pr_labels(pr.number, &[REWOUND_LABEL.into()])
+        .await
+        .with_context(|| format!("Failed to label PR #{} as rewound", pr.number))?;
+    }
+
+    self.record(JournalEvent::RewindCleanedUp)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    forge::{GitProtocol, CONFLICT_LABEL},
+    git::{git, git_output},
+    gitea::GiteaForge,
+    gitlab::GitlabForge,
+    local::LocalForge,
+    mock_github::{setup, test_config, MockGithub, TEMPLATE_REPO},
+    package::{
+      FileMode, PackOptions, PackageFile, PackageIssue, PackageLabel, PackagePr, PackageSolutions,
+      Patch, SolutionPolicy,
+    },
+    sign::SigningKey,
+    unpack, validate,
+  };
+  use std::{
+    env, fs,
+    sync::atomic::{AtomicBool, Ordering},
+  };
+
+  struct DeleteRemoteRepo(Arc<Quest>);
+  impl Drop for DeleteRemoteRepo {
+    fn drop(&mut self) {
+      tokio::task::block_in_place(move || {
+        tokio::runtime::Handle::current().block_on(async move {
+          self.0.origin.delete().await.unwrap();
+        })
+      })
+    }
+  }
+
+  /// Starts a mock Github whose org has the [`test_config`] quest, and creates
+  /// a quest from `source` in a temporary directory.
+  macro_rules! test_quest {
+    ($mock:ident, $quest:ident, $source:expr) => {
+      setup();
+
+      let $mock = MockGithub::start().await?;
+      $mock
+        .seed_template(&test_config(&$mock.org, TEMPLATE_REPO))
+        .await?;
+      let dir = tempfile::tempdir()?;
+      let source = $source(&$mock)?;
+      let $quest = Quest::create(
+        Arc::new($mock.forge()?),
+        dir.path().to_path_buf(),
+        source,
+        CreateOptions::default(),
+        Box::new(NoopEmitter),
+      )
+      .await?;
+    };
+    ($mock:ident, $quest:ident) => {
+      test_quest!($mock, $quest, remote_source)
+    };
+  }
+
+  fn remote_source(mock: &MockGithub) -> Result<CreateSource> {
+    Ok(CreateSource::Remote {
+      user: mock.org.clone(),
+      repo: TEMPLATE_REPO.into(),
+    })
+  }
+
+  fn package_source(_mock: &MockGithub) -> Result<CreateSource> {
+    Ok(CreateSource::Package(Box::new(test_package())))
+  }
+
+  macro_rules! state_is {
+    ($quest:expr, $a:expr, $b:expr, $c:expr) => {{
+      let state = $quest.infer_state().await?;
+      match state {
+        QuestState::Ongoing {
           stage,
           part,
           status,
@@ -601,22 +1247,45 @@ mod test {
     }};
   }
 
-  // TODO: some of this machinery should be its own tester binary
+  /// Commits a change to `file` on the learner's main branch that conflicts with the quest.
+  fn commit_conflict(quest: &Quest, file: &str) -> Result<()> {
+    quest.origin_git.checkout_main_and_pull()?;
+    fs::write(quest.dir.join(file), "My own work\n")?;
+    git!(quest.origin_git, "add .")?;
+    git!(quest.origin_git, "commit -m 'Learner changes'")?;
+    git!(quest.origin_git, "push")?;
+    Ok(())
+  }
+
+  fn origin_pr(quest: &Quest, branch: &str) -> PullRequest {
+    let pr = quest.origin.pr(&PullSelector::Branch(branch.into()));
+    pr.expect("Missing PR").data.clone()
+  }
+
   #[tokio::test(flavor = "multi_thread")]
-  #[ignore]
   async fn remote_playthrough() -> Result<()> {
-    test_quest!(quest);
+    test_quest!(mock, quest);
 
     state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
 
     let issue = quest.file_issue(0).await?;
     state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
 
+    // Polling only refetches the quest's own PRs and issues.
+    let other = quest.origin.create_issue("Unrelated", "", &[]).await?;
+    quest.infer_state_update().await?;
+    assert!(quest.journal_issue(Some(other.number)).is_none());
+    assert!(quest.journal_issue(Some(issue.number)).is_some());
+
     quest.origin.close_issue(&issue).await?;
     state_is!(quest, 1, StagePart::Starter, StagePartStatus::Start);
 
     let (pr, issue) = quest.file_feature_and_issue(1).await?;
     let pr = pr.unwrap();
+    assert_eq!(
+      issue.body.as_deref(),
+      Some(format!("Starter code is in #{}.", pr.number).as_str())
+    );
     state_is!(quest, 1, StagePart::Starter, StagePartStatus::Ongoing);
 
     quest.origin.merge_pr(&pr).await?;
@@ -624,6 +1293,16 @@ mod test {
 
     let pr = quest.file_solution(1).await?;
     state_is!(quest, 1, StagePart::Solution, StagePartStatus::Ongoing);
+    // State inference doesn't fetch review comments.
+    quest.origin.fetch().await?;
+    let comments = quest
+      .origin
+      .pr(&PullSelector::Branch("s1-b".into()))
+      .unwrap()
+      .comments
+      .clone();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].path, "s1.txt");
 
     quest.origin.merge_pr(&pr).await?;
     state_is!(quest, 1, StagePart::Solution, StagePartStatus::Ongoing);
@@ -631,40 +1310,37 @@ mod test {
     quest.origin.close_issue(&issue).await?;
     state_is!(quest, 2, StagePart::Starter, StagePartStatus::Start);
 
+    quest.origin_git.checkout_main_and_pull()?;
+    assert_eq!(
+      fs::read_to_string(quest.dir.join("s1.txt"))?,
+      "Chapter 1\nsolved\n"
+    );
+
     Ok(())
   }
 
+  /// Packs the [`test_config`] quest repo of `mock`.
+  fn build_package(mock: &MockGithub, options: PackOptions) -> Result<CreateSource> {
+    // Packages are built from a full clone of the quest repo.
+    let dir = tempfile::tempdir()?;
+    let remote = mock
+      .host()
+      .remote(&mock.org, TEMPLATE_REPO, GitProtocol::Https);
+    let mirror = GitRepo::new(dir.path());
+    git!(mirror, "clone --mirror {} .", shlex::try_quote(&remote)?)?;
+    let forge = mock.forge()?;
+    let package = tokio::task::block_in_place(|| {
+      tokio::runtime::Handle::current().block_on(QuestPackage::build(dir.path(), &forge, &options))
+    })?;
+    Ok(CreateSource::Package(Box::new(package)))
+  }
+
   #[tokio::test(flavor = "multi_thread")]
-  #[ignore]
   async fn local_playthrough() -> Result<()> {
-    let status = Command::new("git")
-      .args([
-        "clone",
-        "--mirror",
-        &format!("https://github.com/{TEST_ORG}/{TEST_REPO}"),
-        TEST_REPO,
-      ])
-      .status()?;
-    ensure!(status.success(), "clone failed");
-
-    let repo_path = env::current_dir().unwrap().join(TEST_REPO);
-    let status = Command::new("cargo")
-      .args([
-        "run",
-        "-p",
-        "rq-cli",
-        "--",
-        "pack",
-        &repo_path.display().to_string(),
-      ])
-      .status()?;
-    ensure!(status.success(), "pack failed");
-
-    fs::remove_dir_all(repo_path)?;
-
-    let package_path = PathBuf::from(format!("{TEST_REPO}.json.gz"));
-    let package = QuestPackage::load_from_file(&package_path)?;
-    test_quest!(quest, CreateSource::Package(package));
+    test_quest!(mock, quest, |mock| build_package(
+      mock,
+      PackOptions::default()
+    ));
 
     state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
 
@@ -681,44 +1357,1085 @@ mod test {
     quest.origin.merge_pr(&pr).await?;
     state_is!(quest, 1, StagePart::Solution, StagePartStatus::Start);
 
-    // don't merge the solution PR, that doesn't exist
+    assert_eq!(
+      quest.stage_states()[1].reference_solution,
+      Some(ReferenceSolution::Package)
+    );
+    assert!(quest.reference_solution(1)?.contains("+solved"));
+    let pr = quest.file_solution(1).await?;
+    quest.origin.merge_pr(&pr).await?;
+    quest.origin_git.checkout_main_and_pull()?;
+    assert_eq!(
+      fs::read_to_string(quest.dir.join("s1.txt"))?,
+      "Chapter 1\nsolved\n"
+    );
 
     quest.origin.close_issue(&issue).await?;
     state_is!(quest, 2, StagePart::Starter, StagePartStatus::Start);
 
-    fs::remove_file(package_path)?;
+    Ok(())
+  }
+
+  /// Encrypted solutions can only be filed once the learner has the password.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn locked_solutions() -> Result<()> {
+    let options = PackOptions {
+      solutions: SolutionPolicy::Encrypt("open sesame".into()),
+      ..Default::default()
+    };
+    test_quest!(mock, quest, |mock| build_package(mock, options.clone()));
+    assert!(quest.state_descriptor().await?.solutions_locked);
+
+    // The solution PRs are encrypted with the solutions, or left out with them.
+    let s1b = PullSelector::Branch("s1-b".into());
+    let CreateSource::Package(package) = build_package(&mock, options.clone())? else {
+      unreachable!()
+    };
+    assert!(package.pr(&s1b).is_none());
+    let solutions = package
+      .solutions
+      .as_ref()
+      .unwrap()
+      .open(Some("open sesame"))?;
+    let solution_pr = solutions.prs.iter().find(|pr| pr.matches(&s1b)).unwrap();
+    let omit = PackOptions {
+      solutions: SolutionPolicy::Omit,
+      ..Default::default()
+    };
+    let CreateSource::Package(omitted) = build_package(&mock, omit)? else {
+      unreachable!()
+    };
+    assert!(omitted.pr(&s1b).is_none());
+
+    assert!(quest.stage_states()[1].reference_solution.is_none());
+    assert!(quest.reference_solution(1).is_err());
+
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    let (pr, _) = quest.file_feature_and_issue(1).await?;
+    quest.origin.merge_pr(&pr.unwrap()).await?;
+    assert!(quest.file_solution(1).await.is_err());
+
+    assert!(quest.unlock_solutions("password").await.is_err());
+    quest.unlock_solutions("open sesame").await?;
+    assert!(!quest.state_descriptor().await?.solutions_locked);
+    let pr = quest.file_solution(1).await?;
+    assert_eq!(pr.title, solution_pr.title);
+
+    // The solutions stay unlocked the next time the quest is loaded, without the password
+    // being saved.
+    let config = git_output!(quest.origin_git, "config --local --list")?;
+    assert!(!config.contains("open sesame"));
+    let saved = quest
+      .origin_git
+      .git_dir()?
+      .join("rqst")
+      .join("solutions.json");
+    assert!(!fs::read_to_string(&saved)?.contains("open sesame"));
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(fs::metadata(&saved)?.permissions().mode() & 0o777, 0o600);
+    }
+    let reloaded = Quest::load(
+      Arc::new(mock.forge()?),
+      quest.dir.clone(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    assert!(!reloaded.state_descriptor().await?.solutions_locked);
 
     Ok(())
   }
 
-  // TODO: can't seem to run these even sequentially?
+  /// With a bundle, packaged quests have the quest repo's history, including solutions.
   #[tokio::test(flavor = "multi_thread")]
-  #[ignore]
-  async fn skip() -> Result<()> {
-    test_quest!(quest);
-
-    macro_rules! state_is {
-      ($a:expr, $b:expr, $c:expr) => {
-        let state = quest.infer_state().await?;
-        match state {
-          QuestState::Ongoing {
-            stage,
-            part,
-            status,
-          } => assert_eq!((stage, part, status), ($a, $b, $c)),
-          QuestState::Completed => panic!("finished"),
-        };
+  async fn bundle_playthrough() -> Result<()> {
+    let options = PackOptions {
+      bundle: true,
+      ..Default::default()
+    };
+    test_quest!(mock, quest, |mock| build_package(mock, options.clone()));
+    assert!(quest.state_descriptor().await?.can_skip);
+
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    let (pr, _) = quest.file_feature_and_issue(1).await?;
+    let pr = pr.unwrap();
+    let solution = quest
+      .origin_git
+      .show(&format!("{UPSTREAM}/s1-b"), "s1.txt")?;
+    assert_eq!(solution, "Chapter 1\nsolved\n");
+    quest.origin.merge_pr(&pr).await?;
+
+    let pr = quest.file_solution(1).await?;
+    quest.origin.merge_pr(&pr).await?;
+    quest.origin_git.checkout_main_and_pull()?;
+    assert_eq!(
+      fs::read_to_string(quest.dir.join("s1.txt"))?,
+      "Chapter 1\nsolved\n"
+    );
+
+    quest.skip_to_stage(2).await?;
+    state_is!(quest, 2, StagePart::Starter, StagePartStatus::Start);
+
+    Ok(())
+  }
+
+  /// Signed packages are verified when a quest is created from them and every time it is loaded.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn signed_package() -> Result<()> {
+    let key = SigningKey::generate("Test Author")?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("package.json.gz");
+    let signed = |mock: &MockGithub| {
+      let CreateSource::Package(mut package) = build_package(mock, PackOptions::default())? else {
+        unreachable!()
       };
+      package.sign(&key)?;
+      package.save(&path)?;
+      let package = QuestPackage::load_from_file(&path)?;
+      assert_eq!(package.signer(), Some(&key.public()?));
+      anyhow::Ok(CreateSource::Package(Box::new(package)))
+    };
+    test_quest!(mock, quest, signed);
+
+    Quest::load(
+      Arc::new(mock.forge()?),
+      quest.dir.clone(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+
+    // Removing the signature of the package on meta doesn't pass it off as unsigned.
+    let mut package = QuestPackage::load_from_file(&path)?;
+    package.signature = None;
+    git!(quest.origin_git, "checkout meta")?;
+    package.save(&quest.dir.join("package.json.gz"))?;
+    git!(quest.origin_git, "commit -am 'Remove signature'")?;
+    git!(quest.origin_git, "checkout main")?;
+    let err = Quest::load(
+      Arc::new(mock.forge()?),
+      quest.dir.clone(),
+      Box::new(NoopEmitter),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert!(format!("{err:?}").contains("its signature has been removed"));
+
+    let mut package = QuestPackage::load_from_file(&path)?;
+    package.config.title = "Tampered".into();
+    package.save(&path)?;
+    let err = QuestPackage::load_from_file(&path).err().unwrap();
+    assert!(format!("{err:?}").contains("Package field `config` was changed after it was signed"));
+
+    Ok(())
+  }
+
+  /// A package unpacked and published again packs the same as the original quest repo.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn unpack_package() -> Result<()> {
+    setup();
+    let mock = MockGithub::start().await?;
+    mock
+      .seed_template(&test_config(&mock.org, TEMPLATE_REPO))
+      .await?;
+    let CreateSource::Package(mut package) = build_package(&mock, PackOptions::default())? else {
+      unreachable!()
+    };
+    let issue = package
+      .issues
+      .iter()
+      .find(|issue| issue.labels.contains(&"s1".into()));
+    let issue = PackageIssue {
+      title: "Another issue".into(),
+      ..issue.unwrap().clone()
+    };
+    package.issues.push(issue);
+
+    let dir = tempfile::tempdir()?;
+    let repo = unpack::unpack(&package, &dir.path().join("quest"))?;
+    assert_eq!(repo.show("s1-b", "s1.txt")?, "Chapter 1\nsolved\n");
+    assert_eq!(QuestConfig::load(&repo, None)?, package.config);
+    let forge_dir = tempfile::tempdir()?;
+    let forge = LocalForge::new(forge_dir.path().to_path_buf(), "author");
+    unpack::publish(&package, &repo, &forge, "unpacked").await?;
+    let repacked = QuestPackage::build(repo.path(), &forge, &PackOptions::default()).await?;
+    assert_eq!(repacked.config.author, "author");
+    assert_eq!(repacked.initial, package.initial);
+    let patches = |package: &QuestPackage| {
+      let solutions = package
+        .solutions
+        .as_ref()
+        .unwrap()
+        .open(None)
+        .unwrap()
+        .patches;
+      package
+        .patches
+        .iter()
+        .chain(&solutions)
+        .map(|patch| (patch.head.clone(), patch.patch.clone()))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(patches(&repacked), patches(&package));
+    let sorted = |items: &[PackagePr]| {
+      let mut items = items
+        .iter()
+        .map(|pr| PackagePr {
+          html_url: String::new(),
+          ..pr.clone()
+        })
+        .collect::<Vec<_>>();
+      items.sort_by(|a, b| a.branch.cmp(&b.branch));
+      items
+    };
+    assert_eq!(sorted(&repacked.prs), sorted(&package.prs));
+    assert_eq!(repacked.issues.len(), package.issues.len());
+    assert_eq!(repacked.labels, package.labels);
+
+    // Without a forge, the issues and PRs are kept on meta.
+    let repo = unpack::unpack(&package, &dir.path().join("offline"))?;
+    unpack::write_documents(&package, &repo)?;
+    assert!(repo
+      .show("meta", "pulls/s1-b.md")?
+      .contains("This line is the solution."));
+    // Issues that share a stage label get files of their own.
+    assert!(!repo.show("meta", "issues/s1.md")?.contains("Another issue"));
+    assert!(repo
+      .show("meta", "issues/s1-2.md")?
+      .contains("Another issue"));
+
+    // With a bundle, the branches are the quest repo's own.
+    let options = PackOptions {
+      bundle: true,
+      ..Default::default()
+    };
+    let CreateSource::Package(package) = build_package(&mock, options)? else {
+      unreachable!()
+    };
+    let repo = unpack::unpack(&package, &dir.path().join("bundled"))?;
+    assert_eq!(repo.show("s2-b", "s2.txt")?, "Chapter 2\nsolved\n");
+    assert_eq!(QuestConfig::load(&repo, None)?, package.config);
+    assert!(fs::read_to_string(repo.path().join("README.md"))?.starts_with("Hello"));
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn validate_quest() -> Result<()> {
+    setup();
+    let mock = MockGithub::start().await?;
+    mock
+      .seed_template(&test_config(&mock.org, TEMPLATE_REPO))
+      .await?;
+    let CreateSource::Package(mut package) = build_package(&mock, PackOptions::default())? else {
+      unreachable!()
+    };
+    assert_eq!(validate::validate_package(&package, None)?, vec![]);
+
+    let dir = tempfile::tempdir()?;
+    let repo = unpack::unpack(&package, &dir.path().join("quest"))?;
+    let forge_dir = tempfile::tempdir()?;
+    let forge = LocalForge::new(forge_dir.path().to_path_buf(), "author");
+    unpack::publish(&package, &repo, &forge, "quest").await?;
+    assert_eq!(validate::validate_repo(repo.path(), &forge).await?, vec![]);
+
+    // A fresh clone only has main locally, so its stage branches are found on origin.
+    let remote = forge.load_repo("author", "quest").await?;
+    let clone_dir = tempfile::tempdir()?;
+    let clone = GitRepo::clone(
+      &clone_dir.path().join("quest"),
+      &remote.remote(GitProtocol::Https),
+    )?;
+    assert!(!clone.has_branch("s1-a")?);
+    assert_eq!(validate::validate_repo(clone.path(), &forge).await?, vec![]);
+
+    git!(repo, "branch s0-a main")?;
+    git!(repo, "branch -f s2-a s1-a")?;
+    let problems = validate::validate_repo(repo.path(), &forge).await?;
+    let messages = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      messages,
+      [
+        "s0: The stage has no starter code, but there is a branch s0-a",
+        "s2: s2-a does not build on s1-b",
+      ]
+    );
+
+    package
+      .issues
+      .retain(|issue| !issue.labels.contains(&"s1".into()));
+    let issue = package
+      .issues
+      .iter_mut()
+      .find(|issue| issue.labels.contains(&"s2".into()));
+    issue.unwrap().body = Some("See {{ s9 pr }}.".into());
+    let pr = package.prs.iter_mut().find(|pr| pr.branch == "s2-a");
+    pr.unwrap().body = None;
+    package.patches.push(Patch {
+      base: "main".into(),
+      head: "s0-a".into(),
+      patch: String::new(),
+    });
+    let Some(PackageSolutions::Plain { patches }) = &mut package.solutions else {
+      unreachable!()
+    };
+    let s1_solution = patches.iter().find(|patch| patch.head == "s1-b").unwrap();
+    let s1_solution = s1_solution.patch.clone();
+    patches
+      .iter_mut()
+      .find(|patch| patch.head == "s2-b")
+      .unwrap()
+      .patch = s1_solution;
+
+    let problems = validate::validate_package(&package, None)?;
+    let stages = problems
+      .iter()
+      .map(|p| p.stage.as_deref().unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(stages, ["s0", "s1", "s2", "s2", "s2"]);
+    assert_eq!(
+      problems[0].message,
+      "The stage has no starter code, but there is a patch for s0-a"
+    );
+    assert_eq!(problems[1].message, "No issue has the stage's label");
+    assert!(problems[2]
+      .message
+      .contains("Failed to apply patch for s2-b on s2-a"));
+    assert_eq!(
+      problems[3].message,
+      "The issue has `{{ s9 pr }}`, but no pr has the label s9"
+    );
+    assert_eq!(problems[4].message, "The PR for s2-a has no body");
+
+    Ok(())
+  }
+
+  /// Fails updates once `fail` is set, and keeps the errors it is told about.
+  #[derive(Default)]
+  struct FailingEmitter {
+    fail: AtomicBool,
+    errors: Mutex<Vec<String>>,
+  }
+
+  impl StateEmitter for Arc<FailingEmitter> {
+    fn emit(&self, _state: StateDescriptor) -> Result<()> {
+      if self.fail.load(Ordering::SeqCst) {
+        anyhow::bail!("The window was closed");
+      }
+      Ok(())
     }
 
-    state_is!(0, StagePart::Starter, StagePartStatus::Start);
+    fn emit_error(&self, error: &anyhow::Error) {
+      self.errors.lock().push(error.to_string());
+    }
+  }
+
+  /// A failed update is reported to the emitter rather than stopping the polling loop.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn poll_state_errors() -> Result<()> {
+    test_quest!(mock, quest);
+    let emitter = Arc::new(FailingEmitter::default());
+    let quest = Quest::load(
+      Arc::new(mock.forge()?),
+      quest.dir.clone(),
+      Box::new(Arc::clone(&emitter)),
+    )
+    .await?;
+
+    emitter.fail.store(true, Ordering::SeqCst);
+    quest.poll_state().await;
+    quest.poll_state().await;
+    assert_eq!(*emitter.errors.lock(), vec!["The window was closed"; 2]);
+
+    emitter.fail.store(false, Ordering::SeqCst);
+    quest.poll_state().await;
+    assert_eq!(emitter.errors.lock().len(), 2);
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn skip() -> Result<()> {
+    test_quest!(mock, quest);
+
+    state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
+    assert!(quest.skip_to_stage(0).await.is_err());
 
     quest.skip_to_stage(1).await?;
-    state_is!(1, StagePart::Starter, StagePartStatus::Start);
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Start);
 
     quest.skip_to_stage(2).await?;
-    state_is!(2, StagePart::Starter, StagePartStatus::Start);
+    state_is!(quest, 2, StagePart::Starter, StagePartStatus::Start);
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn rewind() -> Result<()> {
+    test_quest!(mock, quest);
+
+    let issue = quest.file_issue(0).await?;
+    quest.origin.close_issue(&issue).await?;
+
+    let (pr, issue) = quest.file_feature_and_issue(1).await?;
+    let starter_pr = pr.unwrap();
+    quest.origin.merge_pr(&starter_pr).await?;
+    let pr = quest.file_solution(1).await?;
+    quest.origin.merge_pr(&pr).await?;
+    quest.origin.close_issue(&issue).await?;
+    quest.file_feature_and_issue(2).await?;
+    state_is!(quest, 2, StagePart::Starter, StagePartStatus::Ongoing);
+
+    quest.rewind_to_stage(1, RewindTarget::Own).await?;
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Start);
+    assert!(!quest.dir.join("s1.txt").exists());
+    let remote_branches = git_output!(quest.origin_git, "ls-remote --heads origin")?;
+    assert!(!remote_branches.contains("s1-a") && !remote_branches.contains("s2-a"));
+    // Rewound PRs are no longer the quest's, so state inference doesn't refetch them.
+    quest.origin.fetch().await?;
+    let rewound = quest.journal_pr(Some(starter_pr.number)).unwrap();
+    assert!(rewound
+      .labels
+      .iter()
+      .any(|label| label.name == REWOUND_LABEL));
+
+    // The learner's code from before the rewind is saved to a backup branch.
+    let backups = &quest.stage_states()[2].backups;
+    assert_eq!(backups.len(), 1);
+    assert!(backups[0].starts_with("rqst-backup/s2-"));
+    assert!(remote_branches.contains(&backups[0]));
+    assert_eq!(
+      quest.origin_git.show(&backups[0], "s1.txt")?,
+      "Chapter 1\nsolved\n"
+    );
+
+    // The stage can be played again from the same branches.
+    let (pr, _) = quest.file_feature_and_issue(1).await?;
+    quest.origin.merge_pr(&pr.unwrap()).await?;
+    state_is!(quest, 1, StagePart::Solution, StagePartStatus::Start);
+
+    quest.rewind_to_stage(1, RewindTarget::Reference).await?;
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Start);
+    quest.origin_git.checkout_main_and_pull()?;
+    assert_eq!(fs::read_to_string(quest.dir.join("s0.txt"))?, "solved\n");
+
+    quest.rewind_to_stage(0, RewindTarget::Own).await?;
+    state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
+    assert!(!quest.dir.join("s0.txt").exists());
+
+    assert!(quest.rewind_to_stage(2, RewindTarget::Own).await.is_err());
+    assert_eq!(quest.journal.lock().rewind_cleanup(), (vec![], vec![]));
+
+    // A rewind whose forge cleanup failed is cleaned up again on the next poll.
+    let issue = quest.file_issue(0).await?;
+    quest.record(JournalEvent::Rewound {
+      stage: quest.stage(0).label.clone(),
+      dropped: vec![quest.stage(0).label.clone()],
+      issues: vec![issue.number],
+      prs: vec![],
+    })?;
+    quest.poll_state().await;
+    assert_eq!(quest.journal.lock().rewind_cleanup(), (vec![], vec![]));
+    quest.origin.fetch().await?;
+    let issue = quest.journal_issue(Some(issue.number)).unwrap();
+    assert!(matches!(issue.state, IssueState::Closed));
+
+    Ok(())
+  }
+
+  /// Planning an action predicts its effects without changing the repo.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn plan() -> Result<()> {
+    test_quest!(mock, quest);
+
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    commit_conflict(&quest, "s1.txt")?;
+    let head = quest.origin_git.head_commit()?;
+    let pr_count = quest.origin.prs().len();
+    let refs = git_output!(quest.origin_git, "for-each-ref")?;
+
+    let plan = quest
+      .plan(QuestAction::FileFeatureAndIssue { stage: 1 })
+      .await?;
+    assert!(!plan.stale);
+    let [PlanStep::CreateBranch {
+      branch, merge_type, ..
+    }, PlanStep::FilePr { body, labels, .. }, PlanStep::FileIssue { title, .. }] = &plan.steps[..]
+    else {
+      panic!("Unexpected plan: {plan:#?}");
+    };
+    assert_eq!(branch, "s1-a");
+    assert_eq!(merge_type, &MergeType::Conflict(vec!["s1.txt".into()]));
+    assert!(body.contains("- `s1.txt`"));
+    assert!(labels.contains(&"s1".to_string()) && labels.contains(&CONFLICT_LABEL.to_string()));
+    assert_eq!(title, "Chapter 1");
+
+    let plan = quest.plan(QuestAction::SkipToStage { stage: 2 }).await?;
+    assert!(matches!(
+      &plan.steps[..],
+      [
+        PlanStep::Backup { .. },
+        PlanStep::ResetMain { target },
+        PlanStep::FileIssue { .. },
+        PlanStep::CloseIssue { .. }
+      ] if target == "upstream/s1-b"
+    ));
+
+    assert_eq!(quest.origin_git.head_commit()?, head);
+    assert_eq!(git_output!(quest.origin_git, "for-each-ref")?, refs);
+    assert!(git_output!(quest.origin_git, "branch --list s1-a")?.is_empty());
+    assert_eq!(
+      git_output!(quest.origin_git, "worktree list")?
+        .lines()
+        .count(),
+      1
+    );
+    quest.origin.fetch().await?;
+    assert_eq!(quest.origin.prs().len(), pr_count);
+
+    // Once main moves on the forge, the unfetched prediction may be stale.
+    git!(quest.origin_git, "commit --allow-empty -m 'Elsewhere'")?;
+    git!(quest.origin_git, "push origin HEAD:main")?;
+    git!(
+      quest.origin_git,
+      "update-ref refs/remotes/origin/main {head}"
+    )?;
+    let plan = quest
+      .plan(QuestAction::FileFeatureAndIssue { stage: 1 })
+      .await?;
+    assert!(plan.stale);
+
+    Ok(())
+  }
+
+  /// More of the learner's own issues than fit on one page shouldn't hide the quest's issue.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn busy_repo() -> Result<()> {
+    test_quest!(mock, quest);
+
+    quest.file_issue(0).await?;
+    state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
+
+    let origin = mock
+      .local_forge(&mock.user)
+      .load_repo(&mock.user, TEMPLATE_REPO)
+      .await?;
+    for i in 0..110 {
+      let title = format!("Note to self {i}");
+      origin
+        .create_issue(&title, "Not part of the quest.", &[])
+        .await?;
+    }
+
+    state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
+    let state = quest.state_descriptor().await?;
+    assert!(state.stages[0].issue_url.is_some());
+
+    Ok(())
+  }
+
+  /// State comes from the journal, so issues that merely look like part of the quest are ignored.
+  /// Quests without a journal get one reconstructed from the repo.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn journal() -> Result<()> {
+    test_quest!(mock, quest);
+
+    quest.file_issue(0).await?;
+    let journal_path = quest.origin_git.git_dir()?.join("rqst/journal.json");
+    fs::remove_file(&journal_path)?;
+
+    let quest = Quest::load(
+      Arc::new(mock.forge()?),
+      quest.dir.clone(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
+    let journal = Journal::load(&quest.origin_git)?.unwrap();
+    let events = journal
+      .entries()
+      .iter()
+      .map(|entry| entry.event.clone())
+      .collect::<Vec<_>>();
+    assert!(matches!(
+      events.as_slice(),
+      [JournalEvent::IssueFiled { stage, .. }] if stage == "s0"
+    ));
+
+    let origin = mock
+      .local_forge(&mock.user)
+      .load_repo(&mock.user, TEMPLATE_REPO)
+      .await?;
+    let label = vec!["s2".to_string()];
+    origin
+      .create_issue("My own notes", "Not part of the quest.", &label)
+      .await?;
+    state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn remote_merge_conflict() -> Result<()> {
+    test_quest!(mock, quest);
+
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    commit_conflict(&quest, "s1.txt")?;
+
+    quest.file_feature_and_issue(1).await?;
+    let pr = origin_pr(&quest, "s1-a");
+    assert!(pr.labels.iter().any(|label| label.name == CONFLICT_LABEL));
+    assert!(pr.body.unwrap().contains("- `s1.txt`"));
+    assert!(quest.stage_states()[1].feature_pr_conflict);
+    git!(quest.origin_git, "checkout s1-a")?;
+    let merged = fs::read_to_string(quest.dir.join("s1.txt"))?;
+    assert!(merged.contains("<<<<<<<") && merged.contains("My own work"));
+    git!(quest.origin_git, "checkout main")?;
+
+    // Learners can give up on the conflicts and take the reference code instead.
+    quest.reset_stage_pr(1, StagePart::Starter).await?;
+    let pr = origin_pr(&quest, "s1-a");
+    assert!(pr.labels.iter().any(|label| label.name == RESET_LABEL));
+    let stage_state = &quest.stage_states()[1];
+    assert!(!stage_state.feature_pr_conflict);
+    let backup = quest.origin_git.show(&stage_state.backups[0], "s1.txt")?;
+    assert!(backup.contains("<<<<<<<"));
+
+    // The reset PR is based on the learner's main, so it still merges cleanly.
+    quest.merge_stage_pr(1, StagePart::Starter).await?;
+    state_is!(quest, 1, StagePart::Solution, StagePartStatus::Start);
+    quest.origin_git.checkout_main_and_pull()?;
+    assert_eq!(fs::read_to_string(quest.dir.join("s1.txt"))?, "Chapter 1\n");
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn package_merge_conflict() -> Result<()> {
+    test_quest!(mock, quest, package_source);
+
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    commit_conflict(&quest, "s1.txt")?;
+
+    quest.file_feature_and_issue(1).await?;
+    let pr = origin_pr(&quest, "s1-a");
+    assert!(pr.labels.iter().any(|label| label.name == CONFLICT_LABEL));
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Ongoing);
+
+    quest.reset_stage_pr(1, StagePart::Starter).await?;
+    let pr = origin_pr(&quest, "s1-a");
+    assert!(pr.labels.iter().any(|label| label.name == RESET_LABEL));
+    git!(quest.origin_git, "checkout s1-a")?;
+    assert_eq!(fs::read_to_string(quest.dir.join("s1.txt"))?, "Chapter 1\n");
+
+    Ok(())
+  }
+
+  /// Deletes the quest's `s1.txt` on the learner's main, so the solution of stage 1 can't be
+  /// merged three-way, and checks that its PR is filed as a conflict rather than reset.
+  async fn unmergeable_solution(quest: &Quest) -> Result<()> {
+    quest.file_feature_and_issue(0).await?;
+    quest.close_stage_issue(0).await?;
+    quest.file_feature_and_issue(1).await?;
+    quest.merge_stage_pr(1, StagePart::Starter).await?;
+    quest.origin_git.checkout_main_and_pull()?;
+    git!(quest.origin_git, "rm s1.txt")?;
+    git!(quest.origin_git, "commit -m 'Learner deletes s1.txt'")?;
+    git!(quest.origin_git, "push")?;
+
+    quest.file_solution(1).await?;
+    let pr = origin_pr(quest, "s1-b");
+    assert!(pr.labels.iter().any(|label| label.name == CONFLICT_LABEL));
+    assert!(!pr.labels.iter().any(|label| label.name == RESET_LABEL));
+    assert!(pr.body.unwrap().contains("- `s1.txt`"));
+    git!(quest.origin_git, "checkout s1-b")?;
+    assert!(!quest.dir.join("s1.txt").exists());
+    git!(quest.origin_git, "checkout main")?;
+
+    // Overwriting the learner's work is still their choice.
+    quest.reset_stage_pr(1, StagePart::Solution).await?;
+    assert_eq!(
+      quest.origin_git.show("origin/s1-b", "s1.txt")?,
+      "Chapter 1\nsolved\n"
+    );
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn remote_unmergeable_solution() -> Result<()> {
+    test_quest!(mock, quest);
+    unmergeable_solution(&quest).await
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn package_unmergeable_solution() -> Result<()> {
+    test_quest!(mock, quest, |mock| build_package(
+      mock,
+      PackOptions::default()
+    ));
+    unmergeable_solution(&quest).await
+  }
+
+  /// A failed creation is rolled back, so that trying again starts from scratch.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn create_rollback() -> Result<()> {
+    setup();
+    let mock = MockGithub::start().await?;
+    let forge = Arc::new(mock.forge()?);
+    let dir = tempfile::tempdir()?;
+    let create = |package: QuestPackage| {
+      Quest::create(
+        forge.clone(),
+        dir.path().to_path_buf(),
+        CreateSource::Package(Box::new(package)),
+        CreateOptions::default(),
+        Box::new(NoopEmitter),
+      )
+    };
+
+    // A file can't also be a directory, so writing the initial files fails.
+    let mut package = test_package();
+    let repo = package.config.repo.clone();
+    package
+      .initial
+      .insert("a".into(), PackageFile::regular("file"));
+    package
+      .initial
+      .insert("a/b".into(), PackageFile::regular("file"));
+    assert!(create(package).await.is_err());
+
+    let learner_forge = mock.local_forge(&mock.user);
+    assert!(learner_forge.load_repo(&mock.user, &repo).await.is_err());
+    assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+
+    let quest = create(test_package()).await?;
+    state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+    // If the forge can't say whether the repo is gone, the progress is kept for next time.
+    let mut progress = CreateProgress::load(dir.path(), "other")?;
+    progress.repo_created(&mock.user)?;
+    assert!(progress.rollback(&UnreachableForge).await.is_err());
+    assert!(CreateProgress::exists(dir.path(), "other"));
+    let mut progress = CreateProgress::load(dir.path(), "other")?;
+    progress.rollback(&*forge).await?;
+    assert!(!CreateProgress::exists(dir.path(), "other"));
+
+    Ok(())
+  }
+
+  /// A forge that can't be reached, as if the network were down.
+  struct UnreachableForge;
+
+  #[async_trait::async_trait]
+  impl Forge for UnreachableForge {
+    async fn current_user(&self) -> Result<String> {
+      bail!("The forge is unreachable")
+    }
+
+    async fn load_repo(&self, _owner: &str, _name: &str) -> Result<Box<dyn ForgeRepo>> {
+      bail!("The forge is unreachable")
+    }
+
+    async fn repo_exists(&self, _owner: &str, _name: &str) -> Result<bool> {
+      bail!("The forge is unreachable")
+    }
+
+    async fn create_repo(&self, _name: &str) -> Result<Box<dyn ForgeRepo>> {
+      bail!("The forge is unreachable")
+    }
+
+    async fn create_repo_from_template(
+      &self,
+      _base: &dyn ForgeRepo,
+      _name: &str,
+    ) -> Result<Box<dyn ForgeRepo>> {
+      bail!("The forge is unreachable")
+    }
+  }
+
+  /// If RepoQuest exits partway through creating a quest, the next attempt cleans up after it.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn create_after_crash() -> Result<()> {
+    setup();
+    let mock = MockGithub::start().await?;
+    let forge = Arc::new(mock.forge()?);
+    let dir = tempfile::tempdir()?;
+    let package = test_package();
+
+    forge.create_repo(&package.config.repo).await?;
+    fs::write(
+      dir
+        .path()
+        .join(format!(".rqst-create-{}.json", package.config.repo)),
+      format!(r#"{{"owner": "{}", "step": "repo-created"}}"#, mock.user),
+    )?;
+
+    let quest = Quest::create(
+      forge.clone(),
+      dir.path().to_path_buf(),
+      CreateSource::Package(Box::new(package)),
+      CreateOptions::default(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+    Ok(())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn create_existing() -> Result<()> {
+    test_quest!(mock, quest);
+    quest.file_feature_and_issue(0).await?;
+    let dir = quest.dir.parent().unwrap().to_path_buf();
+    let forge: Arc<dyn Forge> = Arc::new(mock.forge()?);
+    assert!(forge.repo_exists(&mock.user, TEMPLATE_REPO).await?);
+    assert!(!forge.repo_exists(&mock.user, "missing").await?);
+    let create = |existing: ExistingRepo, repo_name: Option<&str>| {
+      Quest::create(
+        forge.clone(),
+        dir.clone(),
+        remote_source(&mock).unwrap(),
+        CreateOptions {
+          repo_name: repo_name.map(String::from),
+          existing,
+        },
+        Box::new(NoopEmitter),
+      )
+    };
+
+    let err = create(ExistingRepo::Fail, None).await.err().unwrap();
+    assert!(err.to_string().contains("already exists"));
+
+    // Adopting picks up the quest where the learner left off, with or without a clone.
+    let adopted = create(ExistingRepo::Adopt, None).await?;
+    state_is!(adopted, 0, StagePart::Solution, StagePartStatus::Start);
+    fs::remove_dir_all(&quest.dir)?;
+    let adopted = create(ExistingRepo::Adopt, None).await?;
+    state_is!(adopted, 0, StagePart::Solution, StagePartStatus::Start);
+
+    let renamed = create(ExistingRepo::Rename, None).await?;
+    assert_eq!(renamed.origin.name(), format!("{TEMPLATE_REPO}-2"));
+    assert_eq!(renamed.dir, dir.join(format!("{TEMPLATE_REPO}-2")));
+    state_is!(renamed, 0, StagePart::Starter, StagePartStatus::Start);
+
+    // The repo name is remembered, since it no longer matches the quest config.
+    let reloaded = Quest::load(forge.clone(), renamed.dir.clone(), Box::new(NoopEmitter)).await?;
+    assert_eq!(reloaded.origin.name(), renamed.origin.name());
+
+    let custom = create(ExistingRepo::Fail, Some("my-quest")).await?;
+    assert_eq!(custom.origin.name(), "my-quest");
+    assert!(dir.join("my-quest").exists());
+
+    // A repo made from a different quest can't be adopted.
+    mock.local_forge(&mock.user).create_repo("other").await?;
+    assert!(create(ExistingRepo::Adopt, Some("other")).await.is_err());
+
+    let replaced = create(ExistingRepo::Replace, None).await?;
+    state_is!(replaced, 0, StagePart::Starter, StagePartStatus::Start);
+
+    Ok(())
+  }
+
+  /// Packages keep binary files, executable bits and symlinks intact.
+  #[tokio::test(flavor = "multi_thread")]
+  async fn package_file_modes() -> Result<()> {
+    setup();
+    let mock = MockGithub::start().await?;
+    let dir = tempfile::tempdir()?;
+    let mut package = test_package();
+    let files = [
+      (
+        "logo.png",
+        PackageFile::regular(vec![0x89, b'P', b'N', b'G', 0xff, 0]),
+      ),
+      (
+        "run.sh",
+        PackageFile::new(FileMode::Executable, b"#!/bin/sh\n".to_vec()),
+      ),
+      (
+        "link.md",
+        PackageFile::new(FileMode::Symlink, b"README.md".to_vec()),
+      ),
+    ];
+    package
+      .initial
+      .extend(files.map(|(path, file)| (PathBuf::from(path), file)));
+    let initial = package.initial.clone();
+
+    let quest = Quest::create(
+      Arc::new(mock.forge()?),
+      dir.path().to_path_buf(),
+      CreateSource::Package(Box::new(package)),
+      CreateOptions::default(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    assert_eq!(quest.origin_git.read_initial_files()?, initial);
+    assert_eq!(fs::read(quest.dir.join("logo.png"))?[4..], [0xff, 0]);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = fs::metadata(quest.dir.join("run.sh"))?.permissions().mode();
+      assert_ne!(mode & 0o111, 0);
+      assert!(fs::symlink_metadata(quest.dir.join("link.md"))?.is_symlink());
+    }
+
+    Ok(())
+  }
+
+  fn new_file_patch(path: &str, line: &str) -> String {
+    format!(
+      "diff --git a/{path} b/{path}\nnew file mode 100644\n--- /dev/null\n+++ b/{path}\n@@ -0,0 +1 @@\n+{line}\n"
+    )
+  }
+
+  /// A package for [`test_config`] with starter code for every stage but the first.
+  fn test_package() -> QuestPackage {
+    let config = test_config("rqst", "rqst-offline-test");
+    let stages = &config.stages;
+    let issues = stages
+      .iter()
+      .map(|stage| PackageIssue {
+        title: stage.name.clone(),
+        body: Some(format!("Starter code is in {{{{ {} pr }}}}.", stage.label)),
+        labels: vec![stage.label.clone()],
+      })
+      .collect();
+    let prs = stages[1..]
+      .iter()
+      .map(|stage| PackagePr {
+        title: Some(format!("{} starter", stage.name)),
+        body: Some("Starter code".into()),
+        branch: stage.branch_name(StagePart::Starter),
+        labels: vec![stage.label.clone()],
+        html_url: String::new(),
+        comments: Vec::new(),
+      })
+      .collect();
+    let patches = stages
+      .windows(2)
+      .map(|pair| Patch {
+        base: pair[0].branch_name(StagePart::Solution),
+        head: pair[1].branch_name(StagePart::Starter),
+        patch: new_file_patch(&format!("{}.txt", pair[1].label), &pair[1].name),
+      })
+      .collect();
+    let initial = HashMap::from([(PathBuf::from("README.md"), PackageFile::regular("Hello\n"))]);
+    let labels = config
+      .stages
+      .iter()
+      .map(|stage| PackageLabel {
+        name: stage.label.clone(),
+        color: "ededed".into(),
+        description: None,
+      })
+      .collect();
+    QuestPackage::new(config, issues, prs, initial, patches, labels)
+  }
+
+  /// Plays through the first two stages of [`test_package`], acting as the learner
+  /// through [`Quest::merge_stage_pr`] and [`Quest::close_stage_issue`].
+  async fn package_playthrough(quest: &Quest) -> Result<()> {
+    state_is!(quest, 0, StagePart::Starter, StagePartStatus::Start);
+
+    let (pr, _) = quest.file_feature_and_issue(0).await?;
+    assert!(pr.is_none());
+    state_is!(quest, 0, StagePart::Solution, StagePartStatus::Start);
+
+    quest.close_stage_issue(0).await?;
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Start);
+
+    let (pr, issue) = quest.file_feature_and_issue(1).await?;
+    let pr = pr.unwrap();
+    assert_eq!(
+      issue.body.as_deref(),
+      Some(format!("Starter code is in #{}.", pr.number).as_str())
+    );
+    state_is!(quest, 1, StagePart::Starter, StagePartStatus::Ongoing);
+
+    quest.merge_stage_pr(1, StagePart::Starter).await?;
+    state_is!(quest, 1, StagePart::Solution, StagePartStatus::Start);
+
+    quest.origin_git.checkout_main_and_pull()?;
+    assert!(quest.dir.join("s1.txt").exists());
+
+    quest.close_stage_issue(1).await?;
+    state_is!(quest, 2, StagePart::Starter, StagePartStatus::Start);
 
     Ok(())
   }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn offline_playthrough() -> Result<()> {
+    setup();
+
+    let dir = tempfile::tempdir()?;
+    let forge = Arc::new(LocalForge::new(dir.path().join("forge"), "learner"));
+    let quest = Quest::create(
+      forge,
+      dir.path().to_path_buf(),
+      CreateSource::Package(Box::new(test_package())),
+      CreateOptions::default(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    package_playthrough(&quest).await
+  }
+
+  /// Runs against a Gitea or Forgejo server, e.g. `docker run -p 3000:3000 gitea/gitea`.
+  /// Set `RQST_GITEA_TOKEN` to an access token for a user with an SSH key registered.
+  #[tokio::test(flavor = "multi_thread")]
+  #[ignore]
+  async fn gitea_playthrough() -> Result<()> {
+    setup();
+
+    let url = env::var("RQST_GITEA_URL").unwrap_or_else(|_| "http://localhost:3000".into());
+    let token = env::var("RQST_GITEA_TOKEN").context("Missing RQST_GITEA_TOKEN")?;
+    let forge = Arc::new(GiteaForge::new(&url, &token)?);
+    let dir = tempfile::tempdir()?;
+    let quest = Quest::create(
+      forge,
+      dir.path().to_path_buf(),
+      CreateSource::Package(Box::new(test_package())),
+      CreateOptions::default(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    let quest = Arc::new(quest);
+    let _remote = DeleteRemoteRepo(Arc::clone(&quest));
+    package_playthrough(&quest).await
+  }
+
+  /// Runs against a GitLab instance, e.g. `docker run -p 8080:80 gitlab/gitlab-ce`.
+  /// Set `RQST_GITLAB_TOKEN` to a personal access token with the `api` scope.
+  #[tokio::test(flavor = "multi_thread")]
+  #[ignore]
+  async fn gitlab_playthrough() -> Result<()> {
+    setup();
+
+    let url = env::var("RQST_GITLAB_URL").unwrap_or_else(|_| "http://localhost:8080".into());
+    let token = env::var("RQST_GITLAB_TOKEN").context("Missing RQST_GITLAB_TOKEN")?;
+    let forge = Arc::new(GitlabForge::new(&url, &token)?);
+    let dir = tempfile::tempdir()?;
+    let quest = Quest::create(
+      forge,
+      dir.path().to_path_buf(),
+      CreateSource::Package(Box::new(test_package())),
+      CreateOptions::default(),
+      Box::new(NoopEmitter),
+    )
+    .await?;
+    let quest = Arc::new(quest);
+    let _remote = DeleteRemoteRepo(Arc::clone(&quest));
+    package_playthrough(&quest).await
+  }
 }
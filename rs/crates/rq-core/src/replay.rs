@@ -0,0 +1,434 @@
+//! Record and replay of [`GithubForge`]'s API requests, so changes to them are caught without
+//! credentials.
+//!
+//! The committed fixtures are recordings of [`MockGithub`], as their `source` says, not of
+//! github.com. They pin the shape of the requests [`GithubForge`] makes, such as a JSON body
+//! or a query string, but the responses are the mock's own, so replaying them says nothing
+//! about how Github answers. Fixtures recorded with `RQST_RECORD=github` would check that,
+//! but none are committed.
+//!
+//! A [`Cassette`] serves the fixture `fixtures/github/<name>.json` on a local port. Each request
+//! is matched against an unused recorded interaction with the same method, path and JSON body,
+//! so a change to the shape of a request fails the test that makes it.
+//!
+//! Setting `RQST_RECORD` re-records fixtures by proxying requests to a live server:
+//!
+//! - `RQST_RECORD=github` records against api.github.com with the token found by
+//!   [`get_github_token`]. The template is [`GITHUB_TEMPLATE_ORG`]`/`[`TEMPLATE_REPO`],
+//!   and the learner's copies of it are created and deleted under the token's account.
+//! - `RQST_RECORD=mock` records against a [`MockGithub`] seeded with [`test_config`].
+//!
+//! Recording pushes branches over SSH and reuses repo names between tests, so run it with
+//! `--test-threads=1`.
+
+use std::{collections::BTreeMap, env, fs, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+  body::Bytes,
+  extract::State,
+  http::{header, HeaderMap, Method, StatusCode, Uri},
+  response::{IntoResponse, Response},
+  Router,
+};
+use octocrab::Octocrab;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+  github::{get_github_token, GithubForge, GithubHost, GithubToken},
+  mock_github::{setup, test_config, MockGithub, TEMPLATE_REPO},
+};
+
+/// Owner of the template repo when recording against github.com.
+pub const GITHUB_TEMPLATE_ORG: &str = "cognitive-engineering-lab";
+
+const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/github");
+
+#[derive(Serialize, Deserialize, Default)]
+struct Fixture {
+  /// The `RQST_RECORD` target the fixture was recorded against, `github` or `mock`.
+  source: String,
+  /// Values that the test computed while recording, e.g. commit hashes from git.
+  vars: BTreeMap<String, String>,
+  interactions: Vec<Interaction>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Interaction {
+  method: String,
+  path: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  request: Option<Value>,
+  status: u16,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  response: Option<Value>,
+  /// The `Link` header for pagination, with URLs relative to the API base.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  link: Option<String>,
+}
+
+impl Interaction {
+  fn matches(&self, method: &Method, path: &str, request: &Option<Value>) -> bool {
+    self.method == method.as_str() && self.path == path && &self.request == request
+  }
+
+  fn describe(method: &Method, path: &str, request: &Option<Value>) -> String {
+    match request {
+      Some(body) => format!("{method} {path} {body}"),
+      None => format!("{method} {path}"),
+    }
+  }
+}
+
+enum Upstream {
+  Github,
+  Mock(MockGithub),
+}
+
+struct CassetteState {
+  /// Base URL that requests are proxied to, or `None` when replaying.
+  upstream_base: Option<String>,
+  client: reqwest::Client,
+  fixture: Mutex<Fixture>,
+  used: Mutex<Vec<bool>>,
+  unmatched: Mutex<Vec<String>>,
+}
+
+/// A local Github API that either replays a fixture or records one.
+///
+/// Replaying a mock fixture only checks the requests [`GithubForge`] makes, not Github's
+/// answers, see the [module docs](self).
+pub struct Cassette {
+  name: &'static str,
+  upstream: Option<Upstream>,
+  state: Arc<CassetteState>,
+  addr: SocketAddr,
+  server: JoinHandle<()>,
+}
+
+impl Cassette {
+  /// Starts serving the fixture `name`, or recording it if `RQST_RECORD` is set.
+  pub async fn load(name: &'static str) -> Result<Self> {
+    let upstream = match env::var("RQST_RECORD").as_deref() {
+      Err(_) => None,
+      Ok("github") => Some(Upstream::Github),
+      Ok("mock") => {
+        setup();
+        let mock = MockGithub::start().await?;
+        mock
+          .seed_template(&test_config(&mock.org, TEMPLATE_REPO))
+          .await?;
+        Some(Upstream::Mock(mock))
+      }
+      Ok(other) => bail!("Unknown RQST_RECORD target: {other}"),
+    };
+
+    let fixture = match &upstream {
+      Some(upstream) => Fixture {
+        source: match upstream {
+          Upstream::Github => "github",
+          Upstream::Mock(_) => "mock",
+        }
+        .into(),
+        ..Default::default()
+      },
+      None => {
+        let path = Self::path(name);
+        let contents = fs::read_to_string(&path).with_context(|| {
+          format!(
+            "Failed to read fixture {}, record it with RQST_RECORD",
+            path.display()
+          )
+        })?;
+        serde_json::from_str(&contents)
+          .with_context(|| format!("Invalid fixture: {}", path.display()))?
+      }
+    };
+
+    let upstream_base = upstream.as_ref().map(|upstream| match upstream {
+      Upstream::Github => GithubHost::default().api_base(),
+      Upstream::Mock(mock) => mock.host().api_base(),
+    });
+    let state = Arc::new(CassetteState {
+      upstream_base,
+      client: reqwest::Client::new(),
+      used: Mutex::new(vec![false; fixture.interactions.len()]),
+      fixture: Mutex::new(fixture),
+      unmatched: Mutex::new(Vec::new()),
+    });
+
+    let app = Router::new()
+      .fallback(handle)
+      .with_state(Arc::clone(&state));
+    let listener = TcpListener::bind("127.0.0.1:0")
+      .await
+      .context("Failed to bind replay server")?;
+    let addr = listener.local_addr()?;
+    let server = tokio::spawn(async move {
+      axum::serve(listener, app).await.unwrap();
+    });
+
+    Ok(Cassette {
+      name,
+      upstream,
+      state,
+      addr,
+      server,
+    })
+  }
+
+  fn path(name: &str) -> PathBuf {
+    PathBuf::from(FIXTURE_DIR).join(format!("{name}.json"))
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.upstream.is_some()
+  }
+
+  /// A [`GithubForge`] whose API requests go through the cassette.
+  pub fn forge(&self) -> Result<GithubForge> {
+    let (host, token) = match &self.upstream {
+      Some(Upstream::Github) => {
+        let host = GithubHost::default();
+        let token = match get_github_token(&host) {
+          GithubToken::Found(token) => token,
+          GithubToken::NotFound => bail!("Recording against Github requires a token"),
+          GithubToken::Error(err) => bail!("Failed to load Github token: {err}"),
+        };
+        (host, token)
+      }
+      Some(Upstream::Mock(mock)) => (mock.host(), "mock-token".into()),
+      None => (GithubHost::default(), "replay-token".into()),
+    };
+    let gh = Octocrab::builder()
+      .base_uri(format!("http://{}", self.addr))?
+      .personal_token(token)
+      .build()
+      .context("Failed to build replay Github connector")?;
+    Ok(GithubForge::from_octocrab(gh, host))
+  }
+
+  /// Returns `value()` when recording and stores it in the fixture, or the stored value when replaying.
+  /// This is for inputs to requests that come from outside the Github API, like commit hashes.
+  pub fn var(&self, key: &str, value: impl FnOnce() -> Result<String>) -> Result<String> {
+    if self.is_recording() {
+      let value = value()?;
+      let mut fixture = self.state.fixture.lock();
+      fixture.vars.insert(key.to_string(), value.clone());
+      Ok(value)
+    } else {
+      let fixture = self.state.fixture.lock();
+      let value = fixture.vars.get(key).with_context(|| {
+        format!(
+          "Fixture {} has no variable {key}, re-record it with RQST_RECORD",
+          self.name
+        )
+      })?;
+      Ok(value.clone())
+    }
+  }
+
+  /// The owner of the quest template [`TEMPLATE_REPO`].
+  pub fn template_owner(&self) -> Result<String> {
+    self.var("template_owner", || {
+      Ok(match &self.upstream {
+        Some(Upstream::Mock(mock)) => mock.org.clone(),
+        _ => GITHUB_TEMPLATE_ORG.to_string(),
+      })
+    })
+  }
+
+  /// Writes the fixture when recording. When replaying, fails if any request went unmatched
+  /// or any recorded interaction was never requested.
+  pub fn finish(self) -> Result<()> {
+    if self.is_recording() {
+      let path = Self::path(self.name);
+      fs::create_dir_all(FIXTURE_DIR)?;
+      let mut contents = serde_json::to_string_pretty(&*self.state.fixture.lock())?;
+      contents.push('\n');
+      fs::write(&path, contents)
+        .with_context(|| format!("Failed to write fixture: {}", path.display()))?;
+      return Ok(());
+    }
+
+    let unmatched = self.state.unmatched.lock();
+    if !unmatched.is_empty() {
+      bail!(
+        "Requests without a recorded response in fixture {}:\n{}",
+        self.name,
+        unmatched.join("\n")
+      );
+    }
+
+    let fixture = self.state.fixture.lock();
+    let used = self.state.used.lock();
+    let unused = fixture
+      .interactions
+      .iter()
+      .zip(used.iter())
+      .filter(|(_, used)| !**used)
+      .map(|(int, _)| format!("{} {}", int.method, int.path))
+      .collect::<Vec<_>>();
+    if !unused.is_empty() {
+      bail!(
+        "Recorded requests that were never made in fixture {}:\n{}",
+        self.name,
+        unused.join("\n")
+      );
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for Cassette {
+  fn drop(&mut self) {
+    self.server.abort();
+  }
+}
+
+fn parse_body(body: &[u8]) -> Option<Value> {
+  if body.is_empty() {
+    return None;
+  }
+  Some(
+    serde_json::from_slice(body)
+      .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned())),
+  )
+}
+
+fn json_response(status: u16, body: &Option<Value>) -> Response {
+  let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+  match body {
+    Some(body) => (
+      status,
+      [(header::CONTENT_TYPE, "application/json")],
+      body.to_string(),
+    )
+      .into_response(),
+    None => status.into_response(),
+  }
+}
+
+/// Responds with a recorded interaction, pointing its `Link` header at the cassette's `host`.
+fn interaction_response(interaction: &Interaction, host: &str) -> Response {
+  let mut response = json_response(interaction.status, &interaction.response);
+  if let Some(link) = &interaction.link {
+    let link = link.replace("</", &format!("<http://{host}/"));
+    response
+      .headers_mut()
+      .insert(header::LINK, link.parse().unwrap());
+  }
+  response
+}
+
+async fn handle(
+  State(state): State<Arc<CassetteState>>,
+  method: Method,
+  uri: Uri,
+  headers: HeaderMap,
+  body: Bytes,
+) -> Response {
+  let path = uri
+    .path_and_query()
+    .map_or_else(|| uri.path().to_string(), |path| path.to_string());
+  let host = headers
+    .get(header::HOST)
+    .and_then(|host| host.to_str().ok())
+    .unwrap_or_default()
+    .to_string();
+
+  match &state.upstream_base {
+    Some(base) => match record(&state, base, &host, method, path, headers, body).await {
+      Ok(response) => response,
+      Err(err) => json_response(502, &Some(json!({ "message": format!("{err:?}") }))),
+    },
+    None => replay(&state, &host, &method, &path, &parse_body(&body)),
+  }
+}
+
+async fn record(
+  state: &CassetteState,
+  base: &str,
+  host: &str,
+  method: Method,
+  path: String,
+  headers: HeaderMap,
+  body: Bytes,
+) -> Result<Response> {
+  let request = parse_body(&body);
+  let mut upstream_request = state
+    .client
+    .request(method.clone(), format!("{base}{path}"))
+    .body(body);
+  for (name, value) in &headers {
+    if ![
+      header::HOST,
+      header::CONTENT_LENGTH,
+      header::ACCEPT_ENCODING,
+    ]
+    .contains(name)
+    {
+      upstream_request = upstream_request.header(name, value);
+    }
+  }
+  let upstream_response = upstream_request
+    .send()
+    .await
+    .with_context(|| format!("Failed to forward {method} {path}"))?;
+  let status = upstream_response.status().as_u16();
+  let link = upstream_response
+    .headers()
+    .get(header::LINK)
+    .and_then(|link| link.to_str().ok())
+    .map(|link| link.replace(&format!("<{base}"), "<"));
+  let response = parse_body(&upstream_response.bytes().await?);
+
+  let interaction = Interaction {
+    method: method.to_string(),
+    path,
+    request,
+    status,
+    response,
+    link,
+  };
+  let response = interaction_response(&interaction, host);
+  state.fixture.lock().interactions.push(interaction);
+  Ok(response)
+}
+
+fn replay(
+  state: &CassetteState,
+  host: &str,
+  method: &Method,
+  path: &str,
+  request: &Option<Value>,
+) -> Response {
+  let fixture = state.fixture.lock();
+  let mut used = state.used.lock();
+  let found = fixture
+    .interactions
+    .iter()
+    .enumerate()
+    .find(|(i, int)| !used[*i] && int.matches(method, path, request));
+  match found {
+    Some((i, int)) => {
+      used[i] = true;
+      interaction_response(int, host)
+    }
+    None => {
+      let description = Interaction::describe(method, path, request);
+      state.unmatched.lock().push(description.clone());
+      json_response(
+        501,
+        &Some(json!({
+          "message": format!("No recorded response for {description}"),
+          "documentation_url": "https://docs.github.com/rest",
+        })),
+      )
+    }
+  }
+}
@@ -0,0 +1,165 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{header::HeaderMap, Client, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+const PAGE_SIZE: usize = 50;
+
+/// Minimal JSON client for forges that don't have a dedicated Rust SDK.
+pub(crate) struct RestClient {
+  http: Client,
+  base: String,
+  page_size_param: &'static str,
+}
+
+impl RestClient {
+  pub fn new(base: &str, auth_header: &'static str, auth_value: &str) -> Result<Self> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      auth_header,
+      auth_value.parse().context("Invalid access token")?,
+    );
+    let http = Client::builder()
+      .default_headers(headers)
+      .user_agent("repo-quest")
+      .build()
+      .context("Failed to build HTTP client")?;
+    Ok(RestClient {
+      http,
+      base: base.trim_end_matches('/').to_string(),
+      page_size_param: "limit",
+    })
+  }
+
+  /// Overrides the query parameter used by [`RestClient::get_all`] to set the page size.
+  pub fn with_page_size_param(mut self, param: &'static str) -> Self {
+    self.page_size_param = param;
+    self
+  }
+
+  /// Sends a request and returns the response body, or `None` if the resource was not found.
+  pub async fn call(
+    &self,
+    method: Method,
+    route: &str,
+    body: Option<&Value>,
+  ) -> Result<Option<String>> {
+    let url = format!("{}{route}", self.base);
+    tracing::debug!("{method} {url}");
+    let mut request = self.http.request(method.clone(), &url);
+    if let Some(body) = body {
+      request = request.json(body);
+    }
+    let response = request
+      .send()
+      .await
+      .with_context(|| format!("Failed to send request: {method} {url}"))?;
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    let text = response.text().await.context("Failed to read response")?;
+    if !status.is_success() {
+      bail!("{method} {url} failed with status {status}:\n{text}");
+    }
+    Ok(Some(text))
+  }
+
+  pub async fn json<T: DeserializeOwned>(
+    &self,
+    method: Method,
+    route: &str,
+    body: Option<&Value>,
+  ) -> Result<Option<T>> {
+    let Some(text) = self.call(method, route, body).await? else {
+      return Ok(None);
+    };
+    let value = serde_json::from_str(&text)
+      .with_context(|| format!("Failed to parse response from {route}:\n{text}"))?;
+    Ok(Some(value))
+  }
+
+  pub async fn get<T: DeserializeOwned>(&self, route: &str) -> Result<T> {
+    self
+      .json(Method::GET, route, None)
+      .await?
+      .with_context(|| format!("Not found: {route}"))
+  }
+
+  pub async fn send<T: DeserializeOwned>(
+    &self,
+    method: Method,
+    route: &str,
+    body: &Value,
+  ) -> Result<T> {
+    self
+      .json(method, route, Some(body))
+      .await?
+      .with_context(|| format!("Not found: {route}"))
+  }
+
+  /// Sends a request whose response body is irrelevant.
+  pub async fn send_unit(&self, method: Method, route: &str, body: Option<&Value>) -> Result<()> {
+    self
+      .call(method, route, body)
+      .await?
+      .with_context(|| format!("Not found: {route}"))?;
+    Ok(())
+  }
+
+  /// Fetches every page of a list endpoint, or `None` if it was not found.
+  ///
+  /// Forges may cap the page size below [`PAGE_SIZE`] (e.g. Gitea's `MAX_RESPONSE_ITEMS`),
+  /// so a short page doesn't mean it's the last one. Only an empty page does.
+  pub async fn get_all<T: DeserializeOwned>(&self, route: &str) -> Result<Option<Vec<T>>> {
+    let sep = if route.contains('?') { '&' } else { '?' };
+    let mut items = Vec::new();
+    for page in 1.. {
+      let paged = format!(
+        "{route}{sep}page={page}&{}={PAGE_SIZE}",
+        self.page_size_param
+      );
+      let Some(batch) = self.json::<Vec<T>>(Method::GET, &paged, None).await? else {
+        return Ok(None);
+      };
+      if batch.is_empty() {
+        break;
+      }
+      items.extend(batch);
+    }
+    Ok(Some(items))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use axum::{extract::Query, routing::get, Json, Router};
+  use std::collections::HashMap;
+  use tokio::net::TcpListener;
+
+  /// Pages are fetched until an empty one, even if the server returns fewer items than asked for.
+  #[tokio::test]
+  async fn get_all_capped_pages() -> Result<()> {
+    const TOTAL: usize = 45;
+    const CAP: usize = 20;
+    let app = Router::new().route(
+      "/items",
+      get(|Query(query): Query<HashMap<String, usize>>| async move {
+        let start = (query["page"] - 1) * CAP;
+        let end = (start + query["limit"].min(CAP)).min(TOTAL);
+        Json((start.min(end)..end).collect::<Vec<_>>())
+      }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let client = RestClient::new(&base, "Authorization", "token test")?;
+    let items = client.get_all::<usize>("/items").await?.unwrap();
+    assert_eq!(items, (0..TOTAL).collect::<Vec<_>>());
+
+    server.abort();
+    Ok(())
+  }
+}
@@ -0,0 +1,160 @@
+//! The forge that quests are played on, as chosen by the learner.
+//!
+//! Github keeps its own settings in [`GithubHost`], since its API and git hosts can differ.
+//! Other forges only need the base URL of their web interface and an access token.
+
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use crate::{
+  forge::Forge,
+  gitea::GiteaForge,
+  github::{self, GithubForge, GithubHost, GithubToken},
+  gitlab::GitlabForge,
+  local::LocalForge,
+};
+
+/// The kinds of forge that RepoQuest can play quests on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub enum ForgeKind {
+  #[default]
+  Github,
+  Gitea,
+  Gitlab,
+  /// The offline forge in `~/.rqst-forge`, which needs no account.
+  Local,
+}
+
+/// Which forge to use, and where to find it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct ForgeSettings {
+  pub kind: ForgeKind,
+
+  /// Base URL of the forge, e.g. `https://codeberg.org`. Unused for Github and the local forge.
+  pub url: Option<String>,
+}
+
+impl ForgeSettings {
+  pub fn new(kind: ForgeKind, url: Option<String>) -> Self {
+    ForgeSettings { kind, url }
+  }
+
+  fn config_path() -> Option<PathBuf> {
+    Some(home::home_dir()?.join(".rqst-forge.toml"))
+  }
+
+  /// Loads the settings from `RQST_FORGE` and `RQST_FORGE_URL` if set,
+  /// then from `~/.rqst-forge.toml`, and otherwise defaults to Github.
+  pub fn load() -> Result<Self> {
+    if let Ok(kind) = env::var("RQST_FORGE") {
+      let kind = match kind.to_lowercase().as_str() {
+        "github" => ForgeKind::Github,
+        "gitea" => ForgeKind::Gitea,
+        "gitlab" => ForgeKind::Gitlab,
+        "local" => ForgeKind::Local,
+        _ => bail!("Unknown forge in RQST_FORGE: {kind}"),
+      };
+      return Ok(ForgeSettings::new(kind, env::var("RQST_FORGE_URL").ok()));
+    }
+
+    match Self::config_path() {
+      Some(path) if path.exists() => {
+        let contents = fs::read_to_string(&path)
+          .with_context(|| format!("Failed to read forge settings from: {}", path.display()))?;
+        toml::from_str(&contents)
+          .with_context(|| format!("Failed to parse forge settings from: {}", path.display()))
+      }
+      _ => Ok(ForgeSettings::default()),
+    }
+  }
+
+  /// Saves the settings to `~/.rqst-forge.toml` so they are used by later sessions.
+  pub fn save(&self) -> Result<()> {
+    let path = Self::config_path().context("Could not find home directory")?;
+    let contents = toml::to_string(self)?;
+    fs::write(&path, contents)
+      .with_context(|| format!("Failed to write forge settings to: {}", path.display()))
+  }
+
+  /// The base URL of a forge other than Github, which must be set.
+  pub fn url(&self) -> Result<&str> {
+    match &self.url {
+      Some(url) => Ok(url),
+      None => bail!("No URL is set for the {:?} forge", self.kind),
+    }
+  }
+
+  /// Connects to the forge, looking up its access token with [`get_forge_token`]
+  /// (or [`github::get_github_token`] for Github). The local forge needs no token.
+  pub fn connect(&self, github_host: &GithubHost) -> Result<Arc<dyn Forge>> {
+    let forge: Arc<dyn Forge> = match self.kind {
+      ForgeKind::Github => {
+        match github::get_github_token(github_host) {
+          GithubToken::Found(token) => github::init_octocrab(&token, github_host)?,
+          other => bail!("Failed to get github token: {other:?}"),
+        }
+        Arc::new(GithubForge::new(github_host.clone()))
+      }
+      ForgeKind::Gitea | ForgeKind::Gitlab => {
+        let url = self.url()?;
+        let token = match get_forge_token(url) {
+          GithubToken::Found(token) => token,
+          other => bail!("Failed to get access token for {url}: {other:?}"),
+        };
+        connect_rest(self.kind, url, &token)?
+      }
+      ForgeKind::Local => Arc::new(LocalForge::open_default()?),
+    };
+    Ok(forge)
+  }
+}
+
+/// Connects to the Gitea or Gitlab instance at `url` with `token`.
+pub fn connect_rest(kind: ForgeKind, url: &str, token: &str) -> Result<Arc<dyn Forge>> {
+  Ok(match kind {
+    ForgeKind::Gitea => Arc::new(GiteaForge::new(url, token)?),
+    ForgeKind::Gitlab => Arc::new(GitlabForge::new(url, token)?),
+    ForgeKind::Github | ForgeKind::Local => {
+      bail!("The {kind:?} forge is not reached with an access token for a URL")
+    }
+  })
+}
+
+fn token_path(url: &str) -> Result<PathBuf> {
+  let url = Url::parse(url).with_context(|| format!("Invalid forge URL: {url}"))?;
+  let host = url
+    .host_str()
+    .with_context(|| format!("Forge URL has no host: {url}"))?;
+  let home = home::home_dir().context("Could not find home directory")?;
+  Ok(home.join(format!(".rqst-token-{host}")))
+}
+
+/// Finds the access token for the forge at `url`, from `RQST_FORGE_TOKEN` if set,
+/// and otherwise from `~/.rqst-token-<host>`.
+pub fn get_forge_token(url: &str) -> GithubToken {
+  if let Ok(token) = env::var("RQST_FORGE_TOKEN") {
+    return GithubToken::Found(token);
+  }
+  let path = match token_path(url) {
+    Ok(path) => path,
+    Err(e) => return GithubToken::Error(format!("{e:?}")),
+  };
+  if !path.exists() {
+    return GithubToken::NotFound;
+  }
+  match fs::read_to_string(&path) {
+    Ok(token) => GithubToken::Found(token.trim_end().to_string()),
+    Err(e) => GithubToken::Error(format!("{e:?}")),
+  }
+}
+
+/// Saves the access token for the forge at `url` to `~/.rqst-token-<host>`.
+pub fn save_forge_token(url: &str, token: &str) -> Result<()> {
+  let path = token_path(url)?;
+  fs::write(&path, token)
+    .with_context(|| format!("Failed to write access token to: {}", path.display()))
+}
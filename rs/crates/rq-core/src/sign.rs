@@ -0,0 +1,226 @@
+//! Ed25519 signatures over quest packages, so learners can tell who made a package and
+//! whether it was changed since.
+//!
+//! A signature covers a manifest with the SHA-256 hash of every top-level field of the
+//! package JSON except the signature itself. Packages are verified when loaded, before they
+//! are upgraded from older formats, so the hashes are of the JSON exactly as it was signed.
+//!
+//! A valid signature only says that whoever holds the key signed the package, since the
+//! signer's name and key come from the package itself. Learners pin the keys they trust by
+//! fingerprint, in `~/.rqst-trusted-keys` or `RQST_TRUSTED_KEYS`.
+
+use std::{collections::BTreeMap, env, fs, path::Path};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::{
+  digest::{digest, SHA256},
+  rand::SystemRandom,
+  signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use specta::Type;
+
+/// The field of the package JSON that holds its [`PackageSignature`].
+pub const SIGNATURE_FIELD: &str = "signature";
+
+/// Who signed a package.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct Signer {
+  pub name: String,
+  /// The base64 Ed25519 public key.
+  pub public_key: String,
+}
+
+impl Signer {
+  /// A short hex digest of the public key, for learners to compare with the one their
+  /// instructor published.
+  pub fn fingerprint(&self) -> String {
+    let hash = digest(&SHA256, self.public_key.as_bytes());
+    hex(&hash.as_ref()[..8])
+  }
+
+  /// Whether the learner pinned this signer's key, see [`trusted_fingerprints`].
+  pub fn is_trusted(&self) -> Result<bool> {
+    let fingerprint = self.fingerprint();
+    Ok(trusted_fingerprints()?.contains(&fingerprint))
+  }
+}
+
+/// The fingerprints of the keys the learner trusts, from `RQST_TRUSTED_KEYS` (separated by
+/// commas) and `~/.rqst-trusted-keys` (one per line, with `#` starting a comment).
+pub fn trusted_fingerprints() -> Result<Vec<String>> {
+  let mut fingerprints = match env::var("RQST_TRUSTED_KEYS") {
+    Ok(keys) => keys
+      .split(',')
+      .map(|key| key.trim().to_lowercase())
+      .collect(),
+    Err(_) => Vec::new(),
+  };
+  if let Some(path) = home::home_dir().map(|home| home.join(".rqst-trusted-keys")) {
+    if path.exists() {
+      let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read trusted keys from: {}", path.display()))?;
+      fingerprints.extend(contents.lines().map(|line| {
+        let key = line.split('#').next().unwrap_or_default();
+        key.trim().to_lowercase()
+      }));
+    }
+  }
+  fingerprints.retain(|key| !key.is_empty());
+  Ok(fingerprints)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageSignature {
+  pub signer: Signer,
+  /// The hex SHA-256 hash of each signed field.
+  pub manifest: BTreeMap<String, String>,
+  /// The base64 signature of the signer and manifest.
+  pub signature: String,
+}
+
+/// What to do when a signed package fails verification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verification {
+  /// Refuse to load the package.
+  #[default]
+  Strict,
+  /// Log a warning and load the package as if it were unsigned.
+  Warn,
+}
+
+impl Verification {
+  /// Reads the mode from `RQST_VERIFY_PACKAGES`, which can be `strict` or `warn`.
+  pub fn from_env() -> Result<Self> {
+    match env::var("RQST_VERIFY_PACKAGES").as_deref() {
+      Err(_) | Ok("strict") => Ok(Verification::Strict),
+      Ok("warn") => Ok(Verification::Warn),
+      Ok(other) => Err(anyhow!(
+        "Unknown RQST_VERIFY_PACKAGES mode: {other}, expected strict or warn"
+      )),
+    }
+  }
+}
+
+/// An Ed25519 key for signing packages, stored as JSON with the PKCS#8 key in base64.
+#[derive(Serialize, Deserialize)]
+pub struct SigningKey {
+  pub signer: String,
+  pkcs8: String,
+}
+
+impl SigningKey {
+  pub fn generate(signer: &str) -> Result<Self> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+      .map_err(|_| anyhow!("Failed to generate signing key"))?;
+    Ok(SigningKey {
+      signer: signer.to_string(),
+      pkcs8: BASE64.encode(pkcs8.as_ref()),
+    })
+  }
+
+  pub fn load(path: &Path) -> Result<Self> {
+    let contents = fs::read_to_string(path)
+      .with_context(|| format!("Failed to read signing key: {}", path.display()))?;
+    let key: SigningKey = serde_json::from_str(&contents)
+      .with_context(|| format!("Failed to parse signing key: {}", path.display()))?;
+    key.key_pair()?;
+    Ok(key)
+  }
+
+  /// Writes the key to `path`, readable only by the current user where supported.
+  pub fn save(&self, path: &Path) -> Result<()> {
+    ensure!(
+      !path.exists(),
+      "Refusing to overwrite existing file: {}",
+      path.display()
+    );
+    fs::write(path, serde_json::to_string_pretty(self)?)
+      .with_context(|| format!("Failed to write signing key: {}", path.display()))?;
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to set permissions of: {}", path.display()))?;
+    }
+    Ok(())
+  }
+
+  fn key_pair(&self) -> Result<Ed25519KeyPair> {
+    let pkcs8 = BASE64
+      .decode(&self.pkcs8)
+      .context("Invalid base64 in signing key")?;
+    Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| anyhow!("Invalid signing key: {e}"))
+  }
+
+  pub fn public(&self) -> Result<Signer> {
+    Ok(Signer {
+      name: self.signer.clone(),
+      public_key: BASE64.encode(self.key_pair()?.public_key()),
+    })
+  }
+
+  /// Signs the fields of `package`, a package serialized as a JSON object.
+  pub fn sign(&self, package: &Map<String, Value>) -> Result<PackageSignature> {
+    let signer = self.public()?;
+    let manifest = manifest(package)?;
+    let signature = self.key_pair()?.sign(&message(&signer, &manifest)?);
+    Ok(PackageSignature {
+      signer,
+      manifest,
+      signature: BASE64.encode(signature),
+    })
+  }
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn manifest(package: &Map<String, Value>) -> Result<BTreeMap<String, String>> {
+  package
+    .iter()
+    .filter(|(field, _)| *field != SIGNATURE_FIELD)
+    .map(|(field, value)| {
+      // Objects are sorted by key, so the same JSON always serializes to the same bytes.
+      let bytes = serde_json::to_vec(value)?;
+      Ok((field.clone(), hex(digest(&SHA256, &bytes).as_ref())))
+    })
+    .collect()
+}
+
+fn message(signer: &Signer, manifest: &BTreeMap<String, String>) -> Result<Vec<u8>> {
+  Ok(serde_json::to_vec(&(signer, manifest))?)
+}
+
+impl PackageSignature {
+  /// Checks that the signature is valid and that `package` has exactly the signed contents.
+  pub fn verify(&self, package: &Map<String, Value>) -> Result<()> {
+    let public_key = BASE64
+      .decode(&self.signer.public_key)
+      .context("Invalid base64 in signer's public key")?;
+    let signature = BASE64
+      .decode(&self.signature)
+      .context("Invalid base64 in package signature")?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+      .verify(&message(&self.signer, &self.manifest)?, &signature)
+      .map_err(|_| anyhow!("Package signature is invalid"))?;
+
+    let actual = manifest(package)?;
+    for (field, hash) in &self.manifest {
+      ensure!(
+        actual.get(field) == Some(hash),
+        "Package field `{field}` was changed after it was signed"
+      );
+    }
+    if let Some(field) = actual
+      .keys()
+      .find(|field| !self.manifest.contains_key(*field))
+    {
+      bail!("Package field `{field}` was added after it was signed");
+    }
+    Ok(())
+  }
+}
@@ -1,69 +1,138 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use async_trait::async_trait;
-use octocrab::models::issues::Issue;
-use std::path::Path;
+use parking_lot::Mutex;
+use std::{fs, io::Write, path::PathBuf, sync::Arc};
 
 use crate::{
-  git::{GitRepo, MergeType},
-  github::{find_issue, find_pr, FullPullRequest, GithubRepo, PullSelector},
-  package::QuestPackage,
-  quest::QuestConfig,
+  forge::{Forge, ForgeRepo, Label, PullSelector},
+  git::{GitRepo, MergeType, UPSTREAM},
+  package::{PackageIssue, PackagePr, QuestPackage, Solutions},
+  plan::PatchPlan,
+  quest::{QuestConfig, ReferenceSolution},
   stage::{Stage, StagePart},
 };
 
-pub struct InstanceOutputs {
-  pub origin: GithubRepo,
-  pub origin_git: GitRepo,
-  pub config: QuestConfig,
-}
-
 #[async_trait]
 pub trait QuestTemplate: Send + Sync + 'static {
-  async fn instantiate(&self, path: &Path) -> Result<InstanceOutputs>;
-  fn pull_request(&self, selector: &PullSelector) -> Result<FullPullRequest>;
-  fn issue(&self, label: &str) -> Result<Issue>;
+  /// The default name of the learner's repo.
+  fn repo_name(&self) -> &str;
+  /// Creates the learner's repo on the forge as `name`.
+  async fn create_origin(&self, name: &str) -> Result<Box<dyn ForgeRepo>>;
+  async fn copy_labels(&self, origin: &dyn ForgeRepo) -> Result<()>;
+  /// Sets up a fresh clone of the learner's repo, e.g. by adding the upstream remote.
+  fn init_clone(&self, origin_git: &GitRepo) -> Result<()>;
+  /// Sets up a clone of an existing repo, after checking that it was made from this quest.
+  fn adopt_clone(&self, origin_git: &GitRepo) -> Result<()>;
+  /// Loads the quest config once the clone is set up.
+  fn config(&self, origin_git: &GitRepo) -> Result<QuestConfig>;
+  fn pull_request(&self, selector: &PullSelector) -> Result<PackagePr>;
+  fn issue(&self, label: &str) -> Result<PackageIssue>;
   fn apply_patch(
     &self,
     repo: &GitRepo,
     base_branch: &str,
     target_branch: &str,
   ) -> Result<MergeType>;
-  fn reference_solution_pr_url(&self, stage: &Stage) -> Option<String>;
+  /// Like [`QuestTemplate::apply_patch`], but overwrites the learner's changes instead of merging.
+  fn reset_patch(
+    &self,
+    repo: &GitRepo,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<MergeType>;
+  /// Describes the changes [`QuestTemplate::apply_patch`] would apply.
+  fn plan_patch(&self, repo: &GitRepo, base_branch: &str, target_branch: &str)
+    -> Result<PatchPlan>;
+  /// Where the learner can see the reference solution of `stage`, if it has one they can see.
+  fn reference_solution(&self, stage: &Stage) -> Option<ReferenceSolution>;
   fn can_skip(&self) -> bool;
+  /// Whether the reference solutions need a password before they can be used.
+  fn solutions_locked(&self) -> bool;
+  /// Unlocks the reference solutions with `password`, and saves them in `origin_git` so they
+  /// stay unlocked.
+  fn unlock_solutions(&self, password: &str, origin_git: &GitRepo) -> Result<()>;
 }
 
-pub struct RepoTemplate(pub GithubRepo);
+/// Describes cherry-picking the upstream commits from `base_branch` to `target_branch`.
+fn cherry_pick_plan(repo: &GitRepo, base_branch: &str, target_branch: &str) -> Result<PatchPlan> {
+  let range = format!("{UPSTREAM}/{base_branch}..{UPSTREAM}/{target_branch}");
+  Ok(PatchPlan {
+    source: format!("cherry-pick {range}"),
+    diff: repo.diff(
+      &format!("{UPSTREAM}/{base_branch}"),
+      &format!("{UPSTREAM}/{target_branch}"),
+    )?,
+  })
+}
+
+pub struct RepoTemplate {
+  pub forge: Arc<dyn Forge>,
+  pub repo: Box<dyn ForgeRepo>,
+}
 
 #[async_trait]
 impl QuestTemplate for RepoTemplate {
-  async fn instantiate(&self, path: &Path) -> Result<InstanceOutputs> {
-    let origin = GithubRepo::instantiate_from_repo(&self.0)
+  fn repo_name(&self) -> &str {
+    self.repo.name()
+  }
+
+  async fn create_origin(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    self
+      .forge
+      .create_repo_from_template(&*self.repo, name)
       .await
-      .context("Failed to instantiate Github repo from template")?;
-    let origin_git = origin.clone(path).context("Failed to clone Github repo")?;
+      .context("Failed to instantiate repo from template")
+  }
+
+  async fn copy_labels(&self, origin: &dyn ForgeRepo) -> Result<()> {
+    let labels = self
+      .repo
+      .labels()
+      .await
+      .context("Failed to fetch labels from upstream repo")?;
+    origin
+      .create_labels(&labels)
+      .await
+      .context("Failed to transfer upstream labels to repo")
+  }
+
+  fn init_clone(&self, origin_git: &GitRepo) -> Result<()> {
     origin_git
-      .setup_upstream(&self.0)
-      .context("Failed to setup upstream")?;
-    let config = QuestConfig::load(&origin_git, Some("upstream"))
-      .context("Failed to load quest config from upstream")?;
-    Ok(InstanceOutputs {
-      origin,
-      origin_git,
-      config,
-    })
+      .setup_upstream(&*self.repo)
+      .context("Failed to setup upstream")
   }
 
-  fn pull_request(&self, selector: &PullSelector) -> Result<FullPullRequest> {
-    let pr = self.0.pr(selector).ok_or(anyhow!("Missing PR"))?;
-    Ok((*pr).clone())
+  fn adopt_clone(&self, origin_git: &GitRepo) -> Result<()> {
+    if !origin_git.has_upstream()? {
+      self.init_clone(origin_git)?;
+    }
+    // Repos made from a template start with a single commit of the template's main branch.
+    let root = origin_git.root_commit()?;
+    ensure!(
+      origin_git.same_tree(&root, &format!("{UPSTREAM}/main"))?,
+      "Repo was not made from the quest {}/{}",
+      self.repo.owner(),
+      self.repo.name()
+    );
+    Ok(())
   }
 
-  fn issue(&self, label: &str) -> Result<Issue> {
+  fn config(&self, origin_git: &GitRepo) -> Result<QuestConfig> {
+    QuestConfig::load(origin_git, Some(UPSTREAM))
+      .context("Failed to load quest config from upstream")
+  }
+
+  fn pull_request(&self, selector: &PullSelector) -> Result<PackagePr> {
+    let pr = self.repo.pr(selector).ok_or(anyhow!("Missing PR"))?;
+    Ok(PackagePr::from(&*pr))
+  }
+
+  fn issue(&self, label: &str) -> Result<PackageIssue> {
     let issue = self
-      .0
+      .repo
       .issue(label)
       .ok_or_else(|| anyhow!("Missing issue for label: {label}"))?;
-    Ok((*issue).clone())
+    Ok(PackageIssue::from(&*issue))
   }
 
   fn apply_patch(
@@ -75,50 +144,238 @@ impl QuestTemplate for RepoTemplate {
     repo.cherry_pick(base_branch, target_branch)
   }
 
-  fn reference_solution_pr_url(&self, stage: &Stage) -> Option<String> {
+  fn reset_patch(
+    &self,
+    repo: &GitRepo,
+    _base_branch: &str,
+    target_branch: &str,
+  ) -> Result<MergeType> {
+    repo.reset_solution(target_branch)
+  }
+
+  fn plan_patch(
+    &self,
+    repo: &GitRepo,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<PatchPlan> {
+    cherry_pick_plan(repo, base_branch, target_branch)
+  }
+
+  fn reference_solution(&self, stage: &Stage) -> Option<ReferenceSolution> {
     self
-      .0
+      .repo
       .pr(&PullSelector::Branch(
         stage.branch_name(StagePart::Solution),
       ))
-      .map(|pr| pr.data.html_url.as_ref().unwrap().to_string())
+      .map(|pr| ReferenceSolution::Pr(pr.data.html_url.clone()))
   }
 
   fn can_skip(&self) -> bool {
     true
   }
+
+  fn solutions_locked(&self) -> bool {
+    false
+  }
+
+  fn unlock_solutions(&self, _password: &str, _origin_git: &GitRepo) -> Result<()> {
+    bail!("This quest's reference solutions are not locked")
+  }
+}
+
+/// A quest from a [`QuestPackage`]. If the package has a git bundle, its branches are fetched
+/// into the learner's clone as `upstream/*` and used like a [`RepoTemplate`]'s. Otherwise the
+/// package's patches are applied.
+pub struct PackageTemplate {
+  pub forge: Arc<dyn Forge>,
+  pub package: QuestPackage,
+  /// The solutions, unless the package has none or they are still locked.
+  solutions: Mutex<Option<Solutions>>,
 }
 
-pub struct PackageTemplate(pub QuestPackage);
+/// Where a learner's clone keeps the reference solutions once they are unlocked. The password
+/// itself is never saved.
+fn unlocked_solutions_path(origin_git: &GitRepo) -> Result<PathBuf> {
+  Ok(origin_git.git_dir()?.join("rqst").join("solutions.json"))
+}
+
+/// The reference solutions unlocked in `origin_git`, if they have been.
+pub fn unlocked_solutions(origin_git: &GitRepo) -> Result<Option<Solutions>> {
+  let path = unlocked_solutions_path(origin_git)?;
+  if !path.exists() {
+    return Ok(None);
+  }
+  let contents = fs::read_to_string(&path)
+    .with_context(|| format!("Failed to read unlocked solutions: {}", path.display()))?;
+  let solutions = serde_json::from_str(&contents)
+    .with_context(|| format!("Failed to parse unlocked solutions: {}", path.display()))?;
+  Ok(Some(solutions))
+}
+
+/// Saves the unlocked `solutions` in `origin_git`, readable only by the current user where
+/// supported.
+fn save_unlocked_solutions(origin_git: &GitRepo, solutions: &Solutions) -> Result<()> {
+  let path = unlocked_solutions_path(origin_git)?;
+  let dir = path.parent().unwrap();
+  fs::create_dir_all(dir)
+    .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  options
+    .open(&path)
+    .and_then(|mut file| file.write_all(&serde_json::to_vec(solutions)?))
+    .with_context(|| format!("Failed to write unlocked solutions: {}", path.display()))
+}
+
+impl PackageTemplate {
+  /// Loads the package's solutions. Encrypted ones are only available as `unlocked`, from
+  /// [`unlocked_solutions`].
+  pub fn new(forge: Arc<dyn Forge>, package: QuestPackage, unlocked: Option<Solutions>) -> Self {
+    let solutions = match &package.solutions {
+      Some(solutions) if solutions.is_encrypted() => unlocked,
+      Some(solutions) => solutions.open(None).ok(),
+      None => None,
+    };
+    PackageTemplate {
+      forge,
+      package,
+      solutions: Mutex::new(solutions),
+    }
+  }
+
+  /// Remembers who signed the package, so [`Quest::load`] can tell if the copy on meta loses
+  /// its signature.
+  ///
+  /// [`Quest::load`]: crate::quest::Quest::load
+  fn record_signer(&self, origin_git: &GitRepo) -> Result<()> {
+    match self.package.signer() {
+      Some(signer) => origin_git.set_signer_key(&signer.public_key),
+      None => Ok(()),
+    }
+  }
+
+  fn has_bundle(&self) -> bool {
+    self.package.bundle.is_some()
+  }
+
+  fn has_solutions(&self) -> bool {
+    self.has_bundle() || self.solutions.lock().is_some()
+  }
+
+  /// Every patch in the package up to and including `base_branch..target_branch`, with the
+  /// starter and solution patches in stage order.
+  fn patches_until(&self, base_branch: &str, target_branch: &str) -> Result<Vec<String>> {
+    let solutions = self.solutions.lock();
+    let solutions = solutions
+      .as_ref()
+      .map(|solutions| &solutions.patches[..])
+      .unwrap_or_default();
+    let mut chain = Vec::new();
+    for stage in &self.package.config.stages {
+      for (patches, part) in [
+        (&self.package.patches[..], StagePart::Starter),
+        (solutions, StagePart::Solution),
+      ] {
+        let head = stage.branch_name(part);
+        if let Some(patch) = patches.iter().find(|patch| patch.head == head) {
+          chain.push(patch);
+        }
+      }
+    }
+
+    let patch_index = chain
+      .iter()
+      .position(|patch| patch.base == base_branch && patch.head == target_branch)
+      .ok_or_else(|| anyhow!("Missing patch in package: {base_branch}..{target_branch}"))?;
+    Ok(
+      chain[..=patch_index]
+        .iter()
+        .map(|patch| patch.patch.clone())
+        .collect(),
+    )
+  }
+}
 
 #[async_trait]
 impl QuestTemplate for PackageTemplate {
-  async fn instantiate(&self, path: &Path) -> Result<InstanceOutputs> {
-    let origin = GithubRepo::instantiate_from_package(&self.0)
+  fn repo_name(&self) -> &str {
+    &self.package.config.repo
+  }
+
+  async fn create_origin(&self, name: &str) -> Result<Box<dyn ForgeRepo>> {
+    self
+      .forge
+      .create_repo(name)
+      .await
+      .context("Failed to instantiate repo from package")
+  }
+
+  async fn copy_labels(&self, origin: &dyn ForgeRepo) -> Result<()> {
+    let labels = self
+      .package
+      .labels
+      .iter()
+      .map(Label::from)
+      .collect::<Vec<_>>();
+    origin
+      .create_labels(&labels)
       .await
-      .context("Failed to instantiate repo from package")?;
-    let origin_git = origin.clone(path).context("Failed to clone repo")?;
+      .context("Failed to transfer package labels to repo")
+  }
+
+  fn init_clone(&self, origin_git: &GitRepo) -> Result<()> {
     origin_git
-      .write_initial_files(&self.0)
+      .write_initial_files(&self.package)
       .context("Failed to write starter code to new repo")?;
-    let config = self.0.config.clone();
-    Ok(InstanceOutputs {
-      origin,
-      origin_git,
-      config,
-    })
+    self.record_signer(origin_git)
+  }
+
+  fn adopt_clone(&self, origin_git: &GitRepo) -> Result<()> {
+    origin_git
+      .track_branch("meta")
+      .context("Repo has no quest metadata")?;
+    if let Some(bundle) = self.package.bundle()? {
+      origin_git.fetch_bundle(&bundle)?;
+    }
+    let config = QuestConfig::load(origin_git, None)?;
+    ensure!(
+      config == self.package.config,
+      "Repo was made from a different quest: {}",
+      config.title
+    );
+    self.record_signer(origin_git)
   }
 
-  fn pull_request(&self, selector: &PullSelector) -> Result<FullPullRequest> {
-    let index = find_pr(selector, &self.0.prs)
+  fn config(&self, _origin_git: &GitRepo) -> Result<QuestConfig> {
+    Ok(self.package.config.clone())
+  }
+
+  fn pull_request(&self, selector: &PullSelector) -> Result<PackagePr> {
+    if let Some(pr) = self.package.pr(selector) {
+      return Ok(pr.clone());
+    }
+    // Solution PRs are kept with the solutions when those are encrypted.
+    let solutions = self.solutions.lock();
+    let pr = solutions
+      .iter()
+      .flat_map(|solutions| &solutions.prs)
+      .find(|pr| pr.matches(selector))
       .ok_or_else(|| anyhow!("Missing PR for selector: {selector:?}"))?;
-    Ok(self.0.prs[index].clone())
+    Ok(pr.clone())
   }
 
-  fn issue(&self, label: &str) -> Result<Issue> {
-    let index = find_issue(label, &self.0.issues)
+  fn issue(&self, label: &str) -> Result<PackageIssue> {
+    let issue = self
+      .package
+      .issue(label)
       .ok_or_else(|| anyhow!("Missing issue for label: {label}"))?;
-    Ok(self.0.issues[index].clone())
+    Ok(issue.clone())
   }
 
   fn apply_patch(
@@ -127,24 +384,79 @@ impl QuestTemplate for PackageTemplate {
     base_branch: &str,
     target_branch: &str,
   ) -> Result<MergeType> {
-    let patch_index = self
-      .0
-      .patch(&(base_branch.to_string(), target_branch.to_string()))
-      .ok_or_else(|| anyhow!("Missing patch in package: {base_branch}..{target_branch}"))?;
+    if self.has_bundle() {
+      return repo.cherry_pick(base_branch, target_branch);
+    }
+    let patches = self.patches_until(base_branch, target_branch)?;
+    repo.apply_patch(&patches.iter().map(String::as_str).collect::<Vec<_>>())
+  }
 
-    let patches = self.0.patches[..=patch_index]
-      .iter()
-      .map(|patch| patch.patch.as_str())
-      .collect::<Vec<_>>();
+  fn reset_patch(
+    &self,
+    repo: &GitRepo,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<MergeType> {
+    if self.has_bundle() {
+      return repo.reset_solution(target_branch);
+    }
+    let patches = self.patches_until(base_branch, target_branch)?;
+    repo.reset_patch(&patches.iter().map(String::as_str).collect::<Vec<_>>())
+  }
 
-    repo.apply_patch(&patches)
+  fn plan_patch(
+    &self,
+    repo: &GitRepo,
+    base_branch: &str,
+    target_branch: &str,
+  ) -> Result<PatchPlan> {
+    if self.has_bundle() {
+      return cherry_pick_plan(repo, base_branch, target_branch);
+    }
+    let patches = self.patches_until(base_branch, target_branch)?;
+    Ok(PatchPlan {
+      source: format!("package patch {base_branch}..{target_branch}"),
+      diff: patches.last().unwrap().clone(),
+    })
   }
 
-  fn reference_solution_pr_url(&self, _stage: &Stage) -> Option<String> {
-    None
+  fn reference_solution(&self, stage: &Stage) -> Option<ReferenceSolution> {
+    // The solution PR's URL is in the author's quest repo, which learners may not be able to
+    // see, so the solution is shown from the package instead.
+    if !self.has_solutions() {
+      return None;
+    }
+    let branch = stage.branch_name(StagePart::Solution);
+    let solutions = self.solutions.lock();
+    let has_patch = solutions
+      .as_ref()
+      .is_some_and(|solutions| solutions.patches.iter().any(|patch| patch.head == branch));
+    (self.has_bundle() || has_patch).then_some(ReferenceSolution::Package)
   }
 
   fn can_skip(&self) -> bool {
-    false
+    // Skipping resets to the previous stage's solution branch, which only bundles have.
+    self.has_bundle()
+  }
+
+  fn solutions_locked(&self) -> bool {
+    self
+      .package
+      .solutions
+      .as_ref()
+      .is_some_and(|solutions| solutions.is_encrypted())
+      && self.solutions.lock().is_none()
+  }
+
+  fn unlock_solutions(&self, password: &str, origin_git: &GitRepo) -> Result<()> {
+    let solutions = self
+      .package
+      .solutions
+      .as_ref()
+      .context("This quest package has no reference solutions")?
+      .open(Some(password))?;
+    save_unlocked_solutions(origin_git, &solutions)?;
+    *self.solutions.lock() = Some(solutions);
+    Ok(())
   }
 }
@@ -0,0 +1,264 @@
+//! Rebuilding a quest repo from a [`QuestPackage`], e.g. when the original was lost.
+//!
+//! [`unpack`] recreates main from the package's initial files, each stage branch by applying
+//! the package's patches in order, and meta with the quest config. If the package has a git
+//! bundle, its branches are used instead, so they keep their history. The issues and PRs are
+//! then either committed to meta as Markdown files by [`write_documents`], or filed on a forge
+//! along with the branches by [`publish`].
+
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::Serialize;
+
+use crate::{
+  forge::{Forge, ForgeRepo, GitProtocol, Label, PullSelector},
+  git::{git, git_output, GitRepo},
+  local::write_document,
+  package::{prev_solution, PackageComment, PackageIssue, PackageLabel, QuestPackage},
+  quest::QuestConfig,
+  stage::StagePart,
+};
+
+const CONFIG_FILE: &str = "rqst.toml";
+
+/// Rebuilds the quest repo of `package` in `dir`, which must be empty or not exist yet.
+/// Encrypted reference solutions are left out, unless unlocked first with
+/// [`QuestPackage::unlock_solutions`].
+pub fn unpack(package: &QuestPackage, dir: &Path) -> Result<GitRepo> {
+  if dir.exists() {
+    let mut entries =
+      fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    ensure!(
+      entries.next().is_none(),
+      "Directory is not empty: {}",
+      dir.display()
+    );
+  }
+  let repo = GitRepo::init(dir)?;
+
+  if let Some(bundle) = package.bundle()? {
+    repo.fetch_bundle_branches(&bundle)?;
+    return Ok(repo);
+  }
+
+  repo.commit_initial_files(&package.initial)?;
+
+  let solutions = match &package.solutions {
+    Some(solutions) if solutions.is_encrypted() => {
+      tracing::warn!("Reference solutions are locked, so solution branches are left out");
+      Vec::new()
+    }
+    Some(solutions) => solutions.open(None)?.patches,
+    None => Vec::new(),
+  };
+  let mut prev = "main".to_string();
+  for stage in &package.config.stages {
+    for part in [StagePart::Starter, StagePart::Solution] {
+      let branch = stage.branch_name(part);
+      let (patches, message) = match part {
+        StagePart::Starter => (&package.patches, "Starter code"),
+        StagePart::Solution => (&solutions, "Solution"),
+      };
+      let Some(patch) = patches.iter().find(|patch| patch.head == branch) else {
+        continue;
+      };
+      if repo.has_branch(&patch.base)? {
+        repo.commit_patch(&patch.base, &branch, &patch.patch, message)?;
+      } else {
+        // Without the reference solutions, the best guess is the previous stage's starter code.
+        tracing::warn!(
+          "Missing {}, building {branch} on {prev} instead",
+          patch.base
+        );
+        repo
+          .commit_patch(&prev, &branch, &patch.patch, message)
+          .with_context(|| format!("Failed to rebuild {branch} without {}", patch.base))?;
+      }
+      prev = branch;
+    }
+  }
+
+  git!(repo, "checkout -b meta main")?;
+  write_config(&repo, &package.config, "Add meta")?;
+  git!(repo, "checkout main")?;
+
+  Ok(repo)
+}
+
+fn write_config(repo: &GitRepo, config: &QuestConfig, message: &str) -> Result<()> {
+  let path = repo.path().join(CONFIG_FILE);
+  let contents = toml::to_string_pretty(config).context("Failed to serialize quest config")?;
+  fs::write(&path, contents).with_context(|| format!("Failed to write: {}", path.display()))?;
+  git!(repo, "add {CONFIG_FILE}")?;
+  git!(repo, "commit -m {}", shlex::try_quote(message)?)
+}
+
+#[derive(Serialize)]
+struct IssueHeader<'a> {
+  title: &'a str,
+  labels: &'a [String],
+}
+
+#[derive(Serialize)]
+struct PullHeader<'a> {
+  title: Option<&'a str>,
+  branch: &'a str,
+  labels: &'a [String],
+  comments: &'a [PackageComment],
+}
+
+#[derive(Serialize)]
+struct LabelsFile<'a> {
+  labels: &'a [PackageLabel],
+}
+
+/// The package's issues in stage order, each with a unique name for its file. Issues that
+/// share a stage are numbered after the first, e.g. `s1` and `s1-2`.
+fn stage_issues(package: &QuestPackage) -> Vec<(String, &PackageIssue)> {
+  let stages = &package.config.stages;
+  let mut issues = package
+    .issues
+    .iter()
+    .enumerate()
+    .map(|(i, issue)| {
+      let stage = issue
+        .labels
+        .iter()
+        .find_map(|label| stages.iter().position(|stage| &stage.label == label));
+      let name = match stage {
+        Some(stage) => stages[stage].label.clone(),
+        None => format!("issue-{}", i + 1),
+      };
+      (stage.unwrap_or(usize::MAX), name, issue)
+    })
+    .collect::<Vec<_>>();
+  issues.sort_by_key(|(stage, ..)| *stage);
+  let mut names = HashSet::new();
+  issues
+    .into_iter()
+    .map(|(_, name, issue)| {
+      let mut unique = name.clone();
+      for n in 2.. {
+        if names.insert(unique.clone()) {
+          break;
+        }
+        unique = format!("{name}-{n}");
+      }
+      (unique, issue)
+    })
+    .collect()
+}
+
+/// Commits the package's issues, PRs and labels to the meta branch of `repo`, as Markdown files
+/// under `issues/` and `pulls/` with a TOML header, and `labels.toml`.
+pub fn write_documents(package: &QuestPackage, repo: &GitRepo) -> Result<()> {
+  git!(repo, "checkout meta")?;
+
+  for dir in ["issues", "pulls"] {
+    let path = repo.path().join(dir);
+    fs::create_dir_all(&path)
+      .with_context(|| format!("Failed to create directory: {}", path.display()))?;
+  }
+  for (name, issue) in stage_issues(package) {
+    let header = IssueHeader {
+      title: &issue.title,
+      labels: &issue.labels,
+    };
+    let path = repo.path().join("issues").join(format!("{name}.md"));
+    write_document(&path, &header, issue.body.as_deref().unwrap_or_default())?;
+  }
+  for pr in &package.prs {
+    let header = PullHeader {
+      title: pr.title.as_deref(),
+      branch: &pr.branch,
+      labels: &pr.labels,
+      comments: &pr.comments,
+    };
+    let path = repo.path().join("pulls").join(format!("{}.md", pr.branch));
+    write_document(&path, &header, pr.body.as_deref().unwrap_or_default())?;
+  }
+  let labels = toml::to_string_pretty(&LabelsFile {
+    labels: &package.labels,
+  })?;
+  fs::write(repo.path().join("labels.toml"), labels).context("Failed to write labels.toml")?;
+
+  git!(repo, "add issues pulls labels.toml")?;
+  git!(repo, "commit -m 'Add issues and PRs'")?;
+  git!(repo, "checkout main")
+}
+
+/// Creates a repo named `name` on `forge` for the quest unpacked into `repo`, and pushes its
+/// branches, labels, PRs and issues. The quest config on meta is updated to point at it.
+pub async fn publish(
+  package: &QuestPackage,
+  repo: &GitRepo,
+  forge: &dyn Forge,
+  name: &str,
+) -> Result<Box<dyn ForgeRepo>> {
+  let owner = forge.current_user().await?;
+  let origin = forge
+    .create_repo(name)
+    .await
+    .with_context(|| format!("Failed to create repo: {owner}/{name}"))?;
+
+  let config = QuestConfig {
+    author: owner.clone(),
+    repo: name.to_string(),
+    ..package.config.clone()
+  };
+  git!(repo, "checkout meta")?;
+  write_config(repo, &config, &format!("Publish quest as {owner}/{name}"))?;
+  git!(repo, "checkout main")?;
+
+  let remote = shlex::try_quote(&origin.remote(GitProtocol::Https))?.into_owned();
+  git!(repo, "remote add origin {remote}")?;
+  git!(repo, "push --all origin")?;
+
+  let labels = package.labels.iter().map(Label::from).collect::<Vec<_>>();
+  origin
+    .create_labels(&labels)
+    .await
+    .context("Failed to create labels")?;
+
+  for (i, stage) in config.stages.iter().enumerate() {
+    for part in [StagePart::Starter, StagePart::Solution] {
+      let branch = stage.branch_name(part);
+      let Some(pr) = package.pr(&PullSelector::Branch(branch.clone())) else {
+        continue;
+      };
+      if !repo.has_branch(&branch)? {
+        tracing::warn!("Skipping the PR for {branch}, which was not rebuilt");
+        continue;
+      }
+      let base = match part {
+        StagePart::Solution if !stage.no_starter() => stage.branch_name(StagePart::Starter),
+        _ => prev_solution(&config, i),
+      };
+      let title = pr.title.as_deref().unwrap_or(&branch);
+      let body = pr.body.as_deref().unwrap_or_default();
+      let new_pr = origin
+        .create_pr(title, &branch, &base, body)
+        .await
+        .with_context(|| format!("Failed to create PR for {branch}"))?;
+      origin.add_pr_labels(new_pr.number, &pr.labels).await?;
+      let head = git_output!(repo, "rev-parse {branch}")?;
+      for comment in &pr.comments {
+        origin
+          .create_pr_comment(new_pr.number, &comment.into(), head.trim())
+          .await
+          .with_context(|| format!("Failed to add comment to PR for {branch}"))?;
+      }
+    }
+  }
+
+  for (_, issue) in stage_issues(package) {
+    let body = issue.body.as_deref().unwrap_or_default();
+    origin
+      .create_issue(&issue.title, body, &issue.labels)
+      .await
+      .with_context(|| format!("Failed to create issue: {}", issue.title))?;
+  }
+
+  Ok(origin)
+}
@@ -0,0 +1,331 @@
+//! Checks for authoring mistakes in a quest, which would otherwise only surface when a learner
+//! runs into them.
+//!
+//! [`validate_repo`] checks a quest repo with its issues and PRs on the forge, and
+//! [`validate_package`] checks a quest package. Both report every [`Problem`] they find rather
+//! than stopping at the first one.
+
+use std::{collections::HashSet, fmt, path::Path};
+
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+  forge::{Forge, PullSelector},
+  git::GitRepo,
+  package::{prev_solution, PackageIssue, PackagePr, QuestPackage, Solutions},
+  quest::QuestConfig,
+  stage::{Stage, StagePart},
+};
+
+/// A mistake in a quest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Problem {
+  /// The label of the stage with the problem, if it belongs to one.
+  pub stage: Option<String>,
+  pub message: String,
+}
+
+impl Problem {
+  fn stage(stage: &Stage, message: impl Into<String>) -> Self {
+    Problem {
+      stage: Some(stage.label.clone()),
+      message: message.into(),
+    }
+  }
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.stage {
+      Some(stage) => write!(f, "{stage}: {}", self.message),
+      None => write!(f, "{}", self.message),
+    }
+  }
+}
+
+/// The parts of `stage` that learners get a PR for.
+fn stage_parts(stage: &Stage) -> &'static [StagePart] {
+  if stage.no_starter() {
+    &[StagePart::Solution]
+  } else {
+    &[StagePart::Starter, StagePart::Solution]
+  }
+}
+
+/// The branch that the PR for `part` of stage `index` builds on.
+fn base_branch(config: &QuestConfig, index: usize, part: StagePart) -> String {
+  let stage = &config.stages[index];
+  if part == StagePart::Solution && !stage.no_starter() {
+    stage.branch_name(StagePart::Starter)
+  } else {
+    prev_solution(config, index)
+  }
+}
+
+/// Orders `problems` by stage, keeping the order they were found in within each stage.
+fn sort_by_stage(config: &QuestConfig, problems: &mut [Problem]) {
+  problems.sort_by_key(|problem| {
+    problem
+      .stage
+      .as_ref()
+      .and_then(|label| config.stages.iter().position(|stage| &stage.label == label))
+  });
+}
+
+/// Checks that every stage has an issue and PRs that can be copied to the learner's repo.
+/// Without `solution_prs`, the solution PRs are unknown, so they aren't checked.
+fn check_documents(
+  config: &QuestConfig,
+  issues: &[PackageIssue],
+  prs: &[PackagePr],
+  solution_prs: bool,
+  problems: &mut Vec<Problem>,
+) {
+  let mut labels = HashSet::new();
+  for stage in &config.stages {
+    if !labels.insert(&stage.label) {
+      problems.push(Problem::stage(stage, "Another stage has the same label"));
+    }
+  }
+
+  let re = Regex::new(r"\{\{ (\S+) (\S+) \}\}").unwrap();
+  for stage in &config.stages {
+    match issues
+      .iter()
+      .find(|issue| issue.labels.contains(&stage.label))
+    {
+      None => problems.push(Problem::stage(stage, "No issue has the stage's label")),
+      Some(issue) => match &issue.body {
+        None => problems.push(Problem::stage(stage, "The issue has no body")),
+        Some(body) => {
+          for cap in re.captures_iter(body) {
+            let (reference, label, kind) = (&cap[0], &cap[1], &cap[2]);
+            let found = match kind {
+              "pr" => prs
+                .iter()
+                .any(|pr| pr.matches(&PullSelector::Label(label.to_string()))),
+              "issue" => issues
+                .iter()
+                .any(|issue| issue.labels.iter().any(|l| l == label)),
+              _ => {
+                problems.push(Problem::stage(
+                  stage,
+                  format!("The issue has `{reference}`, but only pr and issue can be referenced"),
+                ));
+                continue;
+              }
+            };
+            // The reference could be to a solution PR we can't see.
+            if !found && (solution_prs || kind != "pr") {
+              problems.push(Problem::stage(
+                stage,
+                format!("The issue has `{reference}`, but no {kind} has the label {label}"),
+              ));
+            }
+          }
+        }
+      },
+    }
+
+    for part in stage_parts(stage) {
+      if *part == StagePart::Solution && !solution_prs {
+        continue;
+      }
+      let branch = stage.branch_name(*part);
+      let Some(pr) = prs.iter().find(|pr| pr.branch == branch) else {
+        problems.push(Problem::stage(stage, format!("No PR for {branch}")));
+        continue;
+      };
+      if pr.title.is_none() {
+        problems.push(Problem::stage(
+          stage,
+          format!("The PR for {branch} has no title"),
+        ));
+      }
+      if pr.body.is_none() {
+        problems.push(Problem::stage(
+          stage,
+          format!("The PR for {branch} has no body"),
+        ));
+      }
+    }
+
+    if stage.no_starter() {
+      let branch = stage.branch_name(StagePart::Starter);
+      if prs.iter().any(|pr| pr.branch == branch) {
+        problems.push(Problem::stage(
+          stage,
+          format!("The stage has no starter code, but there is a PR for {branch}"),
+        ));
+      }
+    }
+  }
+}
+
+/// The rev of `branch` in `repo`: the local branch if there is one, or else `origin`'s, since a
+/// fresh clone of a quest only has `main` locally.
+fn branch_rev(repo: &GitRepo, branch: &str) -> Result<Option<String>> {
+  Ok(if repo.has_branch(branch)? {
+    Some(branch.to_string())
+  } else if repo.has_remote_branch("origin", branch)? {
+    Some(format!("origin/{branch}"))
+  } else {
+    None
+  })
+}
+
+/// Checks that each stage branch of `repo` exists if it should, and builds on the branch before.
+fn check_branches(repo: &GitRepo, config: &QuestConfig, problems: &mut Vec<Problem>) -> Result<()> {
+  for (i, stage) in config.stages.iter().enumerate() {
+    if stage.no_starter() {
+      let branch = stage.branch_name(StagePart::Starter);
+      if branch_rev(repo, &branch)?.is_some() {
+        problems.push(Problem::stage(
+          stage,
+          format!("The stage has no starter code, but there is a branch {branch}"),
+        ));
+      }
+    }
+
+    for part in stage_parts(stage) {
+      let branch = stage.branch_name(*part);
+      let Some(rev) = branch_rev(repo, &branch)? else {
+        problems.push(Problem::stage(stage, format!("No branch {branch}")));
+        continue;
+      };
+      // Learners get the commits between the base and the branch, so the branch must
+      // contain all of its base.
+      let base = base_branch(config, i, *part);
+      if let Some(base_rev) = branch_rev(repo, &base)? {
+        if !repo.is_ancestor(&base_rev, &rev)? {
+          problems.push(Problem::stage(
+            stage,
+            format!("{branch} does not build on {base}"),
+          ));
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Checks that the patches of `package` apply in sequence, by applying them to a scratch repo.
+fn check_patches(
+  package: &QuestPackage,
+  solutions: Option<&Solutions>,
+  problems: &mut Vec<Problem>,
+) -> Result<()> {
+  let config = &package.config;
+  for stage in &config.stages {
+    let branch = stage.branch_name(StagePart::Starter);
+    if stage.no_starter() {
+      if package.patches.iter().any(|patch| patch.head == branch) {
+        problems.push(Problem::stage(
+          stage,
+          format!("The stage has no starter code, but there is a patch for {branch}"),
+        ));
+      }
+    } else if !package.patches.iter().any(|patch| patch.head == branch) {
+      problems.push(Problem::stage(stage, format!("No patch for {branch}")));
+    }
+  }
+
+  let solutions = solutions
+    .map(|solutions| &solutions.patches[..])
+    .unwrap_or_default();
+  let dir = tempfile::tempdir()?;
+  let repo = GitRepo::init(dir.path())?;
+  repo.use_scratch_identity()?;
+  repo.commit_initial_files(&package.initial)?;
+  // Branches that were built, so patches on a branch that failed aren't reported again.
+  let mut built = HashSet::from(["main".to_string()]);
+  for (i, stage) in config.stages.iter().enumerate() {
+    for part in [StagePart::Starter, StagePart::Solution] {
+      let branch = stage.branch_name(part);
+      let patches = match part {
+        StagePart::Starter => &package.patches[..],
+        StagePart::Solution => solutions,
+      };
+      let Some(patch) = patches.iter().find(|patch| patch.head == branch) else {
+        continue;
+      };
+      let base = base_branch(config, i, part);
+      if patch.base != base {
+        problems.push(Problem::stage(
+          stage,
+          format!(
+            "The patch for {branch} is based on {} instead of {base}",
+            patch.base
+          ),
+        ));
+      }
+      if !built.contains(&patch.base) {
+        continue;
+      }
+      match repo.commit_patch(&patch.base, &branch, &patch.patch, "Validate") {
+        Ok(()) => {
+          built.insert(branch);
+        }
+        Err(e) => problems.push(Problem::stage(stage, format!("{e:#}"))),
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Finds the problems in the quest repo at `path`, with its issues and PRs on `forge`.
+/// Branches that are only on `origin`, as in a fresh clone, are checked there.
+pub async fn validate_repo(path: &Path, forge: &dyn Forge) -> Result<Vec<Problem>> {
+  let git_repo = GitRepo::new(path);
+  let remote = (!git_repo.has_branch("meta")? && git_repo.has_remote_branch("origin", "meta")?)
+    .then_some("origin");
+  let config = QuestConfig::load(&git_repo, remote)?;
+  let repo = forge.load_repo(&config.author, &config.repo).await?;
+  let issues = repo
+    .issues()
+    .iter()
+    .map(PackageIssue::from)
+    .collect::<Vec<_>>();
+  let prs = repo.prs().iter().map(PackagePr::from).collect::<Vec<_>>();
+
+  let mut problems = Vec::new();
+  check_branches(&git_repo, &config, &mut problems)?;
+  check_documents(&config, &issues, &prs, true, &mut problems);
+  sort_by_stage(&config, &mut problems);
+  Ok(problems)
+}
+
+/// Finds the problems in `package`. Encrypted reference solutions are only checked if
+/// `password` is given, and omitted ones not at all.
+pub fn validate_package(package: &QuestPackage, password: Option<&str>) -> Result<Vec<Problem>> {
+  let solutions = match &package.solutions {
+    Some(solutions) if solutions.is_encrypted() && password.is_none() => {
+      tracing::warn!("Reference solutions are locked, so they and their PRs are not checked");
+      None
+    }
+    Some(solutions) => Some(solutions.open(password)?),
+    None => None,
+  };
+  let mut prs = package.prs.clone();
+  prs.extend(solutions.iter().flat_map(|solutions| solutions.prs.clone()));
+  // Packages from before reference solutions were packed have none, but keep the solution PRs.
+  let solution_prs = solutions.is_some()
+    || package.config.stages.iter().any(|stage| {
+      let branch = stage.branch_name(StagePart::Solution);
+      prs.iter().any(|pr| pr.branch == branch)
+    });
+
+  let mut problems = Vec::new();
+  check_patches(package, solutions.as_ref(), &mut problems)?;
+  check_documents(
+    &package.config,
+    &package.issues,
+    &prs,
+    solution_prs,
+    &mut problems,
+  );
+  sort_by_stage(&package.config, &mut problems);
+  Ok(problems)
+}